@@ -2,28 +2,72 @@ use glium::glutin::{EventsLoop};
 
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::path::PathBuf;
-use std::time::{SystemTime};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Instant};
 
 use ::gfx::rendering::Renderer;
 use ::gfx::scene::Scene as GraphicsScene;
-use ::gfx::resources::{Material};
-use ::gfx::scene::MeshInstance;
+use ::gfx::scene::{CameraRenderParams, MeshInstance, MeshInstanceHandle};
+use ::gfx::resources::{Material, Mesh};
 use ::math::*;
 use ::assets::Asset;
 use ::assets::util::*;
-use super::Input;
-use super::input;
-use ::terrain::Terrain;
+use super::{Input, Key, KeyBindings, FixedTimestep, TICK_RATE, duration_to_secs};
+use ::terrain::{Terrain, Heightfield};
+use ::navigation::{Navigation, NavGrid};
+use ::game::*;
+
+const KEY_BINDINGS_PATH: &str = "config/bindings.toml";
+const NAVGRID_CELL_SIZE: Real = 1.0;
+const COMBAT_DATA_PATH: &str = "data/combat.toml";
+const ECONOMY_DATA_PATH: &str = "data/economy.toml";
+const FOG_CELL_SIZE: Real = 1.0;
+const BUILDINGS_DATA_PATH: &str = "data/buildings.toml";
+const PRODUCTION_DATA_PATH: &str = "data/production.toml";
+/// Resources every player starts with
+const STARTING_GOLD: u32 = 200;
+/// Where the local player's depot stands at the start
+const STARTING_DEPOT_POSITION: Vector3 = Vector3 { x: 4.0, y: 0.0, z: 6.0 };
+/// Ghost tint where the building fits and where it doesn't
+const GHOST_VALID_EMISSION: Vector3 = Vector3 { x: 0.0, y: 0.6, z: 0.0 };
+const GHOST_INVALID_EMISSION: Vector3 = Vector3 { x: 0.6, y: 0.0, z: 0.0 };
+
+/// Building the local player is about to place, shown at the cursor
+struct BuildingGhost {
+	def: Rc<BuildingDef>,
+	instance: MeshInstanceHandle,
+}
 
 pub struct App {
 	events_loop: Rc<RefCell<EventsLoop>>,
 	input: Input,
-	last_frame_time: SystemTime,
+	last_frame_time: Instant,
 	delta_time: f32,
+	timestep: FixedTimestep,
 
 	renderer: Rc<Renderer>,
 
+	camera_controller: RTSCameraController,
+
+	world: World,
+	navigation: Option<Navigation>,
+	fog_of_war: Option<FogOfWar>,
+	local_player: PlayerId,
+	selection: Selection,
+	/// Formation the selected units take when ordered to move
+	formation: Formation,
+	/// Kind of order forced on the next left click
+	command_override: Option<CommandOverride>,
+	combat_data: CombatData,
+	economy_data: EconomyData,
+	building_data: BuildingData,
+	building_mesh: Option<Asset<Mesh>>,
+	production_data: ProductionData,
+	/// Mesh of units trained in buildings
+	unit_mesh: Option<Asset<Mesh>>,
+	ghost: Option<BuildingGhost>,
+
 	graphics_scene: Option<Rc<RefCell<GraphicsScene>>>,
 }
 
@@ -35,20 +79,53 @@ impl App {
 
 		let renderer = Rc::new(Renderer::new(&events_loop.borrow_mut()));
 
+		let mut input = Input::new(KeyBindings::load_or_default(Path::new(KEY_BINDINGS_PATH)));
+		if let Some((width, height)) = renderer.get_display().gl_window().get_inner_size() {
+			input.set_window_size(width, height);
+		}
+
+		// production data names weapons and buildings, so it's loaded last
+		let combat_data = require_data("combat data", COMBAT_DATA_PATH, |path| CombatData::load(path));
+		let building_data = require_data("building data", BUILDINGS_DATA_PATH, |path| BuildingData::load(path));
+		let production_data = require_data("production data", PRODUCTION_DATA_PATH, |path| ProductionData::load(path, &combat_data, &building_data));
+		let economy_data = require_data("economy data", ECONOMY_DATA_PATH, |path| EconomyData::load(path));
+
 		App {
 			events_loop: events_loop,
-			input: Input::new(),
-			last_frame_time: SystemTime::now(),
+			input: input,
+			last_frame_time: Instant::now(),
 			delta_time: 0.0,
+			timestep: FixedTimestep::new(TICK_RATE),
 
 			renderer: renderer,
 
+			camera_controller: RTSCameraController::new(vec3(50.0, 0.0, 50.0)),
+
+			world: World::new(),
+			navigation: None,
+			fog_of_war: None,
+			local_player: PlayerId(0),
+			selection: Selection::new(),
+			formation: Formation::Box,
+			command_override: None,
+			combat_data: combat_data,
+			economy_data: economy_data,
+			building_data: building_data,
+			building_mesh: None,
+			production_data: production_data,
+			unit_mesh: None,
+			ghost: None,
+
 			graphics_scene: Some(Rc::new(RefCell::new(GraphicsScene::new()))),
 		}	
 	}
 
 	pub fn run(&mut self) {
 
+		// the local player against one enemy
+		self.world.players.add(Player::new(PlayerId(0), TeamId(0)));
+		self.world.players.add(Player::new(PlayerId(1), TeamId(1)));
+
 		if let Some(ref scene) = self.graphics_scene {
 			let mut scene = scene.borrow_mut();
 
@@ -66,13 +143,17 @@ impl App {
 
 				let mesh = load_mesh(self.renderer.get_display(), PathBuf::from("data/monkey.dae").as_path(), material.clone());
 
-				let instance = MeshInstance {
-					spatial: Default::default(),
-					is_static: false,
-					mesh: mesh,
-				};
+				self.building_mesh = Some(mesh.clone());
+				self.unit_mesh = Some(mesh.clone());
+				self.world.stockpile_mut(PlayerId(0)).unwrap().add("gold", STARTING_GOLD);
+
+				spawn_unit(&mut self.world, mesh.clone(), PlayerId(0), vec3(0.0, 0.0, 0.0), self.combat_data.weapon("rifle"));
 
-				scene.add_mesh_instance(instance);
+				let worker = spawn_unit(&mut self.world, mesh.clone(), PlayerId(0), vec3(3.0, 0.0, 0.0), None);
+				self.world.workers.insert(worker, Worker::new());
+				if let Some(gold) = self.economy_data.resource("gold") {
+					spawn_resource_node(&mut self.world, mesh, gold, 500, vec3(8.0, 0.0, 2.0));
+				}
 			}
 
 			{
@@ -84,16 +165,7 @@ impl App {
 
 				let mesh = load_mesh(self.renderer.get_display(), PathBuf::from("data/monkey.dae").as_path(), material.clone());
 
-				let mut instance = MeshInstance {
-					spatial: Default::default(),
-					is_static: false,
-					mesh: mesh,
-				};
-
-				instance.spatial.position = vec3(10.0, 10.0, 10.0);
-
-
-				scene.add_mesh_instance(instance);
+				spawn_unit(&mut self.world, mesh, PlayerId(1), vec3(10.0, 0.0, 10.0), self.combat_data.weapon("rifle"));
 			}
 
 			{
@@ -104,12 +176,31 @@ impl App {
 					metallic_map: black.clone(),
 				});
 
-				let map = load_texture(self.renderer.get_display(), PathBuf::from("data/terrain.png").as_path());
+				let map_path = PathBuf::from("data/terrain.png");
+				let map = load_texture(self.renderer.get_display(), map_path.as_path());
+				let heightfield = load_heightfield(map_path.as_path(), vec3(100.0, 20.0, 100.0));
 
-				let terrain = Asset::asset(Terrain::new(map));
+				let terrain = Asset::asset(Terrain::new(map, heightfield));
 
 				terrain.asset.borrow_mut().materials.push(material.clone());
 
+				self.navigation = Some(Navigation::new(NavGrid::from_heightfield(&terrain.asset.borrow().heightfield, NAVGRID_CELL_SIZE)));
+				self.fog_of_war = Some(FogOfWar::new(&terrain.asset.borrow().heightfield, FOG_CELL_SIZE));
+
+				// the starting depot goes down like a placed one, so it blocks its cells
+				match self.building_data.building("depot") {
+					Some(def) => {
+						let mut terrain = terrain.asset.borrow_mut();
+						let navigation = self.navigation.as_mut().unwrap();
+						match spawn_building(&mut self.world, navigation, &mut terrain.heightfield, &def, self.building_mesh.clone(), PlayerId(0), STARTING_DEPOT_POSITION) {
+							Ok(_) if def.flatten => *terrain.map.asset.borrow_mut() = heightfield_texture(self.renderer.get_display(), &terrain.heightfield),
+							Ok(_) => (),
+							Err(error) => eprintln!("Failed to place the starting depot: {:?}", error),
+						}
+					},
+					None => eprintln!("No depot in the building data, starting without one"),
+				}
+
 				scene.terrain = Some(terrain);
 			}
 
@@ -125,41 +216,83 @@ impl App {
 
 			self.process_events();
 
-			let delta_duration = self.last_frame_time.elapsed().unwrap();
-			self.delta_time = (delta_duration.as_secs() as f32) + (delta_duration.subsec_nanos() as f32) * 0.000000001;
-			self.last_frame_time = SystemTime::now();
+			let now = Instant::now();
+			self.delta_time = duration_to_secs(now.duration_since(self.last_frame_time));
+			self.last_frame_time = now;
+
+			let ticks = self.timestep.advance(now);
 
 			{
-				if self.input.is_key_down(input::Key::LookAround) {
-					let scene_ref = self.graphics_scene.clone().unwrap();
-					let mut scene = scene_ref.borrow_mut();
-					let camera = scene.camera_mut();
-					let delta_mouse = self.input.delta_mouse();
+				let scene_ref = self.graphics_scene.clone().unwrap();
+				let mut scene = scene_ref.borrow_mut();
 
-					camera.spatial.rotation = Quaternion::from_angle_y(Rad(delta_mouse.x * 0.01)) * camera.spatial.rotation * Quaternion::from_angle_x(Rad(delta_mouse.y * 0.01));
-				}
+				if let Some(terrain) = scene.terrain.clone() {
+					let terrain = terrain.asset.borrow();
 
-				let mut tr = vec3(0.0, 0.0, 0.0);
-				
-				if self.input.is_key_down(input::Key::Forward) {
-					tr += vec3(0.0, 0.0, 1.0);
+					let spatial = self.camera_controller.update(&self.input, &terrain, self.delta_time);
+					scene.camera_mut().spatial = spatial;
 				}
-				if self.input.is_key_down(input::Key::Backward) {
-					tr += vec3(0.0, 0.0, -1.0);
-				}
-				if self.input.is_key_down(input::Key::Left) {
-					tr += vec3(-1.0, 0.0, 0.0);
+
+				let window_size = self.input.window_size();
+				let camera = CameraRenderParams::new(scene.camera(), ((window_size.x as u32).max(1), (window_size.y as u32).max(1)));
+				if self.input.is_key_pressed(Key::Build) {
+					self.start_placement(&mut scene);
 				}
-				if self.input.is_key_down(input::Key::Right) {
-					tr += vec3(1.0, 0.0, 0.0);
+
+				// clicks place the building while there is one at the cursor
+				if self.ghost.is_some() {
+					self.update_placement(&mut scene, &camera);
+				} else {
+					for &(key, override_order) in [(Key::AttackMove, CommandOverride::Attack), (Key::Patrol, CommandOverride::Patrol), (Key::ForceMove, CommandOverride::Move), (Key::Unload, CommandOverride::Unload)].iter() {
+						if self.input.is_key_pressed(key) {
+							self.command_override = Some(override_order);
+						}
+					}
+
+					// after an override hotkey the left click gives the order instead of selecting,
+					// a right click or cancel drops the override
+					if let Some(override_order) = self.command_override {
+						if self.input.is_key_pressed(Key::Select) {
+							self.command_override = None;
+							self.command_selected_units(&scene, &camera, Some(override_order));
+						} else if self.input.is_key_pressed(Key::Command) || self.input.is_key_pressed(Key::Cancel) {
+							self.command_override = None;
+						}
+					} else {
+						self.selection.update(&self.input, &camera, &self.world, self.fog_of_war.as_ref(), self.local_player);
+
+						if self.input.is_key_pressed(Key::Command) {
+							self.command_selected_units(&scene, &camera, None);
+						}
+					}
+					if self.input.is_key_pressed(Key::Stop) {
+						self.order_selected_units(&scene, Order::Stop);
+					}
+					if self.input.is_key_pressed(Key::Hold) {
+						self.order_selected_units(&scene, Order::Hold);
+					}
+					if self.input.is_key_pressed(Key::CycleFormation) {
+						let formations = Formation::all();
+						let index = formations.iter().position(|&formation| formation == self.formation).unwrap_or(0);
+						self.formation = formations[(index + 1) % formations.len()];
+					}
+
+					if self.input.is_key_pressed(Key::Train) {
+						self.produce_at_selected_buildings(false);
+					}
+					if self.input.is_key_pressed(Key::Research) {
+						self.produce_at_selected_buildings(true);
+					}
+					if self.input.is_key_pressed(Key::CancelProduction) {
+						self.cancel_at_selected_buildings();
+					}
 				}
 
-				{
-					let scene_ref = self.graphics_scene.clone().unwrap();
-					let mut scene = scene_ref.borrow_mut();
-					let camera = scene.camera_mut();
-					camera.spatial.position += (camera.spatial.rotation_matrix() * tr) * self.delta_time * 5.0;
-				}				
+				// camera follows the input every frame, game state only changes in ticks
+				scene.update_flashes(self.delta_time);
+				for _ in 0..ticks {
+					self.tick_simulation(&mut scene);
+				}
 			}
 
 			self.render_scene();
@@ -178,10 +311,273 @@ impl App {
 	}
 
 
+	/// Puts a ghost of a building at the cursor, pressing the key again switches to the next building
+	fn start_placement(&mut self, scene: &mut GraphicsScene) {
+		let buildings = self.building_data.buildings();
+		if buildings.is_empty() {
+			return;
+		}
+		let mesh = match self.building_mesh {
+			Some(ref mesh) => mesh.clone(),
+			None => return,
+		};
+
+		let next = match self.ghost.take() {
+			Some(ghost) => {
+				scene.remove_mesh_instance(&ghost.instance);
+				buildings.iter().position(|def| def.name == ghost.def.name).map_or(0, |index| (index + 1) % buildings.len())
+			},
+			None => 0,
+		};
+
+		let mut instance = MeshInstance::new(mesh, Spatial { position: vec3(0.0, 0.0, 0.0), rotation: Quaternion::one() });
+		instance.visible = false;
+		self.ghost = Some(BuildingGhost {
+			def: buildings[next].clone(),
+			instance: scene.add_mesh_instance(instance),
+		});
+	}
+
+	/// Moves the ghost with the cursor and tints it by whether the building fits there.
+	/// Left click places the building, right click or cancel drops it.
+	fn update_placement(&mut self, scene: &mut GraphicsScene, camera: &CameraRenderParams) {
+		if self.input.is_key_pressed(Key::Command) || self.input.is_key_pressed(Key::Cancel) {
+			if let Some(ghost) = self.ghost.take() {
+				scene.remove_mesh_instance(&ghost.instance);
+			}
+			return;
+		}
+
+		let (def, instance) = match self.ghost {
+			Some(ref ghost) => (ghost.def.clone(), ghost.instance.clone()),
+			None => return,
+		};
+		let (terrain, navigation) = match (scene.terrain.clone(), self.navigation.as_mut()) {
+			(Some(terrain), Some(navigation)) => (terrain, navigation),
+			_ => return,
+		};
+
+		let cursor = self.input.cursor_position();
+		let position = match terrain.asset.borrow().raycast(&camera.screen_point_to_ray(cursor.x, cursor.y)) {
+			Some(hit) => hit.position,
+			None => {
+				instance.0.borrow_mut().visible = false;
+				return;
+			},
+		};
+
+		if self.input.is_key_pressed(Key::Select) {
+			let mut terrain = terrain.asset.borrow_mut();
+			if spawn_building(&mut self.world, navigation, &mut terrain.heightfield, &def, self.building_mesh.clone(), self.local_player, position).is_ok() {
+				// the terrain shaders read heights from the map, it has to show the levelled ground
+				if def.flatten {
+					*terrain.map.asset.borrow_mut() = heightfield_texture(self.renderer.get_display(), &terrain.heightfield);
+				}
+
+				scene.remove_mesh_instance(&instance);
+				self.ghost = None;
+				return;
+			}
+		}
+
+		let fits = check_placement(&self.world, &navigation.grid, &def, position).is_ok();
+
+		let mut ghost = instance.0.borrow_mut();
+		ghost.teleport(Spatial { position: def.footprint.snap(navigation.grid.cell_size(), position), rotation: Quaternion::one() });
+		ghost.visible = true;
+		ghost.emission = if fits { GHOST_VALID_EMISSION } else { GHOST_INVALID_EMISSION };
+	}
+
+	/// Gives selected units of the local player the order that fits what is under the cursor,
+	/// or the kind of order the override forces
+	fn command_selected_units(&mut self, scene: &GraphicsScene, camera: &CameraRenderParams, override_order: Option<CommandOverride>) {
+		let (terrain, navigation) = match (scene.terrain.as_ref(), self.navigation.as_ref()) {
+			(Some(terrain), Some(navigation)) => (terrain.asset.borrow(), navigation),
+			_ => return,
+		};
+
+		let cursor = self.input.cursor_position();
+		// units hidden in the fog can't be clicked, the order goes to the ground under them
+		let target = match pick_unit(&self.world, camera, cursor, self.fog_of_war.as_ref(), self.local_player) {
+			Some(id) => CommandTarget::Entity { id: id, relationship: relationship(&self.world, self.local_player, id) },
+			None => match terrain.raycast(&camera.screen_point_to_ray(cursor.x, cursor.y)) {
+				Some(hit) => CommandTarget::Ground(hit.position),
+				None => return,
+			},
+		};
+
+		let units: Vec<EntityId> = self.selection.selected().iter().cloned().collect();
+
+		// selected buildings send the units they train there
+		let rally_point = match target {
+			CommandTarget::Ground(position) => Some(position),
+			CommandTarget::Entity { id, .. } => self.world.poses.get(id).map(|pose| pose.position),
+		};
+		for &id in units.iter() {
+			if relationship(&self.world, self.local_player, id) == Relationship::Own {
+				set_rally_point(&mut self.world, id, rally_point);
+			}
+		}
+
+		let commands = resolve_command(&self.world, self.local_player, &units, target, override_order, self.input.modifiers().shift, self.formation);
+
+		for command in commands.iter() {
+			issue_command(&mut self.world, &navigation.grid, &terrain.heightfield, command);
+		}
+	}
+
+	/// Gives selected units of the local player an order without a target
+	fn order_selected_units(&mut self, scene: &GraphicsScene, order: Order) {
+		let (terrain, navigation) = match (scene.terrain.as_ref(), self.navigation.as_ref()) {
+			(Some(terrain), Some(navigation)) => (terrain.asset.borrow(), navigation),
+			_ => return,
+		};
+
+		let command = Command {
+			player: self.local_player,
+			units: self.selection.selected().iter().cloned().collect(),
+			order: order,
+			queued: self.input.modifiers().shift,
+			formation: self.formation,
+		};
+
+		issue_command(&mut self.world, &navigation.grid, &terrain.heightfield, &command);
+	}
+
+	/// Queues the first unit, or upgrade, any of the selected buildings can make right now
+	fn produce_at_selected_buildings(&mut self, upgrades: bool) {
+		for &id in self.selection.selected().iter() {
+			if relationship(&self.world, self.local_player, id) != Relationship::Own {
+				continue;
+			}
+			let building = match (self.world.buildings.get(id), self.world.producers.contains(id)) {
+				(Some(building), true) => building.kind.clone(),
+				_ => continue,
+			};
+
+			for item in self.production_data.items_at(&building) {
+				let is_upgrade = match item {
+					ProductionItem::Upgrade(_) => true,
+					ProductionItem::Unit(_) => false,
+				};
+				if is_upgrade == upgrades && queue_production(&mut self.world, id, item).is_ok() {
+					return;
+				}
+			}
+		}
+	}
+
+	/// Cancels the last item queued in the selected buildings
+	fn cancel_at_selected_buildings(&mut self) {
+		for &id in self.selection.selected().iter() {
+			if relationship(&self.world, self.local_player, id) != Relationship::Own {
+				continue;
+			}
+			let length = self.world.producers.get(id).map_or(0, |producer| producer.queue.len());
+			if length > 0 {
+				cancel_production(&mut self.world, id, length - 1);
+			}
+		}
+	}
+
+	fn tick_simulation(&mut self, scene: &mut GraphicsScene) {
+		let delta_time = self.timestep.tick_delta();
+
+		if let Some(ref mut navigation) = self.navigation {
+			update_navigation(&mut self.world, navigation);
+			update_orders(&mut self.world, navigation, delta_time);
+		}
+
+		if let (Some(terrain), Some(navigation)) = (scene.terrain.clone(), self.navigation.as_ref()) {
+			update_movement(&mut self.world, &navigation.grid, &terrain.asset.borrow().heightfield, delta_time);
+		}
+
+		update_economy(&mut self.world, delta_time);
+		update_production(&mut self.world, delta_time);
+		for event in self.world.take_production_events() {
+			if let (ProductionEvent::Trained { unit, .. }, Some(mesh)) = (event, self.unit_mesh.clone()) {
+				self.world.renderables.insert(unit, Renderable::new(mesh));
+			}
+		}
+		update_combat(&mut self.world, &self.combat_data, delta_time);
+		if let Some(terrain) = scene.terrain.clone() {
+			update_projectiles(&mut self.world, &terrain.asset.borrow().heightfield, &self.combat_data, delta_time);
+		}
+		let combat_events = self.world.take_combat_events();
+
+		if let (Some(terrain), Some(fog)) = (scene.terrain.clone(), self.fog_of_war.as_mut()) {
+			update_visibility(&self.world, fog, &terrain.asset.borrow().heightfield);
+		}
+
+		sync_graphics_scene(&mut self.world, scene);
+		if let Some(ref fog) = self.fog_of_war {
+			sync_fog_of_war(&self.world, fog, self.local_player, scene);
+		}
+		// hits and deaths have nothing to show them yet, dead entities are already gone from the world
+		sync_combat_effects(&combat_events, self.fog_of_war.as_ref(), self.local_player, scene);
+	}
+
 	fn render_scene(&self) {
 		if let Some(ref scene) = self.graphics_scene {
-			self.renderer.render(&scene.borrow())
+			self.renderer.render(&scene.borrow(), self.timestep.alpha())
 		}
 	}
 
 }
+
+/// Game data ships with the game, so a missing or broken file stops it from starting
+fn require_data<T, F>(name: &str, path: &str, load: F) -> T
+	where F: FnOnce(&Path) -> Result<T, DataError>
+{
+	let path = Path::new(path);
+	match load(path) {
+		Ok(data) => data,
+		Err(error) => {
+			eprintln!("Failed to load {} from {}: {}", name, path.display(), error);
+			process::exit(1);
+		}
+	}
+}
+
+fn spawn_unit(world: &mut World, mesh: Asset<Mesh>, owner: PlayerId, position: Vector3, weapon: Option<Rc<WeaponDef>>) -> EntityId {
+	let unit = world.spawn();
+
+	world.poses.insert(unit, Pose::new(position));
+	world.owners.insert(unit, Owner(owner));
+	world.healths.insert(unit, Health::new(100.0));
+	world.movements.insert(unit, Movement::new(5.0));
+	world.order_queues.insert(unit, OrderQueue::new());
+	if let Some(weapon) = weapon {
+		world.weapons.insert(unit, Weapon::new(weapon));
+	}
+	world.renderables.insert(unit, Renderable::new(mesh));
+	world.selectables.insert(unit, Selectable::new(1.0, 2.0));
+	world.visions.insert(unit, Vision::new(12.0, 2.0));
+
+	unit
+}
+
+/// Puts the building down with `place_building` and makes it seen and selectable
+fn spawn_building(world: &mut World, navigation: &mut Navigation, heightfield: &mut Heightfield, def: &BuildingDef, mesh: Option<Asset<Mesh>>, owner: PlayerId, position: Vector3) -> Result<EntityId, PlacementError> {
+	let building = place_building(world, navigation, heightfield, def, owner, position)?;
+
+	if let Some(mesh) = mesh {
+		world.renderables.insert(building, Renderable::new(mesh));
+	}
+	let radius = def.footprint.width().max(def.footprint.depth()) as Real * navigation.grid.cell_size() * 0.5;
+	world.selectables.insert(building, Selectable::new(radius, 2.0));
+	world.visions.insert(building, Vision::new(8.0, 4.0));
+
+	Ok(building)
+}
+
+fn spawn_resource_node(world: &mut World, mesh: Asset<Mesh>, resource: Rc<ResourceDef>, amount: u32, position: Vector3) -> EntityId {
+	let node = world.spawn();
+
+	world.poses.insert(node, Pose::new(position));
+	world.resource_nodes.insert(node, ResourceNode::new(resource, amount));
+	world.renderables.insert(node, Renderable::new(mesh));
+	world.selectables.insert(node, Selectable::new(1.0, 1.0));
+
+	node
+}
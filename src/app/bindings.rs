@@ -0,0 +1,384 @@
+use glium::glutin::{VirtualKeyCode, MouseButton};
+use enum_map::EnumMap;
+use toml;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use super::{Key, Modifiers};
+
+/// Physical button that activates a binding
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum Trigger {
+	Keyboard(VirtualKeyCode),
+	Mouse(MouseButton),
+}
+
+/// Trigger together with modifiers that have to be held, e.g. Ctrl+1
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Binding {
+	pub trigger: Trigger,
+	pub modifiers: Modifiers,
+}
+
+#[derive(Debug)]
+pub enum BindingsError {
+	Io(io::Error),
+	Format(String),
+}
+
+impl From<io::Error> for BindingsError {
+	fn from(error: io::Error) -> Self {
+		BindingsError::Io(error)
+	}
+}
+
+impl fmt::Display for BindingsError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			BindingsError::Io(ref error) => write!(f, "{}", error),
+			BindingsError::Format(ref message) => write!(f, "{}", message),
+		}
+	}
+}
+
+impl Binding {
+
+	pub fn key(code: VirtualKeyCode) -> Self {
+		Binding {
+			trigger: Trigger::Keyboard(code),
+			modifiers: Modifiers::default(),
+		}
+	}
+
+	pub fn mouse(button: MouseButton) -> Self {
+		Binding {
+			trigger: Trigger::Mouse(button),
+			modifiers: Modifiers::default(),
+		}
+	}
+
+	pub fn with_modifiers(self, modifiers: Modifiers) -> Self {
+		Binding {
+			modifiers: modifiers,
+			.. self
+		}
+	}
+
+	/// Modifiers that have to be held are held, extra modifiers are allowed
+	pub fn accepts(&self, modifiers: Modifiers) -> bool {
+		(!self.modifiers.shift || modifiers.shift)
+			&& (!self.modifiers.ctrl || modifiers.ctrl)
+			&& (!self.modifiers.alt || modifiers.alt)
+	}
+
+	pub fn modifier_count(&self) -> usize {
+		self.modifiers.shift as usize + self.modifiers.ctrl as usize + self.modifiers.alt as usize
+	}
+
+	/// Parses bindings written like `W`, `MouseMiddle` or `Ctrl+Shift+1`
+	pub fn parse(text: &str) -> Result<Binding, BindingsError> {
+		let mut modifiers = Modifiers::default();
+		let mut parts: Vec<&str> = text.split('+').map(|part| part.trim()).collect();
+		let trigger_name = parts.pop().unwrap();
+
+		for part in parts {
+			match part.to_lowercase().as_str() {
+				"shift" => modifiers.shift = true,
+				"ctrl" => modifiers.ctrl = true,
+				"alt" => modifiers.alt = true,
+				_ => return Err(BindingsError::Format(format!("unknown modifier '{}' in '{}'", part, text))),
+			}
+		}
+
+		let trigger = match parse_trigger(trigger_name) {
+			Some(trigger) => trigger,
+			None => return Err(BindingsError::Format(format!("unknown key '{}' in '{}'", trigger_name, text))),
+		};
+
+		Ok(Binding {
+			trigger: trigger,
+			modifiers: modifiers,
+		})
+	}
+
+}
+
+impl fmt::Display for Binding {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		if self.modifiers.ctrl {
+			write!(f, "Ctrl+")?;
+		}
+		if self.modifiers.shift {
+			write!(f, "Shift+")?;
+		}
+		if self.modifiers.alt {
+			write!(f, "Alt+")?;
+		}
+		match self.trigger {
+			Trigger::Keyboard(code) => write!(f, "{}", key_code_name(code)),
+			Trigger::Mouse(MouseButton::Left) => write!(f, "MouseLeft"),
+			Trigger::Mouse(MouseButton::Right) => write!(f, "MouseRight"),
+			Trigger::Mouse(MouseButton::Middle) => write!(f, "MouseMiddle"),
+			Trigger::Mouse(MouseButton::Other(button)) => write!(f, "Mouse{}", button),
+		}
+	}
+}
+
+pub struct KeyBindings {
+	bindings: EnumMap<Key, Vec<Binding>>,
+}
+
+impl KeyBindings {
+
+	pub fn empty() -> Self {
+		KeyBindings {
+			bindings: EnumMap::default(),
+		}
+	}
+
+	/// Defaults overridden by the bindings listed in the file.
+	/// Actions missing from the file keep their default bindings.
+	pub fn load(path: &Path) -> Result<Self, BindingsError> {
+		let mut text = String::new();
+		File::open(path)?.read_to_string(&mut text)?;
+
+		let table: BTreeMap<String, Vec<String>> = match toml::from_str(&text) {
+			Ok(table) => table,
+			Err(error) => return Err(BindingsError::Format(format!("{}", error))),
+		};
+
+		let mut bindings = KeyBindings::default();
+
+		for (name, entries) in table {
+			let key = match Key::from_name(&name) {
+				Some(key) => key,
+				None => return Err(BindingsError::Format(format!("unknown action '{}'", name))),
+			};
+
+			let mut key_bindings = Vec::new();
+			for entry in entries {
+				key_bindings.push(Binding::parse(&entry)?);
+			}
+			bindings.set(key, key_bindings);
+		}
+
+		Ok(bindings)
+	}
+
+	/// Falls back to defaults when the file is missing or broken
+	pub fn load_or_default(path: &Path) -> Self {
+		match KeyBindings::load(path) {
+			Ok(bindings) => bindings,
+			Err(BindingsError::Io(ref error)) if error.kind() == io::ErrorKind::NotFound => KeyBindings::default(),
+			Err(error) => {
+				eprintln!("Failed to load key bindings from {}: {}, using the defaults", path.display(), error);
+				KeyBindings::default()
+			}
+		}
+	}
+
+	pub fn save(&self, path: &Path) -> Result<(), BindingsError> {
+		let mut table = BTreeMap::new();
+		for (key, bindings) in &self.bindings {
+			table.insert(key.name().to_string(), bindings.iter().map(|binding| binding.to_string()).collect::<Vec<_>>());
+		}
+
+		let text = match toml::to_string(&table) {
+			Ok(text) => text,
+			Err(error) => return Err(BindingsError::Format(format!("{}", error))),
+		};
+
+		if let Some(directory) = path.parent() {
+			fs::create_dir_all(directory)?;
+		}
+
+		File::create(path)?.write_all(text.as_bytes())?;
+
+		Ok(())
+	}
+
+	pub fn get(&self, key: Key) -> &[Binding] {
+		&self.bindings[key]
+	}
+
+	/// Replaces all bindings of the action
+	pub fn set(&mut self, key: Key, bindings: Vec<Binding>) {
+		self.bindings[key] = bindings;
+	}
+
+	/// Adds one more binding to the action, taking it away from any other action
+	pub fn bind(&mut self, key: Key, binding: Binding) {
+		for (_, bindings) in &mut self.bindings {
+			bindings.retain(|existing| *existing != binding);
+		}
+		self.bindings[key].push(binding);
+	}
+
+	pub fn unbind(&mut self, key: Key, binding: Binding) {
+		self.bindings[key].retain(|existing| *existing != binding);
+	}
+
+	/// Actions activated by pressed triggers.
+	/// When several bindings share a trigger, only the ones with the most
+	/// matching modifiers win, so Ctrl+1 does not also fire 1.
+	pub fn active_keys(&self, pressed: &[Trigger], modifiers: Modifiers) -> Vec<Key> {
+		let mut result = Vec::new();
+
+		for &trigger in pressed {
+			let mut best = 0;
+			let mut matches = Vec::new();
+
+			for (key, bindings) in &self.bindings {
+				for binding in bindings.iter() {
+					if binding.trigger == trigger && binding.accepts(modifiers) {
+						let count = binding.modifier_count();
+						if count > best {
+							best = count;
+							matches.clear();
+						}
+						if count == best {
+							matches.push(key);
+						}
+					}
+				}
+			}
+
+			for key in matches {
+				if !result.contains(&key) {
+					result.push(key);
+				}
+			}
+		}
+
+		result
+	}
+
+}
+
+impl Default for KeyBindings {
+	fn default() -> Self {
+		let mut bindings = KeyBindings::empty();
+
+		bindings.set(Key::Forward, vec![Binding::key(VirtualKeyCode::W), Binding::key(VirtualKeyCode::Up)]);
+		bindings.set(Key::Backward, vec![Binding::key(VirtualKeyCode::S), Binding::key(VirtualKeyCode::Down)]);
+		bindings.set(Key::Left, vec![Binding::key(VirtualKeyCode::A), Binding::key(VirtualKeyCode::Left)]);
+		bindings.set(Key::Right, vec![Binding::key(VirtualKeyCode::D), Binding::key(VirtualKeyCode::Right)]);
+		bindings.set(Key::RotateCamera, vec![Binding::mouse(MouseButton::Middle)]);
+		bindings.set(Key::Select, vec![Binding::mouse(MouseButton::Left)]);
+		bindings.set(Key::Command, vec![Binding::mouse(MouseButton::Right)]);
+		bindings.set(Key::AttackMove, vec![Binding::key(VirtualKeyCode::F)]);
+		bindings.set(Key::Patrol, vec![Binding::key(VirtualKeyCode::P)]);
+		bindings.set(Key::ForceMove, vec![Binding::key(VirtualKeyCode::M)]);
+		bindings.set(Key::Stop, vec![Binding::key(VirtualKeyCode::X)]);
+		bindings.set(Key::Hold, vec![Binding::key(VirtualKeyCode::H)]);
+		bindings.set(Key::Unload, vec![Binding::key(VirtualKeyCode::U)]);
+		bindings.set(Key::CycleFormation, vec![Binding::key(VirtualKeyCode::G)]);
+		bindings.set(Key::Build, vec![Binding::key(VirtualKeyCode::B)]);
+		bindings.set(Key::Train, vec![Binding::key(VirtualKeyCode::T)]);
+		bindings.set(Key::Research, vec![Binding::key(VirtualKeyCode::R)]);
+		bindings.set(Key::CancelProduction, vec![Binding::key(VirtualKeyCode::Back)]);
+		bindings.set(Key::Cancel, vec![Binding::key(VirtualKeyCode::Escape)]);
+
+		bindings
+	}
+}
+
+// Keys that can be written in the bindings file
+const KEY_CODES: &[VirtualKeyCode] = &[
+	VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4, VirtualKeyCode::Key5,
+	VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9, VirtualKeyCode::Key0,
+	VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D, VirtualKeyCode::E, VirtualKeyCode::F,
+	VirtualKeyCode::G, VirtualKeyCode::H, VirtualKeyCode::I, VirtualKeyCode::J, VirtualKeyCode::K, VirtualKeyCode::L,
+	VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O, VirtualKeyCode::P, VirtualKeyCode::Q, VirtualKeyCode::R,
+	VirtualKeyCode::S, VirtualKeyCode::T, VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X,
+	VirtualKeyCode::Y, VirtualKeyCode::Z,
+	VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4, VirtualKeyCode::F5, VirtualKeyCode::F6,
+	VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F9, VirtualKeyCode::F10, VirtualKeyCode::F11, VirtualKeyCode::F12,
+	VirtualKeyCode::Escape, VirtualKeyCode::Space, VirtualKeyCode::Tab, VirtualKeyCode::Return, VirtualKeyCode::Back,
+	VirtualKeyCode::Insert, VirtualKeyCode::Delete, VirtualKeyCode::Home, VirtualKeyCode::End,
+	VirtualKeyCode::PageUp, VirtualKeyCode::PageDown,
+	VirtualKeyCode::Left, VirtualKeyCode::Up, VirtualKeyCode::Right, VirtualKeyCode::Down,
+	VirtualKeyCode::Numpad0, VirtualKeyCode::Numpad1, VirtualKeyCode::Numpad2, VirtualKeyCode::Numpad3, VirtualKeyCode::Numpad4,
+	VirtualKeyCode::Numpad5, VirtualKeyCode::Numpad6, VirtualKeyCode::Numpad7, VirtualKeyCode::Numpad8, VirtualKeyCode::Numpad9,
+	VirtualKeyCode::Minus, VirtualKeyCode::Equals, VirtualKeyCode::LBracket, VirtualKeyCode::RBracket,
+	VirtualKeyCode::Semicolon, VirtualKeyCode::Apostrophe, VirtualKeyCode::Comma, VirtualKeyCode::Period,
+	VirtualKeyCode::Slash, VirtualKeyCode::Backslash, VirtualKeyCode::Grave,
+	VirtualKeyCode::LShift, VirtualKeyCode::RShift, VirtualKeyCode::LControl, VirtualKeyCode::RControl,
+	VirtualKeyCode::LAlt, VirtualKeyCode::RAlt,
+];
+
+fn key_code_name(code: VirtualKeyCode) -> String {
+	let name = format!("{:?}", code);
+	// Key1 .. Key0 are written as plain digits
+	if name.len() == 4 && name.starts_with("Key") {
+		name[3..].to_string()
+	} else {
+		name
+	}
+}
+
+fn parse_trigger(name: &str) -> Option<Trigger> {
+	match name.to_lowercase().as_str() {
+		"mouseleft" => return Some(Trigger::Mouse(MouseButton::Left)),
+		"mouseright" => return Some(Trigger::Mouse(MouseButton::Right)),
+		"mousemiddle" => return Some(Trigger::Mouse(MouseButton::Middle)),
+		lowercase if lowercase.starts_with("mouse") => return lowercase[5..].parse().ok().map(|button| Trigger::Mouse(MouseButton::Other(button))),
+		_ => (),
+	}
+
+	KEY_CODES.iter()
+		.find(|&&code| key_code_name(code).eq_ignore_ascii_case(name))
+		.map(|&code| Trigger::Keyboard(code))
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn ctrl() -> Modifiers {
+		Modifiers {
+			ctrl: true,
+			.. Default::default()
+		}
+	}
+
+	#[test]
+	fn test_parse_binding() {
+		assert_eq!(Binding::parse("W").unwrap(), Binding::key(VirtualKeyCode::W));
+		assert_eq!(Binding::parse("mousemiddle").unwrap(), Binding::mouse(MouseButton::Middle));
+		assert_eq!(Binding::parse("Mouse4").unwrap(), Binding::mouse(MouseButton::Other(4)));
+		assert_eq!(Binding::parse("Ctrl+1").unwrap(), Binding::key(VirtualKeyCode::Key1).with_modifiers(ctrl()));
+		assert!(Binding::parse("Hyper+1").is_err());
+		assert!(Binding::parse("Ctrl+Nope").is_err());
+
+		for text in ["Ctrl+Shift+Alt+F5", "1", "MouseRight", "PageUp"].iter() {
+			assert_eq!(Binding::parse(text).unwrap().to_string(), *text);
+		}
+	}
+
+	#[test]
+	fn test_chord_wins_over_plain_key() {
+		let mut bindings = KeyBindings::empty();
+		bindings.set(Key::Forward, vec![Binding::key(VirtualKeyCode::Key1)]);
+		bindings.set(Key::Backward, vec![Binding::key(VirtualKeyCode::Key1).with_modifiers(ctrl())]);
+
+		let pressed = [Trigger::Keyboard(VirtualKeyCode::Key1)];
+		assert_eq!(bindings.active_keys(&pressed, Modifiers::default()), vec![Key::Forward]);
+		assert_eq!(bindings.active_keys(&pressed, ctrl()), vec![Key::Backward]);
+	}
+
+	#[test]
+	fn test_bind_moves_binding() {
+		let mut bindings = KeyBindings::default();
+		bindings.bind(Key::Left, Binding::key(VirtualKeyCode::W));
+
+		assert_eq!(bindings.get(Key::Forward), &[Binding::key(VirtualKeyCode::Up)]);
+		assert!(bindings.get(Key::Left).contains(&Binding::key(VirtualKeyCode::W)));
+	}
+
+}
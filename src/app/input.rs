@@ -1,15 +1,72 @@
-use glium::glutin::{Event, DeviceEvent, ElementState, WindowEvent, MouseButton, MouseScrollDelta};
+use glium::glutin::{Event, DeviceEvent, ElementState, WindowEvent, MouseScrollDelta, VirtualKeyCode};
 use enum_map::EnumMap;
 
+use std::time::{Duration, Instant};
+
 use ::math::*;
+use super::{KeyBindings, Trigger};
 
-#[derive(PartialEq, Eq, Clone, Copy, EnumMap)]
+/// Game actions, physical keys are mapped to them with `KeyBindings`
+#[derive(PartialEq, Eq, Clone, Copy, Debug, EnumMap)]
 pub enum Key {
 	Forward,
 	Backward,
 	Left,
 	Right,
-	LookAround,
+	RotateCamera,
+	Select,
+	Command,
+	AttackMove,
+	Patrol,
+	ForceMove,
+	Stop,
+	Hold,
+	Unload,
+	CycleFormation,
+	Build,
+	Train,
+	Research,
+	CancelProduction,
+	Cancel,
+}
+
+impl Key {
+
+	/// Name used in the key bindings file
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Key::Forward => "forward",
+			Key::Backward => "backward",
+			Key::Left => "left",
+			Key::Right => "right",
+			Key::RotateCamera => "rotate_camera",
+			Key::Select => "select",
+			Key::Command => "command",
+			Key::AttackMove => "attack_move",
+			Key::Patrol => "patrol",
+			Key::ForceMove => "force_move",
+			Key::Stop => "stop",
+			Key::Hold => "hold",
+			Key::Unload => "unload",
+			Key::CycleFormation => "cycle_formation",
+			Key::Build => "build",
+			Key::Train => "train",
+			Key::Research => "research",
+			Key::CancelProduction => "cancel_production",
+			Key::Cancel => "cancel",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Key> {
+		let keys: EnumMap<Key, ()> = EnumMap::default();
+		for (key, _) in &keys {
+			if key.name() == name {
+				return Some(key);
+			}
+		}
+		None
+	}
+
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, EnumMap)]
@@ -26,20 +83,58 @@ impl Default for KeyState {
 	}
 }
 
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Default, Debug)]
+pub struct Modifiers {
+	pub shift: bool,
+	pub ctrl: bool,
+	pub alt: bool,
+}
+
+// Scroll distance in pixels reported by touchpads that counts as one wheel line
+const PIXELS_PER_WHEEL_LINE: Real = 20.0;
+
+const DOUBLE_CLICK_TIME_MS: u64 = 300;
+const DOUBLE_CLICK_DISTANCE: Real = 4.0;
+
+struct Click {
+	key: Key,
+	time: Instant,
+	position: Vector2,
+}
+
 pub struct Input {
+	bindings: KeyBindings,
+	pressed_triggers: Vec<Trigger>,
 	key_states: EnumMap<Key, KeyState>,
 	delta_mouse: Vector2,
 	delta_mouse_wheel: Real,
+	cursor_position: Vector2,
+	is_cursor_in_window: bool,
+	window_size: Vector2,
+	modifiers: Modifiers,
+	/// Modifier keys held down, left and right ones apart
+	held_modifier_keys: Vec<VirtualKeyCode>,
+	last_click: Option<Click>,
+	double_clicked_key: Option<Key>,
 	is_window_closed: bool,
 }
 
 impl Input {
 
-	pub fn new() -> Self {
+	pub fn new(bindings: KeyBindings) -> Self {
 		Input {
+			bindings: bindings,
+			pressed_triggers: Vec::new(),
 			key_states: EnumMap::default(),
 			delta_mouse: vec2(0.0, 0.0),
 			delta_mouse_wheel: 0.0,
+			cursor_position: vec2(0.0, 0.0),
+			is_cursor_in_window: true,
+			window_size: vec2(0.0, 0.0),
+			modifiers: Modifiers::default(),
+			held_modifier_keys: Vec::new(),
+			last_click: None,
+			double_clicked_key: None,
 			is_window_closed: false,
 		}
 	}
@@ -53,36 +148,43 @@ impl Input {
 			}
 		}
 		self.delta_mouse = vec2(0.0, 0.0);
+		self.delta_mouse_wheel = 0.0;
+		self.double_clicked_key = None;
 	}
 
 	pub fn consume_event(&mut self, event: Event) {
 		match event {
 			Event::WindowEvent { event, .. } => match event {
-				WindowEvent::KeyboardInput { input, .. } => if let Some(key) = self.key_from_scancode(input.scancode) {
-					match input.state {
-						ElementState::Pressed => if self.key_states[key] == KeyState::Up || self.key_states[key] == KeyState::Released {
-							self.key_states[key] = KeyState::Pressed;
-						}
-						ElementState::Released => if self.key_states[key] == KeyState::Down || self.key_states[key] == KeyState::Pressed {
-							self.key_states[key] = KeyState::Released;
-						}
+				WindowEvent::KeyboardInput { input, .. } => {
+					self.update_modifiers(input.virtual_keycode, input.state);
+
+					if let Some(code) = input.virtual_keycode {
+						self.update_trigger(Trigger::Keyboard(code), input.state);
 					}
+					self.update_key_states();
 				},
-				WindowEvent::MouseInput { button, state, .. } => if let Some(key) = self.key_from_mouse_button(button) {
-					match state {
-						ElementState::Pressed => if self.key_states[key] == KeyState::Up || self.key_states[key] == KeyState::Released {
-							self.key_states[key] = KeyState::Pressed;
-						}
-						ElementState::Released => if self.key_states[key] == KeyState::Down || self.key_states[key] == KeyState::Pressed {
-							self.key_states[key] = KeyState::Released;
-						}
+				WindowEvent::MouseInput { button, state, .. } => {
+					self.update_trigger(Trigger::Mouse(button), state);
+
+					for key in self.update_key_states() {
+						self.register_click(key);
 					}
 				},
 				WindowEvent::MouseWheel { delta, .. } => match delta {
 					MouseScrollDelta::LineDelta(_dh, dv) => self.delta_mouse_wheel += dv,
-					MouseScrollDelta::PixelDelta(_dh, dv) => self.delta_mouse_wheel += dv,
+					MouseScrollDelta::PixelDelta(_dh, dv) => self.delta_mouse_wheel += dv / PIXELS_PER_WHEEL_LINE,
+				},
+				WindowEvent::CursorMoved { position, .. } => {
+					self.cursor_position = vec2(position.0 as Real, position.1 as Real);
+				},
+				WindowEvent::CursorEntered { .. } => self.is_cursor_in_window = true,
+				WindowEvent::CursorLeft { .. } => self.is_cursor_in_window = false,
+				WindowEvent::Resized(width, height) => {
+					self.window_size = vec2(width as Real, height as Real);
 				},
 				WindowEvent::Closed => self.is_window_closed = true,
+				// keys released while the window is in the background never send events
+				WindowEvent::Focused(false) => self.release_all(),
 				_ => (),
 			},
 			Event::DeviceEvent { event, .. } => match event {
@@ -102,24 +204,137 @@ impl Input {
 		}
 	}
 
+	pub fn is_key_pressed(&self, key: Key) -> bool {
+		self.key_states[key] == KeyState::Pressed
+	}
+
+	pub fn is_key_released(&self, key: Key) -> bool {
+		self.key_states[key] == KeyState::Released
+	}
+
+	/// True during the frame the second click of a double click happened
+	pub fn is_key_double_clicked(&self, key: Key) -> bool {
+		self.double_clicked_key == Some(key)
+	}
+
+	pub fn modifiers(&self) -> Modifiers {
+		self.modifiers
+	}
+
 	pub fn delta_mouse(&self) -> Vector2 {
 		self.delta_mouse
 	}
 
-	fn key_from_scancode(&self, scancode: u32) -> Option<Key> {
-		match scancode {
-			13 => Some(Key::Forward),
-			0 => Some(Key::Left),
-			1 => Some(Key::Backward),
-			2 => Some(Key::Right),
-			_ => None,
+	pub fn delta_mouse_wheel(&self) -> Real {
+		self.delta_mouse_wheel
+	}
+
+	/// Cursor position in window pixels, origin at the top left corner
+	pub fn cursor_position(&self) -> Vector2 {
+		self.cursor_position
+	}
+
+	pub fn is_cursor_in_window(&self) -> bool {
+		self.is_cursor_in_window
+	}
+
+	pub fn window_size(&self) -> Vector2 {
+		self.window_size
+	}
+
+	pub fn set_window_size(&mut self, width: u32, height: u32) {
+		self.window_size = vec2(width as Real, height as Real);
+	}
+
+	pub fn bindings(&self) -> &KeyBindings {
+		&self.bindings
+	}
+
+	/// Allows rebinding at runtime, changes apply with the next input event
+	pub fn bindings_mut(&mut self) -> &mut KeyBindings {
+		&mut self.bindings
+	}
+
+	fn update_trigger(&mut self, trigger: Trigger, state: ElementState) {
+		match state {
+			ElementState::Pressed => if !self.pressed_triggers.contains(&trigger) {
+				self.pressed_triggers.push(trigger);
+			},
+			ElementState::Released => self.pressed_triggers.retain(|pressed| *pressed != trigger),
+		}
+	}
+
+	/// Returns keys that became pressed
+	fn update_key_states(&mut self) -> Vec<Key> {
+		let active_keys = self.bindings.active_keys(&self.pressed_triggers, self.modifiers);
+		let mut pressed_keys = Vec::new();
+
+		for (key, state) in &mut self.key_states {
+			if active_keys.contains(&key) {
+				if *state == KeyState::Up || *state == KeyState::Released {
+					*state = KeyState::Pressed;
+					pressed_keys.push(key);
+				}
+			} else if *state == KeyState::Down || *state == KeyState::Pressed {
+				*state = KeyState::Released;
+			}
 		}
+
+		pressed_keys
+	}
+
+	fn release_all(&mut self) {
+		self.modifiers = Modifiers::default();
+		self.held_modifier_keys.clear();
+		self.pressed_triggers.clear();
+		self.update_key_states();
 	}
 
-	fn key_from_mouse_button(&self, mouse_button: MouseButton) -> Option<Key> {
-		match mouse_button {
-			MouseButton::Left => Some(Key::LookAround),
-			_ => None,
+	fn update_modifiers(&mut self, keycode: Option<VirtualKeyCode>, state: ElementState) {
+		let keycode = match keycode {
+			Some(keycode @ VirtualKeyCode::LShift) | Some(keycode @ VirtualKeyCode::RShift) |
+			Some(keycode @ VirtualKeyCode::LControl) | Some(keycode @ VirtualKeyCode::RControl) |
+			Some(keycode @ VirtualKeyCode::LAlt) | Some(keycode @ VirtualKeyCode::RAlt) => keycode,
+			_ => return,
+		};
+
+		self.held_modifier_keys.retain(|&held| held != keycode);
+		if state == ElementState::Pressed {
+			self.held_modifier_keys.push(keycode);
+		}
+
+		let modifiers = {
+			let held_keys = &self.held_modifier_keys;
+			let is_held = |left, right| held_keys.iter().any(|&held| held == left || held == right);
+			Modifiers {
+				shift: is_held(VirtualKeyCode::LShift, VirtualKeyCode::RShift),
+				ctrl: is_held(VirtualKeyCode::LControl, VirtualKeyCode::RControl),
+				alt: is_held(VirtualKeyCode::LAlt, VirtualKeyCode::RAlt),
+			}
+		};
+		self.modifiers = modifiers;
+	}
+
+	fn register_click(&mut self, key: Key) {
+		let now = Instant::now();
+		let position = self.cursor_position;
+
+		let is_double_click = match self.last_click {
+			Some(ref click) => click.key == key
+				&& now.duration_since(click.time) < Duration::from_millis(DOUBLE_CLICK_TIME_MS)
+				&& (position - click.position).magnitude() < DOUBLE_CLICK_DISTANCE,
+			None => false,
+		};
+
+		if is_double_click {
+			self.double_clicked_key = Some(key);
+			self.last_click = None;
+		} else {
+			self.last_click = Some(Click {
+				key: key,
+				time: now,
+				position: position,
+			});
 		}
 	}
 
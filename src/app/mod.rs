@@ -1,5 +1,9 @@
 mod app;
+mod bindings;
 mod input;
+mod timestep;
 
 pub use self::app::{App};
-pub use self::input::{Input};
+pub use self::bindings::{Binding, BindingsError, KeyBindings, Trigger};
+pub use self::input::{Input, Key, Modifiers};
+pub use self::timestep::{FixedTimestep, TICK_RATE, duration_to_secs};
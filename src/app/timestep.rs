@@ -0,0 +1,145 @@
+use std::time::{Duration, Instant};
+
+/// Simulation ticks per second
+pub const TICK_RATE: u32 = 20;
+
+/// Splits real time into fixed simulation ticks.
+///
+/// Rendering runs as fast as it can, the simulation always advances by
+/// `tick_delta()`, so its results don't depend on the frame rate.
+/// Time left in the accumulator is exposed as `alpha()` for interpolating
+/// between the last two ticks.
+pub struct FixedTimestep {
+	tick_duration: Duration,
+	accumulator: Duration,
+	last_time: Option<Instant>,
+	/// Ticks run at most per frame, time beyond that is dropped so
+	/// a slow frame can't make the next one even slower
+	pub max_ticks_per_frame: u32,
+	tick: u64,
+}
+
+impl FixedTimestep {
+
+	pub fn new(tick_rate: u32) -> Self {
+		assert!(tick_rate > 0);
+
+		FixedTimestep {
+			tick_duration: Duration::new(0, 1_000_000_000 / tick_rate),
+			accumulator: Duration::new(0, 0),
+			last_time: None,
+			max_ticks_per_frame: 5,
+			tick: 0,
+		}
+	}
+
+	/// Accumulates time passed since the previous call and returns
+	/// how many ticks the simulation has to run this frame
+	pub fn advance(&mut self, now: Instant) -> u32 {
+		let elapsed = match self.last_time {
+			Some(last_time) if now > last_time => now.duration_since(last_time),
+			_ => Duration::new(0, 0),
+		};
+		self.last_time = Some(now);
+
+		self.accumulate(elapsed)
+	}
+
+	pub fn accumulate(&mut self, elapsed: Duration) -> u32 {
+		self.accumulator += elapsed;
+
+		let mut ticks = 0;
+		while self.accumulator >= self.tick_duration {
+			if ticks == self.max_ticks_per_frame {
+				// spiral of death protection, simulation falls behind real time instead
+				self.accumulator = Duration::new(0, 0);
+				break;
+			}
+			self.accumulator -= self.tick_duration;
+			ticks += 1;
+		}
+
+		self.tick += ticks as u64;
+
+		ticks
+	}
+
+	/// Seconds of game time a single tick simulates
+	pub fn tick_delta(&self) -> f32 {
+		duration_to_secs(self.tick_duration)
+	}
+
+	/// Number of ticks run so far
+	pub fn tick(&self) -> u64 {
+		self.tick
+	}
+
+	/// How far real time got between the last tick and the next one, 0..1
+	pub fn alpha(&self) -> f32 {
+		(duration_to_secs(self.accumulator) / self.tick_delta()).min(1.0)
+	}
+
+}
+
+pub fn duration_to_secs(duration: Duration) -> f32 {
+	(duration.as_secs() as f32) + (duration.subsec_nanos() as f32) * 0.000000001
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn millis(ms: u64) -> Duration {
+		Duration::from_millis(ms)
+	}
+
+	#[test]
+	fn test_ticks_independent_of_frame_rate() {
+		let mut fast = FixedTimestep::new(20);
+		let mut slow = FixedTimestep::new(20);
+
+		for _ in 0..600 {
+			fast.accumulate(millis(5));
+		}
+		for _ in 0..100 {
+			slow.accumulate(millis(30));
+		}
+
+		assert_eq!(fast.tick(), 60);
+		assert_eq!(slow.tick(), 60);
+		assert_eq!(fast.alpha(), 0.0);
+	}
+
+	#[test]
+	fn test_alpha() {
+		let mut timestep = FixedTimestep::new(20);
+
+		assert_eq!(timestep.accumulate(millis(60)), 1);
+		assert!((timestep.alpha() - 0.2).abs() < 0.0001);
+
+		assert_eq!(timestep.accumulate(millis(90)), 2);
+		assert!(timestep.alpha().abs() < 0.0001);
+	}
+
+	#[test]
+	fn test_spiral_of_death() {
+		let mut timestep = FixedTimestep::new(20);
+		timestep.max_ticks_per_frame = 4;
+
+		assert_eq!(timestep.accumulate(millis(10_000)), 4);
+		assert_eq!(timestep.alpha(), 0.0);
+		assert_eq!(timestep.accumulate(millis(50)), 1);
+	}
+
+	#[test]
+	fn test_advance_with_instant() {
+		let mut timestep = FixedTimestep::new(20);
+		let start = Instant::now();
+
+		assert_eq!(timestep.advance(start), 0);
+		assert_eq!(timestep.advance(start + millis(120)), 2);
+		assert_eq!(timestep.advance(start + millis(150)), 1);
+	}
+
+}
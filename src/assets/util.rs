@@ -5,6 +5,8 @@ use std::vec::Vec;
 
 use ::gfx::resources::{Mesh, MeshVertex, Material};
 use ::assets::Asset;
+use ::terrain::Heightfield;
+use ::math::*;
 
 pub fn load_texture(display: &Display, path: &Path) -> Asset<Texture2d> {
 	use image::open;
@@ -20,6 +22,40 @@ pub fn load_texture(display: &Display, path: &Path) -> Asset<Texture2d> {
 	Asset::asset(texture)
 }
 
+/// Loads the red channel of an image as a heightfield, with rows ordered
+/// the same way `load_texture` uploads them
+pub fn load_heightfield(path: &Path, scale: Vector3) -> Heightfield {
+	use image::open;
+
+	let image = open(path).unwrap().to_rgba();
+	let (width, height) = image.dimensions();
+
+	let mut samples = Vec::with_capacity((width * height) as usize);
+	for y in (0..height).rev() {
+		for x in 0..width {
+			samples.push(image.get_pixel(x, y)[0] as Real / 255.0);
+		}
+	}
+
+	Heightfield::new(width as usize, height as usize, samples, scale)
+}
+
+/// Uploads heightfield samples in the layout `load_heightfield` reads them,
+/// used to refresh the terrain texture after the heightfield was edited
+pub fn heightfield_texture(display: &Display, heightfield: &Heightfield) -> Texture2d {
+	use glium::texture::{RawImage2d, ClientFormat};
+	use std::borrow::Cow;
+
+	let image = RawImage2d {
+		data: Cow::Borrowed(heightfield.samples()),
+		width: heightfield.width() as u32,
+		height: heightfield.height() as u32,
+		format: ClientFormat::F32,
+	};
+
+	Texture2d::new(display, image).unwrap()
+}
+
 pub fn load_mesh(display: &Display, path: &Path, material: Asset<Material>) -> Asset<Mesh> {
 	use assimp::import::Importer;
 
@@ -0,0 +1,148 @@
+use ::app::{Input, Key};
+use ::gfx::scene::Camera;
+use ::terrain::Terrain;
+use ::math::*;
+
+pub struct RTSCameraController {
+	camera: Camera,
+	focus: Vector3,
+	yaw: Rad<Real>,
+	pitch: Rad<Real>,
+	zoom: Real,
+
+	/// Pan speed in camera distances per second
+	pub pan_speed: Real,
+	/// Width of the window border in pixels that triggers edge scrolling
+	pub edge_scroll_margin: Real,
+	/// Zoom change per one mouse wheel line
+	pub zoom_speed: Real,
+	/// Orbit speed in radians per pixel of mouse movement
+	pub rotate_speed: Real,
+	pub min_distance: Real,
+	pub max_distance: Real,
+	/// Pitch at the closest zoom
+	pub min_pitch: Deg<Real>,
+	/// Pitch at the farthest zoom
+	pub max_pitch: Deg<Real>,
+	pub min_ground_clearance: Real,
+}
+
+impl RTSCameraController {
+
+	pub fn new(focus: Vector3) -> Self {
+		RTSCameraController {
+			camera: Camera::default(),
+			focus: focus,
+			yaw: Rad(0.0),
+			pitch: Rad(0.0),
+			zoom: 0.5,
+
+			pan_speed: 1.0,
+			edge_scroll_margin: 8.0,
+			zoom_speed: 0.1,
+			rotate_speed: 0.005,
+			min_distance: 10.0,
+			max_distance: 120.0,
+			min_pitch: Deg(35.0),
+			max_pitch: Deg(70.0),
+			min_ground_clearance: 2.0,
+		}
+	}
+
+	pub fn camera(&self) -> &Camera {
+		&self.camera
+	}
+
+	/// Point on the ground the camera orbits around and looks at
+	pub fn focus(&self) -> Vector3 {
+		self.focus
+	}
+
+	pub fn update(&mut self, input: &Input, terrain: &Terrain, delta_time: Real) -> Spatial {
+		if input.is_key_down(Key::RotateCamera) {
+			self.yaw += Rad(input.delta_mouse().x * self.rotate_speed);
+		}
+
+		self.zoom = (self.zoom - input.delta_mouse_wheel() * self.zoom_speed).max(0.0).min(1.0);
+
+		let pan = self.pan_direction(input);
+		if pan.magnitude2() > 0.0 {
+			self.focus += pan.normalize() * self.pan_speed * self.distance() * delta_time;
+		}
+
+		let scale = terrain.scale();
+		self.focus.x = self.focus.x.max(0.0).min(scale.x);
+		self.focus.z = self.focus.z.max(0.0).min(scale.z);
+		self.focus.y = terrain.height_at(self.focus.x, self.focus.z);
+
+		self.pitch = Rad::from(self.min_pitch + (self.max_pitch - self.min_pitch) * self.zoom);
+
+		let mut position = self.focus - self.forward() * self.distance();
+
+		let min_height = terrain.height_at(position.x, position.z) + self.min_ground_clearance;
+		if position.y < min_height {
+			position.y = min_height;
+			let offset = position - self.focus;
+			self.pitch = Rad(offset.y.atan2(vec2(offset.x, offset.z).magnitude()));
+		}
+
+		self.camera.spatial = Spatial {
+			position: position,
+			rotation: self.rotation(),
+		};
+
+		self.camera.spatial
+	}
+
+	fn pan_direction(&self, input: &Input) -> Vector3 {
+		let forward = vec3(self.yaw.sin(), 0.0, self.yaw.cos());
+		let right = vec3(self.yaw.cos(), 0.0, -self.yaw.sin());
+
+		let mut direction = vec3(0.0, 0.0, 0.0);
+
+		if input.is_key_down(Key::Forward) {
+			direction += forward;
+		}
+		if input.is_key_down(Key::Backward) {
+			direction -= forward;
+		}
+		if input.is_key_down(Key::Left) {
+			direction -= right;
+		}
+		if input.is_key_down(Key::Right) {
+			direction += right;
+		}
+
+		let window_size = input.window_size();
+		if input.is_cursor_in_window() && window_size.x > 0.0 && window_size.y > 0.0 {
+			let cursor = input.cursor_position();
+			if cursor.x < self.edge_scroll_margin {
+				direction -= right;
+			}
+			if cursor.x > window_size.x - self.edge_scroll_margin {
+				direction += right;
+			}
+			if cursor.y < self.edge_scroll_margin {
+				direction += forward;
+			}
+			if cursor.y > window_size.y - self.edge_scroll_margin {
+				direction -= forward;
+			}
+		}
+
+		direction
+	}
+
+	fn distance(&self) -> Real {
+		self.min_distance + (self.max_distance - self.min_distance) * self.zoom
+	}
+
+	fn rotation(&self) -> Quaternion {
+		Quaternion::from_angle_y(self.yaw) * Quaternion::from_angle_x(self.pitch)
+	}
+
+	fn forward(&self) -> Vector3 {
+		self.rotation() * vec3(0.0, 0.0, 1.0)
+	}
+
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+use toml;
+
+use ::game::{World, EntityId, Relationship, body_radius, fire_projectile, relationship};
+use ::game::{DataError, parse_data, load_data, data_entries, data_real, data_positive, data_str, data_strings};
+use ::math::*;
+
+/// Armor never takes away more than this part of a hit
+const MIN_DAMAGE_FRACTION: Real = 0.1;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum DamageType {
+	Normal,
+	Piercing,
+	Explosive,
+}
+
+impl DamageType {
+
+	/// Name used in data files
+	pub fn name(&self) -> &'static str {
+		match *self {
+			DamageType::Normal => "normal",
+			DamageType::Piercing => "piercing",
+			DamageType::Explosive => "explosive",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<DamageType> {
+		DamageType::all().iter().cloned().find(|damage_type| damage_type.name() == name)
+	}
+
+	pub fn all() -> &'static [DamageType] {
+		&[DamageType::Normal, DamageType::Piercing, DamageType::Explosive]
+	}
+
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum ArmorType {
+	Unarmored,
+	Light,
+	Heavy,
+	Structure,
+}
+
+impl ArmorType {
+
+	/// Name used in data files
+	pub fn name(&self) -> &'static str {
+		match *self {
+			ArmorType::Unarmored => "unarmored",
+			ArmorType::Light => "light",
+			ArmorType::Heavy => "heavy",
+			ArmorType::Structure => "structure",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<ArmorType> {
+		ArmorType::all().iter().cloned().find(|armor_type| armor_type.name() == name)
+	}
+
+	pub fn all() -> &'static [ArmorType] {
+		&[ArmorType::Unarmored, ArmorType::Light, ArmorType::Heavy, ArmorType::Structure]
+	}
+
+}
+
+/// Weapon stats shared by all units carrying the weapon
+#[derive(Clone, PartialEq, Debug)]
+pub struct WeaponDef {
+	pub name: String,
+	/// Reach from the center of the shooter to the edge of the target
+	pub range: Real,
+	/// Seconds between shots
+	pub cooldown: Real,
+	pub damage: Real,
+	pub damage_type: DamageType,
+	pub targets_units: bool,
+	pub targets_buildings: bool,
+	/// Shots fly as projectiles instead of hitting instantly
+	pub projectile: Option<ProjectileDef>,
+}
+
+/// Projectile fired by a weapon
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ProjectileDef {
+	/// Launch speed, units per second
+	pub speed: Real,
+	/// Everything this close to the impact gets hurt, less the further away
+	pub splash_radius: Real,
+	/// Lob shots over obstacles instead of firing the flat way
+	pub high_arc: bool,
+}
+
+impl WeaponDef {
+
+	fn from_toml(name: &str, value: &toml::Value) -> Result<WeaponDef, DataError> {
+		let context = format!("weapon '{}'", name);
+
+		let damage_type = data_str(value, "damage_type", &context)?;
+		let damage_type = match DamageType::from_name(damage_type) {
+			Some(damage_type) => damage_type,
+			None => return Err(DataError::Format(format!("unknown damage type '{}' of {}", damage_type, context))),
+		};
+
+		let mut weapon = WeaponDef {
+			name: name.to_string(),
+			range: data_positive(value, "range", &context)?,
+			cooldown: data_positive(value, "cooldown", &context)?,
+			damage: data_real(value, "damage", &context)?,
+			damage_type: damage_type,
+			targets_units: false,
+			targets_buildings: false,
+			projectile: None,
+		};
+
+		if value.get("projectile_speed").is_some() {
+			weapon.projectile = Some(ProjectileDef {
+				speed: data_real(value, "projectile_speed", &context)?,
+				splash_radius: if value.get("splash_radius").is_some() { data_real(value, "splash_radius", &context)? } else { 0.0 },
+				high_arc: value.get("high_arc").and_then(|high_arc| high_arc.as_bool()).unwrap_or(false),
+			});
+		}
+
+		// a weapon without targets would never fire
+		let targets = data_strings(value, "targets", &context)?;
+		if targets.is_empty() {
+			return Err(DataError::Format(format!("{} has no 'targets'", context)));
+		}
+		for target in targets {
+			match target {
+				"units" => weapon.targets_units = true,
+				"buildings" => weapon.targets_buildings = true,
+				_ => return Err(DataError::Format(format!("unknown target '{}' of {}", target, context))),
+			}
+		}
+
+		Ok(weapon)
+	}
+
+}
+
+/// Weapons and damage multipliers described in the combat data file
+pub struct CombatData {
+	weapons: BTreeMap<String, Rc<WeaponDef>>,
+	multipliers: BTreeMap<(DamageType, ArmorType), Real>,
+}
+
+impl CombatData {
+
+	pub fn empty() -> Self {
+		CombatData {
+			weapons: BTreeMap::new(),
+			multipliers: BTreeMap::new(),
+		}
+	}
+
+	pub fn parse(text: &str) -> Result<Self, DataError> {
+		CombatData::from_toml(&parse_data(text)?)
+	}
+
+	pub fn load(path: &Path) -> Result<Self, DataError> {
+		CombatData::from_toml(&load_data(path)?)
+	}
+
+	fn from_toml(value: &toml::Value) -> Result<Self, DataError> {
+		let mut data = CombatData::empty();
+
+		for (name, weapon) in data_entries(value, "weapons")? {
+			data.weapons.insert(name.clone(), Rc::new(WeaponDef::from_toml(name, weapon)?));
+		}
+
+		for (damage_name, armors) in data_entries(value, "multipliers")? {
+			let damage_type = match DamageType::from_name(damage_name) {
+				Some(damage_type) => damage_type,
+				None => return Err(DataError::Format(format!("unknown damage type '{}' in multipliers", damage_name))),
+			};
+
+			let context = format!("{} multipliers", damage_name);
+			let armor_names = match armors.as_table() {
+				Some(table) => table.keys(),
+				None => return Err(DataError::Format(format!("{} have to be a table", context))),
+			};
+			for armor_name in armor_names {
+				let armor_type = match ArmorType::from_name(armor_name) {
+					Some(armor_type) => armor_type,
+					None => return Err(DataError::Format(format!("unknown armor type '{}' in {}", armor_name, context))),
+				};
+				data.multipliers.insert((damage_type, armor_type), data_real(armors, armor_name, &context)?);
+			}
+		}
+
+		Ok(data)
+	}
+
+	pub fn weapon(&self, name: &str) -> Option<Rc<WeaponDef>> {
+		self.weapons.get(name).cloned()
+	}
+
+	/// Part of the damage dealt to the armor, pairs missing from the table deal full damage
+	pub fn multiplier(&self, damage_type: DamageType, armor_type: ArmorType) -> Real {
+		self.multipliers.get(&(damage_type, armor_type)).cloned().unwrap_or(1.0)
+	}
+
+}
+
+/// Weapon carried by an entity
+#[derive(Clone, Debug)]
+pub struct Weapon {
+	pub def: Rc<WeaponDef>,
+	/// Seconds until the weapon can fire again
+	pub cooldown: Real,
+}
+
+impl Weapon {
+
+	pub fn new(def: Rc<WeaponDef>) -> Self {
+		Weapon {
+			def: def,
+			cooldown: 0.0,
+		}
+	}
+
+}
+
+/// Protection of an entity, `amount` is taken off every hit
+#[derive(Copy, Clone, Debug)]
+pub struct Armor {
+	pub armor_type: ArmorType,
+	pub amount: Real,
+}
+
+impl Armor {
+
+	pub fn new(armor_type: ArmorType, amount: Real) -> Self {
+		Armor {
+			armor_type: armor_type,
+			amount: amount,
+		}
+	}
+
+}
+
+/// Things that happened in combat, for effects and statistics
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CombatEvent {
+	Hit { target: EntityId, attacker: Option<EntityId>, damage: Real },
+	/// The entity is already destroyed when the event is read
+	Died { entity: EntityId, killer: Option<EntityId>, position: Vector3 },
+	/// Projectile came down, `hit` is the entity it struck directly, if any
+	Impact { position: Vector3, splash_radius: Real, hit: Option<EntityId> },
+}
+
+/// Fires weapons at targets in range, called once per tick after movement
+pub fn update_combat(world: &mut World, data: &CombatData, dt: Real) {
+	for id in world.weapons.ids() {
+		// the shooter could have died earlier in the tick
+		let def = match world.weapons.get_mut(id) {
+			Some(weapon) => {
+				weapon.cooldown = (weapon.cooldown - dt).max(0.0);
+				if weapon.cooldown > 0.0 {
+					continue;
+				}
+				weapon.def.clone()
+			}
+			None => continue,
+		};
+
+		let target = match weapon_target(world, id) {
+			Some(target) => target,
+			None => continue,
+		};
+
+		if def.projectile.is_some() && !fire_projectile(world, id, target, &def) {
+			// out of reach at this launch speed, try again next tick
+			continue;
+		}
+
+		world.weapons.get_mut(id).unwrap().cooldown = def.cooldown;
+		if def.projectile.is_none() {
+			apply_damage(world, data, target, Some(id), def.damage, def.damage_type);
+		}
+	}
+}
+
+/// Target the weapon of the entity fires at: the one it was ordered to fight if in range,
+/// otherwise the nearest enemy in range
+fn weapon_target(world: &World, id: EntityId) -> Option<EntityId> {
+	let ordered = world.order_queues.get(id).and_then(|queue| queue.target);
+	if let Some(target) = ordered {
+		if can_attack(world, id, target) && is_in_range(world, id, target) {
+			return Some(target);
+		}
+	}
+
+	let range = world.weapons.get(id)?.def.range;
+	find_target(world, id, range)
+}
+
+/// Whether the weapon of the entity can hit the target, wherever it is
+pub fn can_attack(world: &World, id: EntityId, target: EntityId) -> bool {
+	let weapon = match world.weapons.get(id) {
+		Some(weapon) => weapon,
+		None => return false,
+	};
+
+	if target == id || !world.healths.contains(target) {
+		return false;
+	}
+
+	if world.buildings.contains(target) {
+		weapon.def.targets_buildings
+	} else {
+		weapon.def.targets_units
+	}
+}
+
+/// Distance between centers from which the entity hits the target
+pub fn attack_distance(world: &World, id: EntityId, target: EntityId) -> Option<Real> {
+	world.weapons.get(id).map(|weapon| weapon.def.range + body_radius(world, target))
+}
+
+fn is_in_range(world: &World, id: EntityId, target: EntityId) -> bool {
+	let distance = match (world.poses.get(id), world.poses.get(target)) {
+		(Some(a), Some(b)) => (vec2(a.position.x, a.position.z) - vec2(b.position.x, b.position.z)).magnitude(),
+		_ => return false,
+	};
+	attack_distance(world, id, target).map_or(false, |reach| distance <= reach)
+}
+
+/// Nearest enemy within the range the entity's weapon can hit, ties go to the lower id.
+/// Allies and neutral players are left alone.
+pub fn find_target(world: &World, id: EntityId, range: Real) -> Option<EntityId> {
+	let position = match world.poses.get(id) {
+		Some(pose) => vec2(pose.position.x, pose.position.z),
+		None => return None,
+	};
+	let owner = match world.owners.get(id) {
+		Some(owner) => owner.0,
+		None => return None,
+	};
+
+	world.spatial_hash.query_radius(position, range).into_iter()
+		.filter(|&other| relationship(world, owner, other) == Relationship::Enemy)
+		.filter(|&other| can_attack(world, id, other))
+		.filter_map(|other| world.poses.get(other).map(|pose| ((vec2(pose.position.x, pose.position.z) - position).magnitude2(), other)))
+		.min_by(|a, b| a.partial_cmp(b).unwrap())
+		.map(|(_, other)| other)
+}
+
+/// Hurts the target through its armor, destroying it when health runs out
+pub fn apply_damage(world: &mut World, data: &CombatData, target: EntityId, attacker: Option<EntityId>, damage: Real, damage_type: DamageType) {
+	let armor = world.armors.get(target).cloned().unwrap_or(Armor::new(ArmorType::Unarmored, 0.0));
+	let damage = damage * data.multiplier(damage_type, armor.armor_type);
+	let damage = (damage - armor.amount).max(damage * MIN_DAMAGE_FRACTION);
+
+	let is_dead = match world.healths.get_mut(target) {
+		Some(health) => {
+			health.current -= damage;
+			health.is_dead()
+		}
+		None => return,
+	};
+
+	world.push_combat_event(CombatEvent::Hit { target: target, attacker: attacker, damage: damage });
+
+	if is_dead {
+		let position = world.poses.get(target).map_or(vec3(0.0, 0.0, 0.0), |pose| pose.position);
+		world.push_combat_event(CombatEvent::Died { entity: target, killer: attacker, position: position });
+		world.destroy(target);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Health, PlayerId, Player, TeamId, Stance, Building, update_spatial_hash};
+	use ::game::test_util::{flat_heightfield, spawn, simulate_combat};
+
+	const DATA: &str = r#"
+		[weapons.rifle]
+		range = 5
+		cooldown = 1.0
+		damage = 10.0
+		damage_type = "piercing"
+		targets = ["units", "buildings"]
+
+		[weapons.demolition]
+		range = 2.0
+		cooldown = 2.0
+		damage = 50.0
+		damage_type = "explosive"
+		targets = ["buildings"]
+
+		[multipliers.piercing]
+		heavy = 0.5
+		structure = 0.25
+	"#;
+
+	/// Target with 30 health, armed with the weapon if there is one
+	fn spawn_armed(world: &mut World, data: &CombatData, player: PlayerId, position: Vector3, weapon: Option<&str>) -> EntityId {
+		let id = spawn(world, player, position);
+		world.healths.insert(id, Health::new(30.0));
+		if let Some(name) = weapon {
+			world.weapons.insert(id, Weapon::new(data.weapon(name).unwrap()));
+		}
+		id
+	}
+
+	#[test]
+	fn test_parse_data() {
+		let data = CombatData::parse(DATA).unwrap();
+
+		let rifle = data.weapon("rifle").unwrap();
+		assert_eq!(rifle.range, 5.0);
+		assert_eq!(rifle.damage_type, DamageType::Piercing);
+		assert!(rifle.targets_units && rifle.targets_buildings);
+		assert!(!data.weapon("demolition").unwrap().targets_units);
+
+		assert_eq!(data.multiplier(DamageType::Piercing, ArmorType::Heavy), 0.5);
+		assert_eq!(data.multiplier(DamageType::Explosive, ArmorType::Heavy), 1.0);
+
+		assert!(CombatData::parse("[weapons.laser]\nrange = 1\ncooldown = 1\ndamage = 1\ndamage_type = \"laser\"").is_err());
+		assert!(CombatData::parse("[multipliers.piercing]\npaper = 2.0").is_err());
+		assert!(CombatData::parse("[weapons.rifle]\nrange = 1").is_err());
+
+		let weapon = "damage = 1\ndamage_type = \"normal\"";
+		assert!(CombatData::parse(&format!("[weapons.a]\n{}\nrange = 1\ncooldown = 1", weapon)).is_err());
+		assert!(CombatData::parse(&format!("[weapons.a]\n{}\nrange = 1\ncooldown = 1\ntargets = []", weapon)).is_err());
+		assert!(CombatData::parse(&format!("[weapons.a]\n{}\nrange = 0\ncooldown = 1\ntargets = [\"units\"]", weapon)).is_err());
+		assert!(CombatData::parse(&format!("[weapons.a]\n{}\nrange = 1\ncooldown = -1\ntargets = [\"units\"]", weapon)).is_err());
+		assert!(CombatData::parse(&format!("[weapons.a]\n{}\nrange = 1\ncooldown = 1\ntargets = [\"units\"]", weapon)).is_ok());
+	}
+
+	#[test]
+	fn test_shipped_data() {
+		let data = CombatData::load(Path::new("data/combat.toml")).unwrap();
+		assert!(data.weapon("rifle").is_some());
+	}
+
+	#[test]
+	fn test_duel() {
+		let data = CombatData::parse(DATA).unwrap();
+		let mut world = World::new();
+		let shooter = spawn_armed(&mut world, &data, PlayerId(0), vec3(0.0, 0.0, 0.0), Some("rifle"));
+		let target = spawn_armed(&mut world, &data, PlayerId(1), vec3(4.0, 0.0, 0.0), None);
+		let far = spawn_armed(&mut world, &data, PlayerId(1), vec3(20.0, 0.0, 0.0), None);
+
+		// a shot every second
+		let heightfield = flat_heightfield(32);
+		simulate_combat(&mut world, &heightfield, &data, 40);
+		assert!(world.is_alive(target));
+		assert_eq!(world.healths.get(target).unwrap().current, 10.0);
+
+		simulate_combat(&mut world, &heightfield, &data, 10);
+		assert!(!world.is_alive(target));
+		assert!(world.is_alive(far));
+
+		let events = world.take_combat_events();
+		assert_eq!(events.len(), 4);
+		assert_eq!(events[3], CombatEvent::Died { entity: target, killer: Some(shooter), position: vec3(4.0, 0.0, 0.0) });
+		assert!(world.take_combat_events().is_empty());
+	}
+
+	#[test]
+	fn test_armor() {
+		let data = CombatData::parse(DATA).unwrap();
+		let mut world = World::new();
+		let target = spawn_armed(&mut world, &data, PlayerId(1), vec3(0.0, 0.0, 0.0), None);
+
+		world.armors.insert(target, Armor::new(ArmorType::Heavy, 2.0));
+		apply_damage(&mut world, &data, target, None, 10.0, DamageType::Piercing);
+		assert_eq!(world.healths.get(target).unwrap().current, 27.0);
+
+		// armor can't stop a hit completely
+		world.armors.insert(target, Armor::new(ArmorType::Heavy, 100.0));
+		apply_damage(&mut world, &data, target, None, 10.0, DamageType::Piercing);
+		assert_eq!(world.healths.get(target).unwrap().current, 26.5);
+	}
+
+	#[test]
+	fn test_target_acquisition() {
+		let data = CombatData::parse(DATA).unwrap();
+		let mut world = World::new();
+		let sapper = spawn_armed(&mut world, &data, PlayerId(0), vec3(0.0, 0.0, 0.0), Some("demolition"));
+		let friend = spawn_armed(&mut world, &data, PlayerId(0), vec3(1.0, 0.0, 0.0), None);
+		let enemy = spawn_armed(&mut world, &data, PlayerId(1), vec3(0.0, 0.0, 1.0), None);
+		let building = spawn_armed(&mut world, &data, PlayerId(1), vec3(-1.5, 0.0, 3.0), None);
+		world.buildings.insert(building, Building::new("bunker"));
+		update_spatial_hash(&mut world);
+
+		// only buildings for the sapper, even with a unit closer
+		assert_eq!(find_target(&world, sapper, 5.0), Some(building));
+		assert!(can_attack(&world, sapper, building) && !can_attack(&world, sapper, enemy) && !can_attack(&world, sapper, friend));
+
+		let rifleman = spawn_armed(&mut world, &data, PlayerId(0), vec3(0.0, 0.0, -1.0), Some("rifle"));
+		let other = spawn_armed(&mut world, &data, PlayerId(1), vec3(0.0, 0.0, -3.0), None);
+		update_spatial_hash(&mut world);
+		// enemy and other are both 2 away, the lower id wins
+		assert_eq!(find_target(&world, rifleman, 5.0), Some(enemy));
+		world.destroy(enemy);
+		update_spatial_hash(&mut world);
+		assert_eq!(find_target(&world, rifleman, 5.0), Some(other));
+	}
+
+	#[test]
+	fn test_stances_decide_targets() {
+		let data = CombatData::parse(DATA).unwrap();
+		let mut world = World::new();
+		world.players.add(Player::new(PlayerId(0), TeamId(0)));
+		world.players.add(Player::new(PlayerId(1), TeamId(0)));
+		world.players.add(Player::new(PlayerId(2), TeamId(1)));
+		world.players.add(Player::new(PlayerId(3), TeamId(2)));
+		world.players.set_stance(PlayerId(0), PlayerId(3), Stance::Neutral);
+
+		let shooter = spawn_armed(&mut world, &data, PlayerId(0), vec3(0.0, 0.0, 0.0), Some("rifle"));
+		spawn_armed(&mut world, &data, PlayerId(1), vec3(1.0, 0.0, 0.0), None);
+		spawn_armed(&mut world, &data, PlayerId(3), vec3(2.0, 0.0, 0.0), None);
+		let enemy = spawn_armed(&mut world, &data, PlayerId(2), vec3(3.0, 0.0, 0.0), None);
+		update_spatial_hash(&mut world);
+
+		// the ally and the neutral player are closer, only the enemy is shot at
+		assert_eq!(find_target(&world, shooter, 5.0), Some(enemy));
+	}
+
+}
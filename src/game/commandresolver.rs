@@ -0,0 +1,193 @@
+use ::game::{World, EntityId, PlayerId, Relationship, Order, Command, Formation, can_attack};
+use ::math::*;
+
+/// What the cursor points at when a command is given
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CommandTarget {
+	Ground(Vector3),
+	Entity { id: EntityId, relationship: Relationship },
+}
+
+/// Forces one kind of order whatever is under the cursor, given with a hotkey before the click
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CommandOverride {
+	/// Walk there, even onto enemies
+	Move,
+	/// Attack the entity, even a friendly one, or attack-move to the ground
+	Attack,
+	Patrol,
+	/// Drive there and let the passengers out, only transports carrying someone take it
+	Unload,
+}
+
+/// Works out what every selected unit does on a command click at the target: a right-click, or a left click after an override hotkey.
+/// Units getting the same order are grouped into one command, so they share a formation.
+pub fn resolve_command(world: &World, player: PlayerId, units: &[EntityId], target: CommandTarget, override_order: Option<CommandOverride>, queued: bool, formation: Formation) -> Vec<Command> {
+	let mut commands: Vec<Command> = Vec::new();
+
+	for &unit in units {
+		let order = match resolve_order(world, unit, target, override_order) {
+			Some(order) => order,
+			None => continue,
+		};
+
+		match commands.iter().position(|command| command.order == order) {
+			Some(index) => commands[index].units.push(unit),
+			None => commands.push(Command {
+				player: player,
+				units: vec![unit],
+				order: order,
+				queued: queued,
+				formation: formation,
+			}),
+		}
+	}
+
+	commands
+}
+
+/// Order the unit gets on a command click at the target, None for units that can't be ordered
+pub fn resolve_order(world: &World, unit: EntityId, target: CommandTarget, override_order: Option<CommandOverride>) -> Option<Order> {
+	if !world.order_queues.contains(unit) {
+		return None;
+	}
+
+	let (entity, relationship, position) = match target {
+		CommandTarget::Ground(position) => (None, Relationship::Neutral, position),
+		CommandTarget::Entity { id, relationship } => match world.poses.get(id) {
+			Some(pose) => (if id != unit { Some(id) } else { None }, relationship, pose.position),
+			None => return None,
+		},
+	};
+
+	let move_order = Order::Move { target: position, group_goal: None };
+
+	let entity = match (entity, override_order) {
+		(_, Some(CommandOverride::Move)) => return Some(move_order),
+		(_, Some(CommandOverride::Patrol)) => return Some(Order::Patrol { target: position, group_goal: None }),
+		(_, Some(CommandOverride::Unload)) if world.transports.get(unit).map_or(false, |transport| !transport.passengers.is_empty()) => {
+			return Some(Order::Unload { target: position, group_goal: None });
+		}
+		(_, Some(CommandOverride::Unload)) => return None,
+		(Some(entity), Some(CommandOverride::Attack)) if can_attack(world, unit, entity) => return Some(Order::Attack(entity)),
+		(_, Some(CommandOverride::Attack)) => return Some(Order::AttackMove { target: position, group_goal: None }),
+		(Some(entity), None) => entity,
+		(None, None) => return Some(move_order),
+	};
+
+	let is_worker = world.workers.contains(unit);
+	let is_friendly = relationship == Relationship::Own || relationship == Relationship::Ally;
+
+	if relationship == Relationship::Enemy && can_attack(world, unit, entity) {
+		return Some(Order::Attack(entity));
+	}
+
+	if is_worker && world.resource_nodes.get(entity).map_or(false, |node| node.amount > 0) {
+		return Some(Order::Gather(entity));
+	}
+
+	if is_friendly && world.movements.contains(unit) && world.transports.get(entity).map_or(false, |transport| !transport.is_full()) {
+		return Some(Order::Enter(entity));
+	}
+
+	if is_friendly && is_worker && world.buildings.contains(entity) && world.healths.get(entity).map_or(false, |health| health.current < health.max) {
+		return Some(Order::Repair(entity));
+	}
+
+	Some(move_order)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Transport, relationship};
+	use ::game::test_util::{spawn_soldier, spawn_worker, spawn_building, spawn_node, gold, rifle};
+
+	fn target(world: &World, id: EntityId) -> CommandTarget {
+		CommandTarget::Entity { id: id, relationship: relationship(world, PlayerId(0), id) }
+	}
+
+	#[test]
+	fn test_context_orders() {
+		let mut world = World::new();
+		let soldier = spawn_soldier(&mut world, PlayerId(0), vec3(0.0, 0.0, 0.0), rifle());
+		let worker = spawn_worker(&mut world, PlayerId(0), vec3(1.0, 0.0, 0.0));
+		let enemy = spawn_soldier(&mut world, PlayerId(1), vec3(10.0, 0.0, 0.0), rifle());
+
+		let node = spawn_node(&mut world, gold(), 100, vec3(5.0, 0.0, 5.0));
+
+		let transport = spawn_soldier(&mut world, PlayerId(0), vec3(5.0, 0.0, -5.0), rifle());
+		world.transports.insert(transport, Transport::new(4));
+
+		let building = spawn_building(&mut world, PlayerId(0), "barracks", vec3(-5.0, 0.0, 5.0));
+		world.healths.get_mut(building).unwrap().current = 50.0;
+
+		let ground = vec3(3.0, 0.0, 3.0);
+		let move_to = |position: Vector3| Some(Order::Move { target: position, group_goal: None });
+
+		assert_eq!(resolve_order(&world, soldier, CommandTarget::Ground(ground), None), move_to(ground));
+		assert_eq!(resolve_order(&world, soldier, target(&world, enemy), None), Some(Order::Attack(enemy)));
+		// unarmed units walk up instead
+		assert_eq!(resolve_order(&world, worker, target(&world, enemy), None), move_to(vec3(10.0, 0.0, 0.0)));
+
+		assert_eq!(resolve_order(&world, worker, target(&world, node), None), Some(Order::Gather(node)));
+		assert_eq!(resolve_order(&world, soldier, target(&world, node), None), move_to(vec3(5.0, 0.0, 5.0)));
+
+		assert_eq!(resolve_order(&world, soldier, target(&world, transport), None), Some(Order::Enter(transport)));
+		assert_eq!(resolve_order(&world, transport, target(&world, transport), None), move_to(vec3(5.0, 0.0, -5.0)));
+
+		assert_eq!(resolve_order(&world, worker, target(&world, building), None), Some(Order::Repair(building)));
+		assert_eq!(resolve_order(&world, soldier, target(&world, building), None), move_to(vec3(-5.0, 0.0, 5.0)));
+		world.healths.get_mut(building).unwrap().current = 100.0;
+		assert_eq!(resolve_order(&world, worker, target(&world, building), None), move_to(vec3(-5.0, 0.0, 5.0)));
+
+		// entities nobody can order
+		assert_eq!(resolve_order(&world, node, CommandTarget::Ground(ground), None), None);
+	}
+
+	#[test]
+	fn test_overrides() {
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(0.0, 0.0, 0.0), rifle());
+		let friend = spawn_soldier(&mut world, PlayerId(0), vec3(2.0, 0.0, 0.0), rifle());
+		let enemy = spawn_soldier(&mut world, PlayerId(1), vec3(10.0, 0.0, 0.0), rifle());
+		let ground = vec3(3.0, 0.0, 3.0);
+
+		assert_eq!(resolve_order(&world, unit, CommandTarget::Ground(ground), Some(CommandOverride::Attack)),
+			Some(Order::AttackMove { target: ground, group_goal: None }));
+		assert_eq!(resolve_order(&world, unit, target(&world, friend), Some(CommandOverride::Attack)), Some(Order::Attack(friend)));
+		assert_eq!(resolve_order(&world, unit, target(&world, enemy), Some(CommandOverride::Move)),
+			Some(Order::Move { target: vec3(10.0, 0.0, 0.0), group_goal: None }));
+		assert_eq!(resolve_order(&world, unit, CommandTarget::Ground(ground), Some(CommandOverride::Patrol)),
+			Some(Order::Patrol { target: ground, group_goal: None }));
+
+		// only loaded transports unload, the rest of the selection sits it out
+		world.transports.insert(friend, Transport::new(2));
+		assert_eq!(resolve_order(&world, friend, CommandTarget::Ground(ground), Some(CommandOverride::Unload)), None);
+		world.transports.get_mut(friend).unwrap().passengers.push(unit);
+		assert_eq!(resolve_order(&world, friend, CommandTarget::Ground(ground), Some(CommandOverride::Unload)),
+			Some(Order::Unload { target: ground, group_goal: None }));
+		assert_eq!(resolve_order(&world, enemy, CommandTarget::Ground(ground), Some(CommandOverride::Unload)), None);
+	}
+
+	#[test]
+	fn test_commands_grouped_by_order() {
+		let mut world = World::new();
+		let soldiers: Vec<EntityId> = (0..3).map(|i| spawn_soldier(&mut world, PlayerId(0), vec3(i as Real, 0.0, 0.0), rifle())).collect();
+		let workers: Vec<EntityId> = (0..2).map(|i| spawn_worker(&mut world, PlayerId(0), vec3(i as Real, 0.0, 2.0))).collect();
+
+		let node = spawn_node(&mut world, gold(), 100, vec3(8.0, 0.0, 8.0));
+
+		let units: Vec<EntityId> = soldiers.iter().chain(workers.iter()).cloned().collect();
+		let commands = resolve_command(&world, PlayerId(0), &units, target(&world, node), None, true, Formation::Line);
+
+		assert_eq!(commands.len(), 2);
+		assert_eq!(commands[0].units, soldiers);
+		assert_eq!(commands[0].order, Order::Move { target: vec3(8.0, 0.0, 8.0), group_goal: None });
+		assert_eq!(commands[1].units, workers);
+		assert_eq!(commands[1].order, Order::Gather(node));
+		assert!(commands.iter().all(|command| command.queued && command.formation == Formation::Line));
+	}
+
+}
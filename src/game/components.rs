@@ -0,0 +1,281 @@
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use ::assets::Asset;
+use ::game::{PlayerId, EntityId, ResourceDef, ProductionItem};
+use ::gfx::resources::Mesh;
+use ::gfx::scene::MeshInstanceHandle;
+use ::navigation::{MovementClass, FlowField, Cell};
+use ::math::*;
+
+/// Transform of an entity on the map: position and facing around the vertical axis
+#[derive(Copy, Clone, Debug)]
+pub struct Pose {
+	pub position: Vector3,
+	pub heading: Rad<Real>,
+}
+
+impl Pose {
+
+	pub fn new(position: Vector3) -> Self {
+		Pose {
+			position: position,
+			heading: Rad(0.0),
+		}
+	}
+
+	pub fn spatial(&self) -> Spatial {
+		Spatial {
+			position: self.position,
+			rotation: Quaternion::from_angle_y(self.heading),
+		}
+	}
+
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Owner(pub PlayerId);
+
+#[derive(Copy, Clone, Debug)]
+pub struct Health {
+	pub current: Real,
+	pub max: Real,
+}
+
+impl Health {
+
+	pub fn new(max: Real) -> Self {
+		Health {
+			current: max,
+			max: max,
+		}
+	}
+
+	pub fn is_dead(&self) -> bool {
+		self.current <= 0.0
+	}
+
+}
+
+#[derive(Clone)]
+pub struct Movement {
+	/// Units per second
+	pub max_speed: Real,
+	/// Radians per second
+	pub turn_speed: Real,
+	/// Radius of the body other units steer around
+	pub radius: Real,
+	pub class: MovementClass,
+	pub velocity: Vector3,
+	/// Point the entity walks straight to
+	pub destination: Option<Vector3>,
+	/// Points to walk to after the destination is reached
+	pub waypoints: Vec<Vector3>,
+	/// Shared field leading to the destination, when moving with a big group
+	pub flow_field: Option<Rc<FlowField>>,
+	/// Where the unit stopped last, it walks back there when pushed away
+	pub rest_position: Option<Vector3>,
+	/// Seconds the unit has been held up by others close to the destination
+	pub blocked_time: Real,
+}
+
+impl Movement {
+
+	pub fn new(max_speed: Real) -> Self {
+		Movement {
+			max_speed: max_speed,
+			turn_speed: 2.0 * ::std::f32::consts::PI,
+			radius: 0.5,
+			class: MovementClass::Infantry,
+			velocity: vec3(0.0, 0.0, 0.0),
+			destination: None,
+			waypoints: Vec::new(),
+			flow_field: None,
+			rest_position: None,
+			blocked_time: 0.0,
+		}
+	}
+
+}
+
+/// Box around the entity used for picking it with the mouse
+#[derive(Copy, Clone, Debug)]
+pub struct Selectable {
+	pub radius: Real,
+	pub height: Real,
+}
+
+impl Selectable {
+
+	pub fn new(radius: Real, height: Real) -> Self {
+		Selectable {
+			radius: radius,
+			height: height,
+		}
+	}
+
+	pub fn bounds(&self, position: Vector3) -> AABB3 {
+		AABB3 {
+			min: position - vec3(self.radius, 0.0, self.radius),
+			max: position + vec3(self.radius, self.height, self.radius),
+		}
+	}
+
+}
+
+/// Mesh the entity is drawn with. The mesh instance is created and
+/// updated by `sync_graphics_scene`.
+pub struct Renderable {
+	pub mesh: Asset<Mesh>,
+	pub instance: Option<MeshInstanceHandle>,
+}
+
+impl Renderable {
+
+	pub fn new(mesh: Asset<Mesh>) -> Self {
+		Renderable {
+			mesh: mesh,
+			instance: None,
+		}
+	}
+
+}
+
+/// Unit that gathers resources and repairs buildings
+#[derive(Clone, Debug)]
+pub struct Worker {
+	/// Resources carried to a drop-off
+	pub cargo: Option<Cargo>,
+	/// Seconds spent harvesting the load being gathered
+	pub harvest_time: Real,
+}
+
+impl Worker {
+
+	pub fn new() -> Self {
+		Worker {
+			cargo: None,
+			harvest_time: 0.0,
+		}
+	}
+
+}
+
+#[derive(Clone, Debug)]
+pub struct Cargo {
+	pub resource: Rc<ResourceDef>,
+	pub amount: u32,
+}
+
+/// Resource deposit workers gather from, removed once depleted
+#[derive(Clone, Debug)]
+pub struct ResourceNode {
+	pub resource: Rc<ResourceDef>,
+	pub amount: u32,
+}
+
+impl ResourceNode {
+
+	pub fn new(resource: Rc<ResourceDef>, amount: u32) -> Self {
+		ResourceNode {
+			resource: resource,
+			amount: amount,
+		}
+	}
+
+}
+
+/// Building workers bring gathered resources to
+#[derive(Copy, Clone, Debug)]
+pub struct DropOff;
+
+/// Unit other units can get into. Passengers are taken off the map
+/// until unloaded and die with the transport.
+#[derive(Clone, Debug)]
+pub struct Transport {
+	pub capacity: usize,
+	pub passengers: Vec<EntityId>,
+}
+
+impl Transport {
+
+	pub fn new(capacity: usize) -> Self {
+		Transport {
+			capacity: capacity,
+			passengers: Vec::new(),
+		}
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.passengers.len() >= self.capacity
+	}
+
+}
+
+/// Structure that doesn't move and can be repaired by workers
+#[derive(Clone, Debug)]
+pub struct Building {
+	/// Name in the building data, prerequisites name it this way
+	pub kind: String,
+}
+
+impl Building {
+
+	pub fn new(kind: &str) -> Self {
+		Building {
+			kind: kind.to_string(),
+		}
+	}
+
+}
+
+/// Navigation grid cells the entity takes, units walk around them
+#[derive(Clone, Debug)]
+pub struct Obstacle {
+	pub cells: Vec<Cell>,
+}
+
+/// Building that trains units and researches upgrades the production data lists for its kind
+#[derive(Clone, Debug)]
+pub struct Producer {
+	/// Items waiting to be made, the first one is in progress
+	pub queue: VecDeque<ProductionItem>,
+	/// Seconds spent on the first item
+	pub progress: Real,
+	/// Trained units walk there
+	pub rally_point: Option<Vector3>,
+	/// How far from the center trained units come out, clear of the footprint
+	pub exit_distance: Real,
+}
+
+impl Producer {
+
+	pub fn new(exit_distance: Real) -> Self {
+		Producer {
+			queue: VecDeque::new(),
+			progress: 0.0,
+			rally_point: None,
+			exit_distance: exit_distance,
+		}
+	}
+
+}
+
+/// Reveals the map around the entity for its owner
+#[derive(Copy, Clone, Debug)]
+pub struct Vision {
+	pub radius: Real,
+	/// Height above the ground the entity looks from, higher eyes see over more hills
+	pub eye_height: Real,
+}
+
+impl Vision {
+
+	pub fn new(radius: Real, eye_height: Real) -> Self {
+		Vision {
+			radius: radius,
+			eye_height: eye_height,
+		}
+	}
+
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use toml;
+
+use ::math::*;
+
+/// Failure to read a game data file
+#[derive(Debug)]
+pub enum DataError {
+	Io(io::Error),
+	Format(String),
+}
+
+impl From<io::Error> for DataError {
+	fn from(error: io::Error) -> Self {
+		DataError::Io(error)
+	}
+}
+
+impl fmt::Display for DataError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			DataError::Io(ref error) => write!(f, "{}", error),
+			DataError::Format(ref message) => write!(f, "{}", message),
+		}
+	}
+}
+
+pub fn parse_data(text: &str) -> Result<toml::Value, DataError> {
+	text.parse::<toml::Value>().map_err(|error| DataError::Format(format!("{}", error)))
+}
+
+pub fn load_data(path: &Path) -> Result<toml::Value, DataError> {
+	let mut text = String::new();
+	File::open(path)?.read_to_string(&mut text)?;
+	parse_data(&text)
+}
+
+/// Entries of the table under the key, an empty list when there is no such table
+pub fn data_entries<'a>(value: &'a toml::Value, key: &str) -> Result<Vec<(&'a String, &'a toml::Value)>, DataError> {
+	match value.get(key) {
+		Some(&toml::Value::Table(ref table)) => Ok(table.iter().collect()),
+		Some(_) => Err(DataError::Format(format!("'{}' has to be a table", key))),
+		None => Ok(Vec::new()),
+	}
+}
+
+/// Number under the key, integers are accepted too
+pub fn data_real(value: &toml::Value, key: &str, context: &str) -> Result<Real, DataError> {
+	match value.get(key) {
+		Some(&toml::Value::Float(number)) => Ok(number as Real),
+		Some(&toml::Value::Integer(number)) => Ok(number as Real),
+		Some(_) => Err(DataError::Format(format!("'{}' of {} has to be a number", key, context))),
+		None => Err(DataError::Format(format!("{} has no '{}'", context, key))),
+	}
+}
+
+/// Number above zero under the key, like a distance or a duration
+pub fn data_positive(value: &toml::Value, key: &str, context: &str) -> Result<Real, DataError> {
+	match data_real(value, key, context)? {
+		number if number > 0.0 => Ok(number),
+		_ => Err(DataError::Format(format!("'{}' of {} has to be above zero", key, context))),
+	}
+}
+
+/// Whole non-negative number under the key, like an amount of resources
+pub fn data_amount(value: &toml::Value, key: &str, context: &str) -> Result<u32, DataError> {
+	match value.get(key) {
+		Some(&toml::Value::Integer(number)) if number >= 0 && number <= ::std::u32::MAX as i64 => Ok(number as u32),
+		Some(_) => Err(DataError::Format(format!("'{}' of {} has to be a whole non-negative number", key, context))),
+		None => Err(DataError::Format(format!("{} has no '{}'", context, key))),
+	}
+}
+
+pub fn data_str<'a>(value: &'a toml::Value, key: &str, context: &str) -> Result<&'a str, DataError> {
+	match value.get(key) {
+		Some(&toml::Value::String(ref text)) => Ok(text),
+		Some(_) => Err(DataError::Format(format!("'{}' of {} has to be a string", key, context))),
+		None => Err(DataError::Format(format!("{} has no '{}'", context, key))),
+	}
+}
+
+/// List of strings under the key, an empty list when missing
+pub fn data_strings<'a>(value: &'a toml::Value, key: &str, context: &str) -> Result<Vec<&'a str>, DataError> {
+	match value.get(key) {
+		Some(&toml::Value::Array(ref items)) => items.iter()
+			.map(|item| item.as_str().ok_or_else(|| DataError::Format(format!("'{}' of {} has to be a list of strings", key, context))))
+			.collect(),
+		Some(_) => Err(DataError::Format(format!("'{}' of {} has to be a list of strings", key, context))),
+		None => Ok(Vec::new()),
+	}
+}
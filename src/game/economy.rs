@@ -0,0 +1,461 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::rc::Rc;
+use toml;
+
+use ::game::{World, EntityId, Cargo};
+use ::game::{DataError, parse_data, load_data, data_entries, data_real, data_amount};
+use ::math::*;
+
+/// Income statistics count deliveries over this many seconds
+pub const INCOME_WINDOW: Real = 60.0;
+
+/// Amounts of resources by name, like the price of a unit
+pub type Cost = BTreeMap<String, u32>;
+
+/// Kind of resource players gather
+#[derive(Clone, PartialEq, Debug)]
+pub struct ResourceDef {
+	pub name: String,
+	/// Seconds a worker spends at the deposit for a load
+	pub gather_time: Real,
+	/// Most a worker carries in one trip
+	pub carry_amount: u32,
+}
+
+impl ResourceDef {
+
+	fn from_toml(name: &str, value: &toml::Value) -> Result<ResourceDef, DataError> {
+		let context = format!("resource '{}'", name);
+
+		Ok(ResourceDef {
+			name: name.to_string(),
+			gather_time: data_real(value, "gather_time", &context)?,
+			carry_amount: data_amount(value, "carry_amount", &context)?,
+		})
+	}
+
+}
+
+/// Resources described in the economy data file
+pub struct EconomyData {
+	resources: BTreeMap<String, Rc<ResourceDef>>,
+}
+
+impl EconomyData {
+
+	pub fn empty() -> Self {
+		EconomyData {
+			resources: BTreeMap::new(),
+		}
+	}
+
+	pub fn parse(text: &str) -> Result<Self, DataError> {
+		EconomyData::from_toml(&parse_data(text)?)
+	}
+
+	pub fn load(path: &Path) -> Result<Self, DataError> {
+		EconomyData::from_toml(&load_data(path)?)
+	}
+
+	fn from_toml(value: &toml::Value) -> Result<Self, DataError> {
+		let mut data = EconomyData::empty();
+
+		for (name, resource) in data_entries(value, "resources")? {
+			data.resources.insert(name.clone(), Rc::new(ResourceDef::from_toml(name, resource)?));
+		}
+
+		Ok(data)
+	}
+
+	pub fn resource(&self, name: &str) -> Option<Rc<ResourceDef>> {
+		self.resources.get(name).cloned()
+	}
+
+}
+
+#[derive(Clone, Debug)]
+struct Delivery {
+	time: Real,
+	resource: String,
+	amount: u32,
+}
+
+/// Resources a player has, and how they came in
+#[derive(Clone, Debug)]
+pub struct Stockpile {
+	amounts: BTreeMap<String, u32>,
+	/// Everything workers ever delivered
+	gathered: BTreeMap<String, u32>,
+	/// Deliveries within the income window, oldest first
+	recent: VecDeque<Delivery>,
+	/// Seconds since the stockpile was created
+	time: Real,
+}
+
+impl Stockpile {
+
+	pub fn new() -> Self {
+		Stockpile {
+			amounts: BTreeMap::new(),
+			gathered: BTreeMap::new(),
+			recent: VecDeque::new(),
+			time: 0.0,
+		}
+	}
+
+	pub fn amount(&self, resource: &str) -> u32 {
+		self.amounts.get(resource).cloned().unwrap_or(0)
+	}
+
+	pub fn amounts(&self) -> &BTreeMap<String, u32> {
+		&self.amounts
+	}
+
+	/// Adds resources that weren't gathered, like starting resources, without counting them as income
+	pub fn add(&mut self, resource: &str, amount: u32) {
+		*self.amounts.entry(resource.to_string()).or_insert(0) += amount;
+	}
+
+	/// Adds resources a worker delivered
+	pub fn deposit(&mut self, resource: &str, amount: u32) {
+		self.add(resource, amount);
+		*self.gathered.entry(resource.to_string()).or_insert(0) += amount;
+		self.recent.push_back(Delivery {
+			time: self.time,
+			resource: resource.to_string(),
+			amount: amount,
+		});
+	}
+
+	pub fn can_afford(&self, cost: &Cost) -> bool {
+		cost.iter().all(|(resource, &amount)| self.amount(resource) >= amount)
+	}
+
+	/// Takes the cost out of the stockpile, nothing is taken when there isn't enough of every resource
+	pub fn spend(&mut self, cost: &Cost) -> bool {
+		if !self.can_afford(cost) {
+			return false;
+		}
+		for (resource, &amount) in cost.iter() {
+			*self.amounts.entry(resource.clone()).or_insert(0) -= amount;
+		}
+		true
+	}
+
+	/// All of the resource ever delivered by workers
+	pub fn gathered(&self, resource: &str) -> u32 {
+		self.gathered.get(resource).cloned().unwrap_or(0)
+	}
+
+	/// Resource delivered within the last `INCOME_WINDOW` seconds
+	pub fn income(&self, resource: &str) -> u32 {
+		self.recent.iter()
+			.filter(|delivery| delivery.resource == resource)
+			.map(|delivery| delivery.amount)
+			.sum()
+	}
+
+	fn advance(&mut self, dt: Real) {
+		self.time += dt;
+		while self.recent.front().map_or(false, |delivery| delivery.time <= self.time - INCOME_WINDOW) {
+			self.recent.pop_front();
+		}
+	}
+
+}
+
+/// Advances income statistics, called once per tick
+pub fn update_economy(world: &mut World, dt: Real) {
+	for player in world.players.iter_mut() {
+		player.stockpile.advance(dt);
+	}
+}
+
+/// Works the resource node next to the worker for a tick. Returns true once the worker
+/// carries a load, the node is removed when that empties it.
+pub fn harvest(world: &mut World, id: EntityId, node: EntityId, dt: Real) -> bool {
+	let resource = match world.resource_nodes.get(node) {
+		Some(node) if node.amount > 0 => node.resource.clone(),
+		_ => return false,
+	};
+
+	{
+		let worker = match world.workers.get_mut(id) {
+			Some(worker) => worker,
+			None => return false,
+		};
+
+		// switching to another resource drops what the worker carries
+		if worker.cargo.as_ref().map_or(false, |cargo| cargo.resource.name != resource.name) {
+			worker.cargo = None;
+		}
+
+		worker.harvest_time += dt;
+		if worker.harvest_time < resource.gather_time {
+			return false;
+		}
+		worker.harvest_time = 0.0;
+	}
+
+	let taken = {
+		let node = world.resource_nodes.get_mut(node).unwrap();
+		let carried = world.workers.get(id).unwrap().cargo.as_ref().map_or(0, |cargo| cargo.amount);
+		let taken = resource.carry_amount.saturating_sub(carried).min(node.amount);
+		node.amount -= taken;
+		taken
+	};
+
+	let worker = world.workers.get_mut(id).unwrap();
+	let carried = worker.cargo.as_ref().map_or(0, |cargo| cargo.amount);
+	worker.cargo = Some(Cargo {
+		resource: resource,
+		amount: carried + taken,
+	});
+
+	if world.resource_nodes.get(node).map_or(false, |node| node.amount == 0) {
+		world.destroy(node);
+	}
+
+	true
+}
+
+/// Whether the worker has anything to bring to a drop-off
+pub fn is_carrying(world: &World, id: EntityId) -> bool {
+	world.workers.get(id).map_or(false, |worker| worker.cargo.is_some())
+}
+
+/// Closest drop-off of the worker's owner, ties go to the older entity
+pub fn nearest_drop_off(world: &World, id: EntityId) -> Option<EntityId> {
+	let (owner, position) = match (world.owners.get(id), world.poses.get(id)) {
+		(Some(owner), Some(pose)) => (*owner, pose.position),
+		_ => return None,
+	};
+
+	let mut nearest: Option<(EntityId, Real)> = None;
+	for (drop_off, _) in world.drop_offs.iter() {
+		if world.owners.get(drop_off) != Some(&owner) {
+			continue;
+		}
+		let distance = match world.poses.get(drop_off) {
+			Some(pose) => (vec2(pose.position.x, pose.position.z) - vec2(position.x, position.z)).magnitude2(),
+			None => continue,
+		};
+		if nearest.map_or(true, |(_, nearest_distance)| distance < nearest_distance) {
+			nearest = Some((drop_off, distance));
+		}
+	}
+
+	nearest.map(|(drop_off, _)| drop_off)
+}
+
+/// Hands what the worker carries over to the stockpile of its owner.
+/// False when there is nothing to hand over or the owner isn't a player in the game, the worker keeps the cargo then.
+pub fn deliver_cargo(world: &mut World, id: EntityId) -> bool {
+	let owner = match world.owners.get(id) {
+		Some(owner) => owner.0,
+		None => return false,
+	};
+	if world.stockpile(owner).is_none() {
+		return false;
+	}
+
+	let cargo = match world.workers.get_mut(id).and_then(|worker| worker.cargo.take()) {
+		Some(cargo) => cargo,
+		None => return false,
+	};
+
+	world.stockpile_mut(owner).unwrap().deposit(&cargo.resource.name, cargo.amount);
+	true
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{DropOff, Owner, PlayerId, Order, Command, Formation, issue_command};
+	use ::game::test_util::{flat_map, add_players, spawn, spawn_worker, spawn_node, simulate};
+
+	const DATA: &str = r#"
+		[resources.gold]
+		gather_time = 2.0
+		carry_amount = 10
+
+		[resources.wood]
+		gather_time = 1
+		carry_amount = 5
+	"#;
+
+	fn gather_command(workers: Vec<EntityId>, node: EntityId) -> Command {
+		Command {
+			player: PlayerId(0),
+			units: workers,
+			order: Order::Gather(node),
+			queued: false,
+			formation: Formation::Box,
+		}
+	}
+
+	#[test]
+	fn test_parse_data() {
+		let data = EconomyData::parse(DATA).unwrap();
+
+		let gold = data.resource("gold").unwrap();
+		assert_eq!(gold.gather_time, 2.0);
+		assert_eq!(gold.carry_amount, 10);
+		assert_eq!(data.resource("wood").unwrap().gather_time, 1.0);
+		assert!(data.resource("stone").is_none());
+
+		assert!(EconomyData::parse("[resources.gold]\ngather_time = 1.0\ncarry_amount = -5").is_err());
+		assert!(EconomyData::parse("[resources.gold]\ncarry_amount = 5").is_err());
+	}
+
+	#[test]
+	fn test_shipped_data() {
+		let data = EconomyData::load(Path::new("data/economy.toml")).unwrap();
+		assert!(data.resource("gold").is_some());
+	}
+
+	#[test]
+	fn test_gather_trips() {
+		let data = EconomyData::parse(DATA).unwrap();
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		add_players(&mut world, 1);
+		let drop_off = spawn(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0));
+		world.drop_offs.insert(drop_off, DropOff);
+		let node = spawn_node(&mut world, data.resource("gold").unwrap(), 25, vec3(14.0, 0.0, 4.0));
+		let worker = spawn_worker(&mut world, PlayerId(0), vec3(6.0, 0.0, 6.0));
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &gather_command(vec![worker], node));
+
+		// walking there and harvesting takes a few seconds
+		simulate(&mut world, &mut navigation, &heightfield, 40);
+		assert_eq!(world.order_queues.get(worker).unwrap().state, ::game::OrderState::Working);
+		assert_eq!(world.resource_nodes.get(node).unwrap().amount, 25);
+
+		// a load takes two seconds to harvest
+		simulate(&mut world, &mut navigation, &heightfield, 40);
+		assert_eq!(world.resource_nodes.get(node).unwrap().amount, 15);
+		assert!(is_carrying(&world, worker));
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("gold"), 0);
+
+		simulate(&mut world, &mut navigation, &heightfield, 40);
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("gold"), 10);
+		assert!(!is_carrying(&world, worker));
+		assert_eq!(nearest_drop_off(&world, worker), Some(drop_off));
+
+		// the last load empties the node, the worker brings it in and stops
+		simulate(&mut world, &mut navigation, &heightfield, 600);
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("gold"), 25);
+		assert!(!world.is_alive(node));
+		assert!(world.order_queues.get(worker).unwrap().is_empty());
+
+		let stockpile = world.stockpile(PlayerId(0)).unwrap();
+		assert_eq!(stockpile.gathered("gold"), 25);
+		assert_eq!(stockpile.income("gold"), 25);
+		assert_eq!(stockpile.income("wood"), 0);
+	}
+
+	#[test]
+	fn test_income_window() {
+		let mut stockpile = Stockpile::new();
+		stockpile.add("gold", 100);
+		stockpile.deposit("gold", 10);
+		assert_eq!(stockpile.amount("gold"), 110);
+		assert_eq!(stockpile.gathered("gold"), 10);
+
+		stockpile.advance(INCOME_WINDOW / 2.0);
+		stockpile.deposit("gold", 5);
+		assert_eq!(stockpile.income("gold"), 15);
+
+		stockpile.advance(INCOME_WINDOW / 2.0);
+		assert_eq!(stockpile.income("gold"), 5);
+		assert_eq!(stockpile.gathered("gold"), 15);
+	}
+
+	#[test]
+	fn test_spend() {
+		let mut stockpile = Stockpile::new();
+		stockpile.add("gold", 100);
+
+		let mut cost = Cost::new();
+		cost.insert("gold".to_string(), 60);
+		cost.insert("wood".to_string(), 20);
+
+		// all or nothing
+		assert!(!stockpile.spend(&cost));
+		assert_eq!(stockpile.amount("gold"), 100);
+
+		stockpile.add("wood", 20);
+		assert!(stockpile.spend(&cost));
+		assert_eq!(stockpile.amount("gold"), 40);
+		assert_eq!(stockpile.amount("wood"), 0);
+		assert!(!stockpile.can_afford(&cost));
+	}
+
+	#[test]
+	fn test_no_drop_off() {
+		let data = EconomyData::parse(DATA).unwrap();
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		add_players(&mut world, 1);
+		let node = spawn_node(&mut world, data.resource("wood").unwrap(), 100, vec3(8.0, 0.0, 4.0));
+		let worker = spawn_worker(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0));
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &gather_command(vec![worker], node));
+		simulate(&mut world, &mut navigation, &heightfield, 100);
+
+		// nowhere to bring the load, the worker keeps it
+		assert_eq!(world.workers.get(worker).unwrap().cargo.as_ref().map(|cargo| cargo.amount), Some(5));
+		assert!(world.order_queues.get(worker).unwrap().is_empty());
+		assert_eq!(world.resource_nodes.get(node).unwrap().amount, 95);
+	}
+
+	#[test]
+	fn test_unknown_owner() {
+		let data = EconomyData::parse(DATA).unwrap();
+		let mut world = World::new();
+		add_players(&mut world, 1);
+		let worker = spawn_worker(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0));
+		world.owners.insert(worker, Owner(PlayerId(5)));
+		world.workers.get_mut(worker).unwrap().cargo = Some(Cargo { resource: data.resource("wood").unwrap(), amount: 5 });
+
+		// no stockpile to hand it to, the worker keeps the load
+		assert!(!deliver_cargo(&mut world, worker));
+		assert!(is_carrying(&world, worker));
+		assert!(world.stockpile(PlayerId(5)).is_none());
+
+		world.owners.insert(worker, Owner(PlayerId(0)));
+		assert!(deliver_cargo(&mut world, worker));
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("wood"), 5);
+	}
+
+	#[test]
+	fn test_deterministic() {
+		let data = EconomyData::parse(DATA).unwrap();
+
+		let run = || {
+			let (heightfield, mut navigation) = flat_map(32);
+			let mut world = World::new();
+			add_players(&mut world, 1);
+			let drop_off = spawn(&mut world, PlayerId(0), vec3(16.0, 0.0, 16.0));
+			world.drop_offs.insert(drop_off, DropOff);
+			let gold = spawn_node(&mut world, data.resource("gold").unwrap(), 60, vec3(6.0, 0.0, 16.0));
+			let wood = spawn_node(&mut world, data.resource("wood").unwrap(), 60, vec3(26.0, 0.0, 16.0));
+			let gatherers: Vec<EntityId> = (0..4).map(|i| spawn_worker(&mut world, PlayerId(0), vec3(14.0 + i as Real, 0.0, 12.0))).collect();
+			let lumberjacks: Vec<EntityId> = (0..3).map(|i| spawn_worker(&mut world, PlayerId(0), vec3(14.0 + i as Real, 0.0, 20.0))).collect();
+			issue_command(&mut world, &navigation.grid, &heightfield, &gather_command(gatherers, gold));
+			issue_command(&mut world, &navigation.grid, &heightfield, &gather_command(lumberjacks, wood));
+			simulate(&mut world, &mut navigation, &heightfield, 400);
+
+			let positions: Vec<Vector3> = world.poses.iter().map(|(_, pose)| pose.position).collect();
+			(world.stockpile(PlayerId(0)).unwrap().amount("gold"), world.stockpile(PlayerId(0)).unwrap().amount("wood"), positions)
+		};
+
+		let (gold, wood, positions) = run();
+		assert!(gold > 0 && wood > 0);
+		assert_eq!((gold, wood, positions), run());
+	}
+
+}
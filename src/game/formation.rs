@@ -0,0 +1,358 @@
+use ::terrain::Heightfield;
+use ::navigation::{NavGrid, MovementClass};
+use ::math::*;
+
+/// Units in a line formation rank
+const LINE_WIDTH: usize = 16;
+
+/// Rings of samples searched around a slot on steep terrain
+const SLOT_SEARCH_RINGS: usize = 8;
+
+/// Shape a group takes at the end of a move order
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Formation {
+	/// Wide ranks facing the move direction
+	Line,
+	/// Square block
+	Box,
+	/// Arrow with the tip pointing in the move direction
+	Wedge,
+	/// Sparse block with irregular spacing
+	Loose,
+}
+
+impl Formation {
+
+	/// Name used in saved commands
+	pub fn name(&self) -> &'static str {
+		match *self {
+			Formation::Line => "line",
+			Formation::Box => "box",
+			Formation::Wedge => "wedge",
+			Formation::Loose => "loose",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Formation> {
+		Formation::all().iter().cloned().find(|formation| formation.name() == name)
+	}
+
+	pub fn all() -> &'static [Formation] {
+		&[Formation::Line, Formation::Box, Formation::Wedge, Formation::Loose]
+	}
+
+	/// Slot offsets around the formation center, `x` goes to the right
+	/// and `y` forward, slots are at least `spacing` apart
+	pub fn slot_offsets(&self, count: usize, spacing: Real) -> Vec<Vector2> {
+		let mut offsets = Vec::with_capacity(count);
+
+		match *self {
+			Formation::Line => grid_offsets(&mut offsets, count, LINE_WIDTH.min(count.max(1)), spacing),
+			Formation::Box => grid_offsets(&mut offsets, count, (count as Real).sqrt().ceil() as usize, spacing),
+			Formation::Wedge => {
+				let mut row = 0;
+				while offsets.len() < count {
+					// row n has 2n + 1 slots, each row further back
+					let row_size = (2 * row + 1).min(count - offsets.len());
+					for i in 0..row_size {
+						let side = ((i + 1) / 2) as Real * if i % 2 == 1 { 1.0 } else { -1.0 };
+						offsets.push(vec2(side * spacing, -(row as Real) * spacing));
+					}
+					row += 1;
+				}
+			}
+			Formation::Loose => {
+				grid_offsets(&mut offsets, count, (count as Real).sqrt().ceil() as usize, spacing * 2.0);
+				for (i, offset) in offsets.iter_mut().enumerate() {
+					*offset += jitter(i) * (spacing * 0.5);
+				}
+			}
+		}
+
+		// formation is centered on the goal
+		if count > 0 {
+			let center = offsets.iter().fold(vec2(0.0, 0.0), |sum, &offset| sum + offset) / (count as Real);
+			for offset in offsets.iter_mut() {
+				*offset -= center;
+			}
+		}
+
+		offsets
+	}
+
+	/// Slot positions on the XZ plane around the goal, facing along `direction`
+	pub fn slots(&self, count: usize, goal: Vector2, direction: Vector2, spacing: Real) -> Vec<Vector2> {
+		let forward = if direction.magnitude2() > 0.0 { direction.normalize() } else { vec2(0.0, 1.0) };
+		let right = vec2(-forward.y, forward.x);
+
+		self.slot_offsets(count, spacing).iter()
+			.map(|offset| goal + right * offset.x + forward * offset.y)
+			.collect()
+	}
+
+}
+
+/// Rows of `columns` slots going back from the front, last row centered
+fn grid_offsets(offsets: &mut Vec<Vector2>, count: usize, columns: usize, spacing: Real) {
+	let columns = columns.max(1);
+	for i in 0..count {
+		let row = i / columns;
+		let row_size = columns.min(count - row * columns);
+		let column = (i % columns) as Real - (row_size as Real - 1.0) * 0.5;
+		offsets.push(vec2(column * spacing, -(row as Real) * spacing));
+	}
+}
+
+/// Repeatable pseudo random offset in -1..1 for the slot
+fn jitter(index: usize) -> Vector2 {
+	let mut state = (index as u32).wrapping_mul(2654435761).wrapping_add(1013904223);
+	let mut next = || {
+		state ^= state << 13;
+		state ^= state >> 17;
+		state ^= state << 5;
+		(state & 0xffff) as Real / 32767.5 - 1.0
+	};
+	let x = next();
+	vec2(x, next())
+}
+
+/// Moves slots on slopes too steep for the class or on blocked cells to the nearest walkable
+/// point around them, slots outside of the terrain are brought inside.
+/// Moved slots keep half the spacing from the others, so they don't end up on top of each other.
+pub fn push_slots_off_slopes(slots: &mut [Vector2], grid: &NavGrid, heightfield: &Heightfield, class: MovementClass, spacing: Real) {
+	let max_slope = class.max_slope().0;
+	let is_walkable = |point: Vector2| {
+		heightfield.contains(point.x, point.y) && heightfield.slope_at(point.x, point.y).0 <= max_slope &&
+			grid.cell_at(point).map_or(false, |cell| grid.is_passable(cell, class))
+	};
+
+	for slot in slots.iter_mut() {
+		*slot = vec2(slot.x.max(0.0).min(heightfield.scale.x), slot.y.max(0.0).min(heightfield.scale.z));
+	}
+
+	// slots that stay where they are, the others are placed around them
+	let mut settled: Vec<bool> = slots.iter().map(|&slot| is_walkable(slot)).collect();
+	let clearance = spacing * 0.5;
+
+	for index in 0..slots.len() {
+		if settled[index] {
+			continue;
+		}
+
+		let center = slots[index];
+		'search: for ring in 1..(SLOT_SEARCH_RINGS + 1) {
+			let radius = ring as Real * spacing * 0.5;
+			let samples = ring * 8;
+			for i in 0..samples {
+				let angle = (i as Real) / (samples as Real) * 2.0 * ::std::f32::consts::PI;
+				let point = center + vec2(angle.cos(), angle.sin()) * radius;
+				let crowded = slots.iter().zip(settled.iter()).any(|(&other, &settled)| settled && (other - point).magnitude() < clearance);
+				if is_walkable(point) && !crowded {
+					slots[index] = point;
+					break 'search;
+				}
+			}
+		}
+		settled[index] = true;
+	}
+}
+
+/// Matches units to slots with the least total travel distance (Hungarian method).
+/// Returns the slot index for every unit, there has to be a slot per unit.
+pub fn assign_slots(positions: &[Vector2], slots: &[Vector2]) -> Vec<usize> {
+	let n = positions.len();
+	let m = slots.len();
+	assert!(n <= m, "less slots than units");
+
+	let infinity = ::std::f32::INFINITY;
+	let cost = |unit: usize, slot: usize| (positions[unit - 1] - slots[slot - 1]).magnitude();
+
+	// potentials and matching, 1-based with 0 as the dummy column
+	let mut unit_potentials = vec![0.0; n + 1];
+	let mut slot_potentials = vec![0.0; m + 1];
+	let mut slot_units = vec![0; m + 1];
+	let mut previous_slots = vec![0; m + 1];
+
+	for unit in 1..(n + 1) {
+		slot_units[0] = unit;
+		let mut slot = 0;
+		let mut min_values = vec![infinity; m + 1];
+		let mut used = vec![false; m + 1];
+
+		loop {
+			used[slot] = true;
+			let current_unit = slot_units[slot];
+			let mut delta = infinity;
+			let mut next_slot = 0;
+
+			for j in 1..(m + 1) {
+				if used[j] {
+					continue;
+				}
+				let reduced = cost(current_unit, j) - unit_potentials[current_unit] - slot_potentials[j];
+				if reduced < min_values[j] {
+					min_values[j] = reduced;
+					previous_slots[j] = slot;
+				}
+				if min_values[j] < delta {
+					delta = min_values[j];
+					next_slot = j;
+				}
+			}
+
+			for j in 0..(m + 1) {
+				if used[j] {
+					unit_potentials[slot_units[j]] += delta;
+					slot_potentials[j] -= delta;
+				} else {
+					min_values[j] -= delta;
+				}
+			}
+
+			slot = next_slot;
+			if slot_units[slot] == 0 {
+				break;
+			}
+		}
+
+		// flip the augmenting path
+		while slot != 0 {
+			let previous = previous_slots[slot];
+			slot_units[slot] = slot_units[previous];
+			slot = previous;
+		}
+	}
+
+	let mut assignment = vec![0; n];
+	for j in 1..(m + 1) {
+		if slot_units[j] != 0 {
+			assignment[slot_units[j] - 1] = j - 1;
+		}
+	}
+	assignment
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn total_distance(positions: &[Vector2], slots: &[Vector2], assignment: &[usize]) -> Real {
+		positions.iter().zip(assignment.iter()).map(|(&position, &slot)| (position - slots[slot]).magnitude()).sum()
+	}
+
+	fn permutations(items: Vec<usize>) -> Vec<Vec<usize>> {
+		if items.len() <= 1 {
+			return vec![items];
+		}
+		let mut result = Vec::new();
+		for i in 0..items.len() {
+			let mut rest = items.clone();
+			let first = rest.remove(i);
+			for mut permutation in permutations(rest) {
+				permutation.insert(0, first);
+				result.push(permutation);
+			}
+		}
+		result
+	}
+
+	#[test]
+	fn test_formation_shapes() {
+		let spacing = 2.0;
+
+		let line = Formation::Line.slot_offsets(5, spacing);
+		assert!(line.iter().all(|offset| offset.y.abs() < 0.001));
+		assert!((line[0].x - line[4].x).abs() > 7.9);
+
+		let box_slots = Formation::Box.slot_offsets(9, spacing);
+		let width = box_slots.iter().fold(0.0, |max: Real, offset| max.max(offset.x.abs()));
+		let depth = box_slots.iter().fold(0.0, |max: Real, offset| max.max(offset.y.abs()));
+		assert!((width - 2.0).abs() < 0.001 && (depth - 2.0).abs() < 0.001);
+
+		// wedge tip leads the formation
+		let wedge = Formation::Wedge.slot_offsets(7, spacing);
+		assert!(wedge.iter().skip(1).all(|offset| offset.y < wedge[0].y));
+		assert!(wedge[0].x.abs() < 0.001);
+
+		for &formation in Formation::all() {
+			let offsets = formation.slot_offsets(12, spacing);
+			assert_eq!(offsets.len(), 12);
+			for i in 0..offsets.len() {
+				for j in (i + 1)..offsets.len() {
+					assert!((offsets[i] - offsets[j]).magnitude() >= spacing - 0.001, "{:?} slots too close", formation);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_slots_face_move_direction() {
+		// moving along +X, the line stretches along Z
+		let slots = Formation::Line.slots(3, vec2(10.0, 10.0), vec2(5.0, 0.0), 2.0);
+		assert!(slots.iter().all(|slot| (slot.x - 10.0).abs() < 0.001));
+		assert!((slots[0] - slots[2]).magnitude() > 3.9);
+
+		let wedge = Formation::Wedge.slots(3, vec2(0.0, 0.0), vec2(1.0, 0.0), 2.0);
+		assert!(wedge[0].x > wedge[1].x && wedge[0].x > wedge[2].x);
+	}
+
+	#[test]
+	fn test_assignment_minimizes_travel() {
+		// crossing is never the best
+		let positions = [vec2(0.0, 0.0), vec2(10.0, 0.0)];
+		let slots = [vec2(10.0, 1.0), vec2(0.0, 1.0)];
+		assert_eq!(assign_slots(&positions, &slots), vec![1, 0]);
+
+		let positions: Vec<Vector2> = (0..6).map(|i| vec2((i * 7 % 5) as Real, (i * 3 % 4) as Real * 2.0)).collect();
+		let slots = Formation::Wedge.slots(6, vec2(20.0, 5.0), vec2(1.0, 0.3), 1.5);
+		let assignment = assign_slots(&positions, &slots);
+
+		let best = permutations((0..6).collect()).into_iter()
+			.map(|permutation| total_distance(&positions, &slots, &permutation))
+			.fold(::std::f32::INFINITY, Real::min);
+		assert!((total_distance(&positions, &slots, &assignment) - best).abs() < 0.001);
+	}
+
+	#[test]
+	fn test_slots_pushed_off_slopes() {
+		// steep ridge across the middle of the map
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 64.0, 64.0), |x, _| {
+			if x < 28 || x > 36 { 0.0 } else { (4 - (x as isize - 32).abs()) as Real / 64.0 * 4.0 }
+		});
+		let grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		let spacing = 1.5;
+		let mut slots = Formation::Box.slots(9, vec2(32.0, 32.0), vec2(0.0, 1.0), spacing);
+		push_slots_off_slopes(&mut slots, &grid, &heightfield, MovementClass::Infantry, spacing);
+
+		let max_slope = MovementClass::Infantry.max_slope().0;
+		for slot in slots.iter() {
+			assert!(heightfield.slope_at(slot.x, slot.y).0 <= max_slope);
+			assert!((slot - vec2(32.0, 32.0)).magnitude() < 10.0);
+		}
+	}
+
+	#[test]
+	fn test_slots_pushed_off_blocked_cells() {
+		let heightfield = Heightfield::from_fn(32, 32, vec3(32.0, 1.0, 32.0), |_, _| 0.0);
+		let mut grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		// a building covering the goal
+		for x in 12..20 {
+			for y in 12..20 {
+				grid.set_blocked((x, y), true);
+			}
+		}
+		let spacing = 2.0;
+		let mut slots = Formation::Box.slots(9, vec2(16.0, 16.0), vec2(0.0, 1.0), spacing);
+		push_slots_off_slopes(&mut slots, &grid, &heightfield, MovementClass::Infantry, spacing);
+
+		for (i, slot) in slots.iter().enumerate() {
+			assert!(grid.is_passable(grid.cell_at(*slot).unwrap(), MovementClass::Infantry));
+			// pushed slots don't pile up on the same free spot
+			for other in slots[..i].iter() {
+				assert!((slot - other).magnitude() >= spacing * 0.5);
+			}
+		}
+	}
+
+}
@@ -1,5 +1,39 @@
+mod cameracontroller;
+mod combat;
+mod commandresolver;
+mod components;
+mod data;
+mod economy;
+mod formation;
+mod movement;
+mod orders;
+mod placement;
+mod player;
+mod production;
+mod projectiles;
+mod scenesync;
+mod selection;
+mod spatialhash;
+mod visibility;
+#[cfg(test)]
+pub mod test_util;
+mod world;
 
-pub struct RTSCameraController {
-
-}
-
+pub use self::cameracontroller::*;
+pub use self::combat::*;
+pub use self::commandresolver::*;
+pub use self::components::*;
+pub use self::data::*;
+pub use self::economy::*;
+pub use self::formation::*;
+pub use self::movement::*;
+pub use self::orders::*;
+pub use self::placement::*;
+pub use self::player::*;
+pub use self::production::*;
+pub use self::projectiles::*;
+pub use self::scenesync::*;
+pub use self::selection::*;
+pub use self::spatialhash::*;
+pub use self::visibility::*;
+pub use self::world::*;
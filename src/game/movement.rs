@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+
+use ::game::{World, EntityId, Movement, Formation, update_spatial_hash, push_slots_off_slopes, assign_slots};
+use ::terrain::Heightfield;
+use ::navigation::{Navigation, NavGrid, AvoidanceAgent, AvoidanceParams, solve_avoidance, grid_obstacles};
+use ::math::*;
+
+/// Finds a path to the target and makes the entity follow it.
+/// Returns false when the target can't be reached.
+pub fn order_move(world: &mut World, navigation: &Navigation, id: EntityId, target: Vector3) -> bool {
+	let position = match world.poses.get(id) {
+		Some(pose) => pose.position,
+		None => return false,
+	};
+	let movement = match world.movements.get_mut(id) {
+		Some(movement) => movement,
+		None => return false,
+	};
+
+	match navigation.find_path(movement.class, vec2(position.x, position.z), vec2(target.x, target.z)) {
+		Some(path) => {
+			let mut waypoints: Vec<Vector3> = path.iter().map(|point| vec3(point.x, 0.0, point.y)).collect();
+			movement.destination = Some(waypoints.remove(0));
+			movement.waypoints = waypoints;
+			movement.flow_field = None;
+			movement.blocked_time = 0.0;
+			true
+		}
+		None => false,
+	}
+}
+
+/// Makes the entity follow the shared flow field to the goal, and walk
+/// straight to the target once close. Returns false when the goal can't be reached.
+pub fn order_flow_move(world: &mut World, navigation: &mut Navigation, id: EntityId, target: Vector3, goal: Vector3) -> bool {
+	let position = match world.poses.get(id) {
+		Some(pose) => vec2(pose.position.x, pose.position.z),
+		None => return false,
+	};
+	let movement = match world.movements.get_mut(id) {
+		Some(movement) => movement,
+		None => return false,
+	};
+	let goal_cell = match navigation.grid.cell_at(vec2(goal.x, goal.z)) {
+		Some(cell) => cell,
+		None => return false,
+	};
+
+	let field = navigation.flow_fields.get(&navigation.grid, movement.class, goal_cell);
+	if !navigation.grid.cell_at(position).map_or(false, |cell| field.is_reachable(cell)) {
+		return false;
+	}
+
+	movement.destination = Some(target);
+	movement.waypoints.clear();
+	movement.flow_field = Some(field);
+	movement.blocked_time = 0.0;
+	true
+}
+
+/// Stops the entity where it stands
+pub fn stop_movement(world: &mut World, id: EntityId) {
+	let position = match world.poses.get(id) {
+		Some(pose) => pose.position,
+		None => return,
+	};
+
+	if let Some(movement) = world.movements.get_mut(id) {
+		movement.destination = None;
+		movement.waypoints.clear();
+		movement.flow_field = None;
+		movement.rest_position = Some(position);
+		movement.blocked_time = 0.0;
+	}
+}
+
+/// Groups at least this big share a flow field instead of searching a path per unit
+pub const FLOW_FIELD_GROUP_SIZE: usize = 8;
+
+/// Distance between formation slots in unit radii
+const FORMATION_SPACING_RADII: Real = 4.0;
+
+/// Slots of the formation around the target for units starting from the
+/// given positions, facing the way the group moves. Returned in unit order.
+pub fn formation_targets(world: &World, grid: &NavGrid, heightfield: &Heightfield, units: &[(EntityId, Vector2)], target: Vector3, formation: Formation) -> Vec<Vector3> {
+	let units: Vec<(EntityId, Vector2)> = units.iter().cloned().filter(|&(id, _)| world.movements.contains(id)).collect();
+	if units.is_empty() {
+		return Vec::new();
+	}
+
+	let positions: Vec<Vector2> = units.iter().map(|&(_, position)| position).collect();
+	let center = positions.iter().fold(vec2(0.0, 0.0), |sum, &position| sum + position) / (positions.len() as Real);
+	let goal = vec2(target.x, target.z);
+
+	let radius = units.iter().map(|&(id, _)| world.movements.get(id).unwrap().radius).fold(0.0, Real::max);
+	let spacing = radius * FORMATION_SPACING_RADII;
+
+	// slots have to suit the unit climbing the least
+	let class = units.iter()
+		.map(|&(id, _)| world.movements.get(id).unwrap().class)
+		.min_by(|a, b| a.max_slope().0.partial_cmp(&b.max_slope().0).unwrap())
+		.unwrap();
+
+	let mut slots = formation.slots(units.len(), goal, goal - center, spacing);
+	push_slots_off_slopes(&mut slots, grid, heightfield, class, spacing);
+
+	assign_slots(&positions, &slots).iter()
+		.map(|&slot| vec3(slots[slot].x, target.y, slots[slot].y))
+		.collect()
+}
+
+/// Sends units to slots of the formation around the target.
+/// Returns how many of them can reach their slots.
+pub fn order_group_move(world: &mut World, navigation: &mut Navigation, heightfield: &Heightfield, ids: &[EntityId], target: Vector3, formation: Formation) -> usize {
+	let units: Vec<(EntityId, Vector2)> = ids.iter()
+		.filter(|&&id| world.movements.contains(id))
+		.filter_map(|&id| world.poses.get(id).map(|pose| (id, vec2(pose.position.x, pose.position.z))))
+		.collect();
+
+	let slots = formation_targets(world, &navigation.grid, heightfield, &units, target, formation);
+
+	// the shared field leads to the goal, close to it units walk straight to their slots
+	let group_goal = if units.len() >= FLOW_FIELD_GROUP_SIZE { Some(target) } else { None };
+
+	units.iter().zip(slots.iter())
+		.filter(|&(&(id, _), &slot)| move_to(world, navigation, id, slot, group_goal))
+		.count()
+}
+
+/// Moves the entity to the target along a path of its own,
+/// or along the shared flow field to the group goal
+pub fn move_to(world: &mut World, navigation: &mut Navigation, id: EntityId, target: Vector3, group_goal: Option<Vector3>) -> bool {
+	match group_goal {
+		Some(goal) => order_flow_move(world, navigation, id, target, goal),
+		None => order_move(world, navigation, id, target),
+	}
+}
+
+/// Updates navigation after obstacles changed and replaces outdated flow fields
+pub fn update_navigation(world: &mut World, navigation: &mut Navigation) {
+	for cell in world.take_freed_cells() {
+		navigation.grid.set_blocked(cell, false);
+	}
+	navigation.update();
+
+	for (_, movement) in world.movements.iter_mut() {
+		let outdated = match movement.flow_field {
+			Some(ref field) if field.is_outdated(&navigation.grid) => Some((field.class(), field.goal())),
+			_ => None,
+		};
+
+		if let Some((class, goal)) = outdated {
+			movement.flow_field = Some(navigation.flow_fields.get(&navigation.grid, class, goal));
+		}
+	}
+}
+
+/// Final destination counts as reached this close to it
+const ARRIVAL_TOLERANCE: Real = 0.01;
+
+/// Units blocked by others within this many radii of the destination
+/// for `CROWDED_ARRIVAL_TIME` stop there, as the crowd already stands at the destination
+const CROWDED_ARRIVAL_RADII: Real = 4.0;
+
+/// Seconds a unit keeps trying to get through to its destination
+const CROWDED_ARRIVAL_TIME: Real = 2.0;
+
+/// Moves entities along their paths, steering around each other and
+/// impassable cells, and keeps them on the ground.
+/// Velocities of all units are solved from the same state, so the result
+/// doesn't depend on the order units are updated in.
+pub fn update_movement(world: &mut World, grid: &NavGrid, heightfield: &Heightfield, delta_time: Real) {
+	let params = AvoidanceParams::default();
+
+	update_spatial_hash(world);
+
+	let mut ids = Vec::new();
+	let mut indices = HashMap::new();
+	let mut classes = Vec::new();
+	let mut agents = Vec::new();
+
+	for (id, movement) in world.movements.iter_mut() {
+		let position = match world.poses.get(id) {
+			Some(pose) => vec2(pose.position.x, pose.position.z),
+			None => continue,
+		};
+
+		indices.insert(id, ids.len());
+		ids.push(id);
+		classes.push(movement.class);
+		agents.push(AvoidanceAgent {
+			position: position,
+			velocity: vec2(movement.velocity.x, movement.velocity.z),
+			preferred_velocity: preferred_velocity(movement, position, delta_time),
+			radius: movement.radius,
+			max_speed: movement.max_speed,
+		});
+	}
+
+	let spatial_hash = &world.spatial_hash;
+	let velocities = solve_avoidance(&agents, |i| {
+		spatial_hash.query_radius(agents[i].position, params.neighbour_distance).into_iter()
+			.filter_map(|id| indices.get(&id).cloned())
+			.collect()
+	}, |i| {
+		let range = agents[i].radius + agents[i].max_speed * params.obstacle_time_horizon;
+		grid_obstacles(grid, classes[i], agents[i].position, range)
+	}, &params, delta_time);
+
+	for (i, &id) in ids.iter().enumerate() {
+		let movement = world.movements.get_mut(id).unwrap();
+		let pose = world.poses.get_mut(id).unwrap();
+		let velocity = velocities[i];
+
+		pose.position.x += velocity.x * delta_time;
+		pose.position.z += velocity.y * delta_time;
+		world.spatial_hash.set_position(id, vec2(pose.position.x, pose.position.z));
+		movement.velocity = vec3(velocity.x, 0.0, velocity.y);
+
+		if velocity.magnitude2() > 0.0001 {
+			pose.heading = turn_towards(pose.heading, Rad(velocity.x.atan2(velocity.y)), Rad(movement.turn_speed * delta_time));
+		}
+
+		if let Some(destination) = movement.destination {
+			if movement.waypoints.is_empty() {
+				let preferred_velocity = agents[i].preferred_velocity;
+				let distance = (vec2(destination.x, destination.z) - vec2(pose.position.x, pose.position.z)).magnitude();
+				let is_blocked = velocity.dot(preferred_velocity) < 0.25 * preferred_velocity.magnitude2();
+
+				if is_blocked && distance < movement.radius * CROWDED_ARRIVAL_RADII {
+					movement.blocked_time += delta_time;
+				} else {
+					movement.blocked_time = 0.0;
+				}
+
+				if distance <= ARRIVAL_TOLERANCE {
+					pose.position.x = destination.x;
+					pose.position.z = destination.z;
+				}
+
+				if distance <= ARRIVAL_TOLERANCE || movement.blocked_time >= CROWDED_ARRIVAL_TIME {
+					movement.destination = None;
+					movement.flow_field = None;
+					movement.rest_position = Some(pose.position);
+					movement.blocked_time = 0.0;
+				}
+			}
+		}
+
+		pose.position.y = heightfield.height_at(pose.position.x, pose.position.z);
+	}
+}
+
+/// Velocity the unit would walk with if nothing was in the way,
+/// switches to the next waypoint when the current one is close.
+/// Units without a destination walk back to where they stopped.
+fn preferred_velocity(movement: &mut Movement, position: Vector2, delta_time: Real) -> Vector2 {
+	let step = movement.max_speed * delta_time;
+
+	while let Some(destination) = movement.destination {
+		let destination = vec2(destination.x, destination.z);
+		let distance = (destination - position).magnitude();
+
+		if !movement.waypoints.is_empty() {
+			if distance <= step.max(movement.radius) {
+				movement.destination = Some(movement.waypoints.remove(0));
+				continue;
+			}
+		} else if distance <= step {
+			// lands exactly on the destination
+			return (destination - position) / delta_time;
+		}
+
+		let target = match movement.flow_field {
+			Some(ref field) => field.steering_target(position, destination),
+			None => destination,
+		};
+		let offset = target - position;
+		let distance = offset.magnitude();
+
+		return if distance > 0.0 {
+			offset / distance * movement.max_speed
+		} else {
+			vec2(0.0, 0.0)
+		};
+	}
+
+	match movement.rest_position {
+		Some(rest_position) => {
+			let offset = vec2(rest_position.x, rest_position.z) - position;
+			let distance = offset.magnitude();
+			if distance <= ARRIVAL_TOLERANCE {
+				vec2(0.0, 0.0)
+			} else if distance <= step {
+				offset / delta_time
+			} else {
+				offset / distance * movement.max_speed
+			}
+		}
+		None => vec2(0.0, 0.0),
+	}
+}
+
+/// Rotates heading to the target heading by at most `max_step`, going the shorter way
+pub fn turn_towards(heading: Rad<Real>, target: Rad<Real>, max_step: Rad<Real>) -> Rad<Real> {
+	let difference = wrap_angle(target - heading);
+	if difference.0.abs() <= max_step.0 {
+		wrap_angle(target)
+	} else {
+		wrap_angle(heading + Rad(max_step.0 * difference.0.signum()))
+	}
+}
+
+/// Brings the angle to the -pi..pi range
+pub fn wrap_angle(angle: Rad<Real>) -> Rad<Real> {
+	let pi = ::std::f32::consts::PI;
+	Rad(angle.0 - 2.0 * pi * ((angle.0 + pi) / (2.0 * pi)).floor())
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::Pose;
+	use std::rc::Rc;
+
+	#[test]
+	fn test_move_to_destination() {
+		let heightfield = Heightfield::from_fn(4, 4, vec3(100.0, 20.0, 100.0), |_, _| 0.25);
+		let grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		let mut world = World::new();
+
+		let unit = world.spawn();
+		world.poses.insert(unit, Pose::new(vec3(10.0, 0.0, 10.0)));
+		world.movements.insert(unit, Movement::new(5.0));
+		world.movements.get_mut(unit).unwrap().destination = Some(vec3(20.0, 0.0, 10.0));
+
+		update_movement(&mut world, &grid, &heightfield, 1.0);
+		let position = world.poses.get(unit).unwrap().position;
+		assert!((position - vec3(15.0, 5.0, 10.0)).magnitude() < 0.001);
+		assert!(world.movements.get(unit).unwrap().destination.is_some());
+
+		update_movement(&mut world, &grid, &heightfield, 1.5);
+		let position = world.poses.get(unit).unwrap().position;
+		assert!((position - vec3(20.0, 5.0, 10.0)).magnitude() < 0.001);
+		assert!(world.movements.get(unit).unwrap().destination.is_none());
+	}
+
+	#[test]
+	fn test_follow_path() {
+		let heightfield = Heightfield::from_fn(16, 16, vec3(16.0, 1.0, 16.0), |_, _| 0.0);
+		let mut grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		for y in 0..12 {
+			grid.set_blocked((8, y), true);
+		}
+		let navigation = Navigation::new(grid);
+		let grid = &navigation.grid;
+
+		let mut world = World::new();
+		let unit = world.spawn();
+		world.poses.insert(unit, Pose::new(vec3(2.5, 0.0, 2.5)));
+		world.movements.insert(unit, Movement::new(5.0));
+
+		assert!(order_move(&mut world, &navigation, unit, vec3(13.5, 0.0, 2.5)));
+		assert!(!world.movements.get(unit).unwrap().waypoints.is_empty());
+
+		for _ in 0..200 {
+			update_movement(&mut world, grid, &heightfield, 0.05);
+			let position = world.poses.get(unit).unwrap().position;
+			assert!(!grid.is_blocked(grid.cell_at(vec2(position.x, position.z)).unwrap()));
+		}
+
+		let position = world.poses.get(unit).unwrap().position;
+		assert!((position - vec3(13.5, 0.0, 2.5)).magnitude() < 0.001);
+		assert!(world.movements.get(unit).unwrap().destination.is_none());
+
+		// target inside the wall
+		assert!(!order_move(&mut world, &navigation, unit, vec3(8.5, 0.0, 2.5)));
+	}
+
+	#[test]
+	fn test_group_move_with_flow_field() {
+		let heightfield = Heightfield::from_fn(16, 16, vec3(16.0, 1.0, 16.0), |_, _| 0.0);
+		let mut grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		for y in 0..12 {
+			grid.set_blocked((8, y), true);
+		}
+		let mut navigation = Navigation::new(grid);
+
+		let mut world = World::new();
+		let units: Vec<EntityId> = (0..FLOW_FIELD_GROUP_SIZE).map(|i| {
+			let unit = world.spawn();
+			world.poses.insert(unit, Pose::new(vec3(1.5 + (i % 4) as Real, 0.0, 1.5 + (i / 4) as Real)));
+			world.movements.insert(unit, Movement::new(5.0));
+			unit
+		}).collect();
+
+		let target = vec3(12.5, 0.0, 5.5);
+		assert_eq!(order_group_move(&mut world, &mut navigation, &heightfield, &units, target, Formation::Box), units.len());
+		assert_eq!(navigation.flow_fields.len(), 1);
+
+		let slots: Vec<Vector3> = units.iter().map(|&unit| world.movements.get(unit).unwrap().destination.unwrap()).collect();
+		for (i, slot) in slots.iter().enumerate() {
+			assert!((slot - target).magnitude() < 3.0);
+			assert!(slots.iter().skip(i + 1).all(|other| (other - slot).magnitude() > 1.0));
+		}
+
+		let first = world.movements.get(units[0]).unwrap().flow_field.clone().unwrap();
+		assert!(units.iter().all(|&unit| Rc::ptr_eq(world.movements.get(unit).unwrap().flow_field.as_ref().unwrap(), &first)));
+
+		// a new obstacle replaces the shared field
+		navigation.grid.set_blocked((4, 14), true);
+		update_navigation(&mut world, &mut navigation);
+		let refreshed = world.movements.get(units[0]).unwrap().flow_field.clone().unwrap();
+		assert!(!Rc::ptr_eq(&first, &refreshed));
+		assert!(!refreshed.is_outdated(&navigation.grid));
+
+		for _ in 0..400 {
+			update_movement(&mut world, &navigation.grid, &heightfield, 0.05);
+			for &unit in units.iter() {
+				let position = world.poses.get(unit).unwrap().position;
+				assert!(!navigation.grid.is_blocked(navigation.grid.cell_at(vec2(position.x, position.z)).unwrap()));
+			}
+		}
+
+		// every unit stands in its own slot
+		for (&unit, slot) in units.iter().zip(slots.iter()) {
+			let movement = world.movements.get(unit).unwrap();
+			let position = world.poses.get(unit).unwrap().position;
+			assert!((vec2(position.x, position.z) - vec2(slot.x, slot.z)).magnitude() < 0.01);
+			assert!(movement.destination.is_none());
+			assert!(movement.flow_field.is_none());
+		}
+	}
+
+	#[test]
+	fn test_units_pass_each_other() {
+		let heightfield = Heightfield::from_fn(16, 16, vec3(16.0, 1.0, 16.0), |_, _| 0.0);
+		let grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		let mut world = World::new();
+
+		let a = world.spawn();
+		world.poses.insert(a, Pose::new(vec3(2.0, 0.0, 8.0)));
+		world.movements.insert(a, Movement::new(5.0));
+		world.movements.get_mut(a).unwrap().destination = Some(vec3(14.0, 0.0, 8.0));
+
+		let b = world.spawn();
+		world.poses.insert(b, Pose::new(vec3(14.0, 0.0, 8.0)));
+		world.movements.insert(b, Movement::new(5.0));
+		world.movements.get_mut(b).unwrap().destination = Some(vec3(2.0, 0.0, 8.0));
+
+		for _ in 0..100 {
+			update_movement(&mut world, &grid, &heightfield, 0.05);
+			let distance = (world.poses.get(a).unwrap().position - world.poses.get(b).unwrap().position).magnitude();
+			assert!(distance > 0.95);
+		}
+
+		assert!((world.poses.get(a).unwrap().position - vec3(14.0, 0.0, 8.0)).magnitude() < 0.001);
+		assert!((world.poses.get(b).unwrap().position - vec3(2.0, 0.0, 8.0)).magnitude() < 0.001);
+	}
+
+	#[test]
+	fn test_turn_towards() {
+		let pi = ::std::f32::consts::PI;
+
+		assert!((turn_towards(Rad(0.0), Rad(1.0), Rad(0.5)).0 - 0.5).abs() < 0.0001);
+		assert!((turn_towards(Rad(0.0), Rad(1.0), Rad(2.0)).0 - 1.0).abs() < 0.0001);
+		// shorter way around goes through -pi
+		let turned = turn_towards(Rad(pi - 0.1), Rad(-pi + 0.1), Rad(0.1));
+		assert!((turned.0.abs() - pi).abs() < 0.0001);
+	}
+
+}
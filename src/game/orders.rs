@@ -0,0 +1,952 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use toml;
+
+use ::game::{World, EntityId, PlayerId, Owner, Pose, Formation, FLOW_FIELD_GROUP_SIZE};
+use ::game::{move_to, order_move, stop_movement, formation_targets, body_radius, find_target, can_attack, attack_distance};
+use ::game::{harvest, is_carrying, nearest_drop_off, deliver_cargo};
+use ::navigation::{Navigation, NavGrid};
+use ::terrain::Heightfield;
+use ::math::*;
+
+/// Enemies closer than this are attacked during attack-move and patrol
+pub const ACQUISITION_RANGE: Real = 8.0;
+
+/// Units chasing an enemy stop this much inside of their weapon range
+const RANGE_MARGIN: Real = 0.25;
+
+/// Chasing units search a new path when the enemy gets this far from the end of the old one
+const REPATH_DISTANCE: Real = 1.0;
+
+/// Enemies running further than this are let go during attack-move and patrol
+const LEASH_RANGE: Real = 12.0;
+
+/// Workers gather, repair and enter this close to the edge of the target
+const INTERACTION_DISTANCE: Real = 1.0;
+
+/// Health points a worker restores per second
+const REPAIR_RATE: Real = 10.0;
+
+/// Orders finishing in a tick let the next ones start in the same tick, up to this many
+const MAX_ORDERS_PER_TICK: usize = 4;
+
+/// What a unit is told to do. Plain data referring to entities by id,
+/// so orders can be recorded for replays and sent over the network.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Order {
+	/// Walk to the point, ignoring enemies.
+	/// `group_goal` is the point the whole group heads to, big groups share a flow field to it.
+	Move { target: Vector3, group_goal: Option<Vector3> },
+	/// Walk to the point, fighting enemies met on the way
+	AttackMove { target: Vector3, group_goal: Option<Vector3> },
+	/// Walk between the point and where the order started until told otherwise
+	Patrol { target: Vector3, group_goal: Option<Vector3> },
+	/// Chase the entity until it's dead, units without a weapon for it drop the order
+	Attack(EntityId),
+	/// Gather from the resource node and carry the loads to the nearest drop-off until the node is depleted
+	Gather(EntityId),
+	/// Get into the transport
+	Enter(EntityId),
+	/// Drive the transport to the point and let the passengers out around it
+	Unload { target: Vector3, group_goal: Option<Vector3> },
+	/// Repair the building until it's at full health
+	Repair(EntityId),
+	/// Stand still, never chase anyone
+	Hold,
+	/// Drop everything
+	Stop,
+}
+
+impl Order {
+
+	/// Point the unit walks to, if the order has one
+	pub fn target_position(&self) -> Option<Vector3> {
+		match *self {
+			Order::Move { target, .. } | Order::AttackMove { target, .. } | Order::Patrol { target, .. } | Order::Unload { target, .. } => Some(target),
+			_ => None,
+		}
+	}
+
+	/// Same order sent to another point
+	pub fn with_target(&self, target: Vector3, group_goal: Option<Vector3>) -> Order {
+		match *self {
+			Order::Move { .. } => Order::Move { target: target, group_goal: group_goal },
+			Order::AttackMove { .. } => Order::AttackMove { target: target, group_goal: group_goal },
+			Order::Patrol { .. } => Order::Patrol { target: target, group_goal: group_goal },
+			Order::Unload { .. } => Order::Unload { target: target, group_goal: group_goal },
+			order => order,
+		}
+	}
+
+	pub fn to_toml(&self) -> toml::Value {
+		let mut table = BTreeMap::new();
+
+		let name = match *self {
+			Order::Move { .. } => "move",
+			Order::AttackMove { .. } => "attack_move",
+			Order::Patrol { .. } => "patrol",
+			Order::Attack(_) => "attack",
+			Order::Gather(_) => "gather",
+			Order::Enter(_) => "enter",
+			Order::Unload { .. } => "unload",
+			Order::Repair(_) => "repair",
+			Order::Hold => "hold",
+			Order::Stop => "stop",
+		};
+		table.insert("type".to_string(), toml::Value::String(name.to_string()));
+
+		match *self {
+			Order::Move { target, group_goal } | Order::AttackMove { target, group_goal } | Order::Patrol { target, group_goal } | Order::Unload { target, group_goal } => {
+				table.insert("target".to_string(), vector_to_toml(target));
+				if let Some(goal) = group_goal {
+					table.insert("group_goal".to_string(), vector_to_toml(goal));
+				}
+			}
+			Order::Attack(entity) | Order::Gather(entity) | Order::Enter(entity) | Order::Repair(entity) => {
+				table.insert("entity".to_string(), entity_to_toml(entity));
+			}
+			Order::Hold | Order::Stop => {}
+		}
+
+		toml::Value::Table(table)
+	}
+
+	pub fn from_toml(value: &toml::Value) -> Result<Order, OrderError> {
+		let name = match value.get("type").and_then(|name| name.as_str()) {
+			Some(name) => name,
+			None => return Err(OrderError::Format("order without a type".to_string())),
+		};
+
+		let target = || value.get("target").ok_or_else(|| OrderError::Format(format!("{} order without a target", name))).and_then(vector_from_toml);
+		let entity = || value.get("entity").ok_or_else(|| OrderError::Format(format!("{} order without an entity", name))).and_then(entity_from_toml);
+		let group_goal = || match value.get("group_goal") {
+			Some(goal) => vector_from_toml(goal).map(Some),
+			None => Ok(None),
+		};
+
+		Ok(match name {
+			"move" => Order::Move { target: target()?, group_goal: group_goal()? },
+			"attack_move" => Order::AttackMove { target: target()?, group_goal: group_goal()? },
+			"patrol" => Order::Patrol { target: target()?, group_goal: group_goal()? },
+			"attack" => Order::Attack(entity()?),
+			"gather" => Order::Gather(entity()?),
+			"enter" => Order::Enter(entity()?),
+			"unload" => Order::Unload { target: target()?, group_goal: group_goal()? },
+			"repair" => Order::Repair(entity()?),
+			"hold" => Order::Hold,
+			"stop" => Order::Stop,
+			_ => return Err(OrderError::Format(format!("unknown order type '{}'", name))),
+		})
+	}
+
+}
+
+/// Progress of the order at the front of the queue
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OrderState {
+	/// Not started yet
+	Pending,
+	/// Walking to the order target, or chasing the attacked entity
+	Moving,
+	/// Walking the patrol route, `origin` is where the patrol started
+	Patrolling { origin: Vector3, returning: bool },
+	Holding,
+	/// Next to the target, gathering or repairing
+	Working,
+	/// Carrying a gathered load to a drop-off
+	Returning,
+}
+
+/// Orders of a unit, executed one after another by `update_orders`
+pub struct OrderQueue {
+	orders: VecDeque<Order>,
+	pub state: OrderState,
+	/// Enemy the unit is fighting, the current order resumes once it's gone
+	pub target: Option<EntityId>,
+}
+
+impl OrderQueue {
+
+	pub fn new() -> Self {
+		OrderQueue {
+			orders: VecDeque::new(),
+			state: OrderState::Pending,
+			target: None,
+		}
+	}
+
+	pub fn orders(&self) -> &VecDeque<Order> {
+		&self.orders
+	}
+
+	pub fn current(&self) -> Option<Order> {
+		self.orders.front().cloned()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.orders.is_empty()
+	}
+
+	/// Appends the order when queued, otherwise replaces all orders with it
+	pub fn push(&mut self, order: Order, queued: bool) {
+		if !queued {
+			self.clear();
+		}
+		self.orders.push_back(order);
+	}
+
+	pub fn clear(&mut self) {
+		self.orders.clear();
+		self.state = OrderState::Pending;
+		self.target = None;
+	}
+
+	/// Drops the current order, the next one starts from scratch
+	pub fn finish_current(&mut self) {
+		self.orders.pop_front();
+		self.state = OrderState::Pending;
+		self.target = None;
+	}
+
+}
+
+/// Order given by a player to a group of units, what gets recorded and sent over the network
+#[derive(Clone, PartialEq, Debug)]
+pub struct Command {
+	pub player: PlayerId,
+	pub units: Vec<EntityId>,
+	/// Order for the whole group, targets are spread over formation slots
+	pub order: Order,
+	/// Appends the order instead of replacing the current ones, Shift in the UI
+	pub queued: bool,
+	pub formation: Formation,
+}
+
+impl Command {
+
+	pub fn to_toml(&self) -> toml::Value {
+		let mut table = BTreeMap::new();
+		table.insert("player".to_string(), toml::Value::Integer(self.player.0 as i64));
+		table.insert("units".to_string(), toml::Value::Array(self.units.iter().map(|&id| entity_to_toml(id)).collect()));
+		table.insert("order".to_string(), self.order.to_toml());
+		table.insert("queued".to_string(), toml::Value::Boolean(self.queued));
+		table.insert("formation".to_string(), toml::Value::String(self.formation.name().to_string()));
+		toml::Value::Table(table)
+	}
+
+	pub fn from_toml(value: &toml::Value) -> Result<Command, OrderError> {
+		let player = match value.get("player").and_then(|player| player.as_integer()) {
+			Some(player) if player >= 0 && player < 256 => PlayerId(player as u8),
+			_ => return Err(OrderError::Format("command without a valid player".to_string())),
+		};
+
+		let units = match value.get("units").and_then(|units| units.as_array()) {
+			Some(units) => units.iter().map(entity_from_toml).collect::<Result<Vec<_>, _>>()?,
+			None => return Err(OrderError::Format("command without units".to_string())),
+		};
+
+		let order = match value.get("order") {
+			Some(order) => Order::from_toml(order)?,
+			None => return Err(OrderError::Format("command without an order".to_string())),
+		};
+
+		let formation = match value.get("formation").and_then(|formation| formation.as_str()) {
+			Some(name) => match Formation::from_name(name) {
+				Some(formation) => formation,
+				None => return Err(OrderError::Format(format!("unknown formation '{}'", name))),
+			},
+			None => Formation::Box,
+		};
+
+		Ok(Command {
+			player: player,
+			units: units,
+			order: order,
+			queued: value.get("queued").and_then(|queued| queued.as_bool()).unwrap_or(false),
+			formation: formation,
+		})
+	}
+
+}
+
+#[derive(Debug)]
+pub enum OrderError {
+	Format(String),
+}
+
+impl fmt::Display for OrderError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			OrderError::Format(ref message) => write!(f, "{}", message),
+		}
+	}
+}
+
+fn vector_to_toml(vector: Vector3) -> toml::Value {
+	toml::Value::Array(vec![
+		toml::Value::Float(vector.x as f64),
+		toml::Value::Float(vector.y as f64),
+		toml::Value::Float(vector.z as f64),
+	])
+}
+
+fn vector_from_toml(value: &toml::Value) -> Result<Vector3, OrderError> {
+	let components: Vec<f64> = match value.as_array() {
+		Some(array) => array.iter().filter_map(|component| component.as_float()).collect(),
+		None => Vec::new(),
+	};
+
+	if components.len() == 3 {
+		Ok(vec3(components[0] as Real, components[1] as Real, components[2] as Real))
+	} else {
+		Err(OrderError::Format(format!("expected three floats, got {}", value)))
+	}
+}
+
+fn entity_to_toml(id: EntityId) -> toml::Value {
+	toml::Value::Array(vec![toml::Value::Integer(id.index() as i64), toml::Value::Integer(id.generation() as i64)])
+}
+
+fn entity_from_toml(value: &toml::Value) -> Result<EntityId, OrderError> {
+	let parts: Vec<i64> = match value.as_array() {
+		Some(array) => array.iter().filter_map(|part| part.as_integer()).collect(),
+		None => Vec::new(),
+	};
+
+	if parts.len() == 2 && parts.iter().all(|&part| part >= 0 && part <= ::std::u32::MAX as i64) {
+		Ok(EntityId::from_parts(parts[0] as u32, parts[1] as u32))
+	} else {
+		Err(OrderError::Format(format!("expected entity index and generation, got {}", value)))
+	}
+}
+
+/// Gives the order to units of the player in the command, other units are ignored
+pub fn issue_command(world: &mut World, grid: &NavGrid, heightfield: &Heightfield, command: &Command) {
+	let mut units: Vec<EntityId> = Vec::new();
+	for &id in command.units.iter() {
+		if !units.contains(&id) && world.owners.get(id) == Some(&Owner(command.player)) && world.order_queues.contains(id) {
+			units.push(id);
+		}
+	}
+
+	let orders: Vec<(EntityId, Order)> = match command.order.target_position() {
+		Some(target) => {
+			// formation is planned from where units will be when the order starts
+			let starts: Vec<(EntityId, Vector2)> = units.iter()
+				.filter(|&&id| world.movements.contains(id))
+				.filter_map(|&id| planned_position(world, id, command.queued).map(|position| (id, vec2(position.x, position.z))))
+				.collect();
+
+			let slots = formation_targets(world, grid, heightfield, &starts, target, command.formation);
+			let group_goal = if starts.len() >= FLOW_FIELD_GROUP_SIZE { Some(target) } else { None };
+
+			starts.iter().zip(slots.iter())
+				.map(|(&(id, _), &slot)| (id, command.order.with_target(slot, group_goal)))
+				.collect()
+		}
+		None => units.iter().map(|&id| (id, command.order)).collect(),
+	};
+
+	for (id, order) in orders {
+		world.order_queues.get_mut(id).unwrap().push(order, command.queued);
+	}
+}
+
+/// Where the unit will be once its queued orders are done
+fn planned_position(world: &World, id: EntityId, queued: bool) -> Option<Vector3> {
+	let last_target = match world.order_queues.get(id) {
+		Some(queue) if queued => queue.orders().iter().rev().filter_map(|order| order.target_position()).next(),
+		_ => None,
+	};
+
+	last_target.or_else(|| world.poses.get(id).map(|pose| pose.position))
+}
+
+/// Runs the order state machines of all units, called once per tick before movement
+pub fn update_orders(world: &mut World, navigation: &mut Navigation, dt: Real) {
+	for id in world.order_queues.ids() {
+		for _ in 0..MAX_ORDERS_PER_TICK {
+			let (order, state) = match world.order_queues.get(id).and_then(|queue| queue.current().map(|order| (order, queue.state))) {
+				Some(current) => current,
+				None => break,
+			};
+
+			match step_order(world, navigation, id, order, state, dt) {
+				Some(state) => {
+					world.order_queues.get_mut(id).unwrap().state = state;
+					break;
+				}
+				None => world.order_queues.get_mut(id).unwrap().finish_current(),
+			}
+		}
+	}
+}
+
+/// Advances the order by a tick, returns the new state or None when the order is done
+fn step_order(world: &mut World, navigation: &mut Navigation, id: EntityId, order: Order, state: OrderState, dt: Real) -> Option<OrderState> {
+	// fighting an enemy met on the way
+	if let Some(enemy) = world.order_queues.get(id).unwrap().target {
+		let leash = match order {
+			Order::Attack(_) => None,
+			_ => Some(LEASH_RANGE),
+		};
+
+		if engage(world, navigation, id, enemy, leash) {
+			return Some(state);
+		}
+
+		set_target(world, id, None);
+		match order {
+			Order::Attack(_) => {
+				stop_movement(world, id);
+				return None;
+			}
+			_ => resume(world, navigation, id, order, state),
+		}
+	}
+
+	match (order, state) {
+		(Order::Stop, _) => {
+			stop_movement(world, id);
+			None
+		}
+		(Order::Hold, OrderState::Pending) => {
+			stop_movement(world, id);
+			Some(OrderState::Holding)
+		}
+		(Order::Attack(enemy), _) => {
+			if can_attack(world, id, enemy) && engage(world, navigation, id, enemy, None) {
+				set_target(world, id, Some(enemy));
+				Some(OrderState::Moving)
+			} else {
+				None
+			}
+		}
+		(Order::Gather(node), _) => gather(world, navigation, id, node, dt),
+		(Order::Enter(transport), _) => {
+			if world.transports.get(transport).map_or(true, |transport| transport.is_full()) {
+				return None;
+			}
+			match interact(world, navigation, id, transport) {
+				Some(true) => {
+					load(world, id, transport);
+					None
+				}
+				Some(false) => Some(OrderState::Moving),
+				None => None,
+			}
+		}
+		(Order::Unload { target, group_goal }, OrderState::Pending) => {
+			if world.transports.get(id).map_or(true, |transport| transport.passengers.is_empty()) {
+				return None;
+			}
+			if move_to(world, navigation, id, target, group_goal) {
+				Some(OrderState::Moving)
+			} else {
+				// can't get there, let them out where the transport is
+				unload(world, navigation, id);
+				None
+			}
+		}
+		(Order::Unload { .. }, OrderState::Moving) => {
+			if is_moving(world, id) {
+				return Some(state);
+			}
+			unload(world, navigation, id);
+			None
+		}
+		(Order::Repair(building), _) => {
+			if !world.buildings.contains(building) || world.healths.get(building).map_or(true, |health| health.current >= health.max) {
+				return None;
+			}
+			match interact(world, navigation, id, building) {
+				Some(true) => {
+					let health = world.healths.get_mut(building).unwrap();
+					health.current = (health.current + REPAIR_RATE * dt).min(health.max);
+					Some(OrderState::Working)
+				}
+				Some(false) => Some(OrderState::Moving),
+				None => None,
+			}
+		}
+		(Order::Move { target, group_goal }, OrderState::Pending) | (Order::AttackMove { target, group_goal }, OrderState::Pending) => {
+			if move_to(world, navigation, id, target, group_goal) {
+				Some(OrderState::Moving)
+			} else {
+				None
+			}
+		}
+		(Order::Patrol { target, group_goal }, OrderState::Pending) => {
+			let origin = match world.poses.get(id) {
+				Some(pose) => pose.position,
+				None => return None,
+			};
+			if move_to(world, navigation, id, target, group_goal) {
+				Some(OrderState::Patrolling { origin: origin, returning: false })
+			} else {
+				None
+			}
+		}
+		(Order::Patrol { target, .. }, OrderState::Patrolling { origin, returning }) => {
+			if acquire(world, navigation, id) || is_moving(world, id) {
+				return Some(state);
+			}
+			let next = if returning { target } else { origin };
+			if order_move(world, navigation, id, next) {
+				Some(OrderState::Patrolling { origin: origin, returning: !returning })
+			} else {
+				None
+			}
+		}
+		(Order::AttackMove { .. }, OrderState::Moving) => {
+			if acquire(world, navigation, id) || is_moving(world, id) {
+				Some(state)
+			} else {
+				None
+			}
+		}
+		(_, OrderState::Moving) => {
+			if is_moving(world, id) {
+				Some(state)
+			} else {
+				None
+			}
+		}
+		(_, state) => Some(state),
+	}
+}
+
+/// Harvests the node until the worker carries a load, then brings it to a drop-off and comes back.
+/// Once the node is gone the last load is delivered and the order is done.
+fn gather(world: &mut World, navigation: &mut Navigation, id: EntityId, node: EntityId, dt: Real) -> Option<OrderState> {
+	let node_left = world.resource_nodes.get(node).map_or(false, |node| node.amount > 0);
+	let loaded = world.workers.get(id).and_then(|worker| worker.cargo.as_ref()).map_or(false, |cargo| cargo.amount >= cargo.resource.carry_amount);
+
+	if is_carrying(world, id) && (loaded || !node_left) {
+		let drop_off = nearest_drop_off(world, id)?;
+		return match interact(world, navigation, id, drop_off) {
+			Some(true) => {
+				if !deliver_cargo(world, id) {
+					return None;
+				}
+				if node_left { Some(OrderState::Moving) } else { None }
+			}
+			Some(false) => Some(OrderState::Returning),
+			None => None,
+		};
+	}
+
+	if !node_left {
+		return None;
+	}
+
+	match interact(world, navigation, id, node) {
+		Some(true) => {
+			if harvest(world, id, node, dt) && nearest_drop_off(world, id).is_none() {
+				// nowhere to bring the load
+				stop_movement(world, id);
+				return None;
+			}
+			Some(OrderState::Working)
+		}
+		Some(false) => Some(OrderState::Moving),
+		None => None,
+	}
+}
+
+fn set_target(world: &mut World, id: EntityId, target: Option<EntityId>) {
+	world.order_queues.get_mut(id).unwrap().target = target;
+}
+
+fn is_moving(world: &World, id: EntityId) -> bool {
+	world.movements.get(id).map_or(false, |movement| movement.destination.is_some())
+}
+
+/// Walks on to where the order was going before the unit got distracted
+fn resume(world: &mut World, navigation: &mut Navigation, id: EntityId, order: Order, state: OrderState) {
+	match (order, state) {
+		(Order::AttackMove { target, .. }, OrderState::Moving) => {
+			order_move(world, navigation, id, target);
+		}
+		(Order::Patrol { target, .. }, OrderState::Patrolling { origin, returning }) => {
+			order_move(world, navigation, id, if returning { origin } else { target });
+		}
+		_ => {}
+	}
+}
+
+/// Starts fighting the nearest enemy in range the unit's weapon can hit, if there is one
+fn acquire(world: &mut World, navigation: &mut Navigation, id: EntityId) -> bool {
+	let range = match world.weapons.get(id) {
+		Some(weapon) => weapon.def.range.max(ACQUISITION_RANGE),
+		None => return false,
+	};
+
+	match find_target(world, id, range) {
+		Some(enemy) => {
+			set_target(world, id, Some(enemy));
+			engage(world, navigation, id, enemy, Some(LEASH_RANGE))
+		}
+		None => false,
+	}
+}
+
+/// Chases the enemy until it's in weapon range. Returns false when the enemy
+/// is gone or ran further than the leash.
+fn engage(world: &mut World, navigation: &mut Navigation, id: EntityId, enemy: EntityId, leash: Option<Real>) -> bool {
+	if let Some(leash) = leash {
+		if distance_between(world, id, enemy).map_or(false, |distance| distance > leash) {
+			return false;
+		}
+	}
+
+	match attack_distance(world, id, enemy) {
+		Some(distance) => approach(world, navigation, id, enemy, (distance - RANGE_MARGIN).max(0.0)).is_some(),
+		None => false,
+	}
+}
+
+/// Walks next to the target of a gather, enter or repair order
+fn interact(world: &mut World, navigation: &mut Navigation, id: EntityId, target: EntityId) -> Option<bool> {
+	let distance = INTERACTION_DISTANCE + body_radius(world, id) + body_radius(world, target);
+	approach(world, navigation, id, target, distance)
+}
+
+/// Walks towards the target until within the distance of it. Returns whether the unit
+/// is there yet, or None when the target is gone or can't be reached.
+fn approach(world: &mut World, navigation: &mut Navigation, id: EntityId, target: EntityId, distance: Real) -> Option<bool> {
+	if !world.is_alive(target) {
+		return None;
+	}
+	let target_position = match world.poses.get(target) {
+		Some(pose) => pose.position,
+		None => return None,
+	};
+
+	if distance_between(world, id, target)? <= distance {
+		if is_moving(world, id) {
+			stop_movement(world, id);
+		}
+		return Some(true);
+	}
+
+	let path_end = world.movements.get(id).and_then(|movement| movement.waypoints.last().cloned().or(movement.destination));
+	let needs_path = path_end.map_or(true, |end| (vec2(end.x, end.z) - vec2(target_position.x, target_position.z)).magnitude() > REPATH_DISTANCE);
+
+	if !needs_path || order_move(world, navigation, id, target_position) {
+		Some(false)
+	} else {
+		None
+	}
+}
+
+/// Distance between the entities on the XZ plane
+fn distance_between(world: &World, a: EntityId, b: EntityId) -> Option<Real> {
+	match (world.poses.get(a), world.poses.get(b)) {
+		(Some(a), Some(b)) => Some((vec2(a.position.x, a.position.z) - vec2(b.position.x, b.position.z)).magnitude()),
+		_ => None,
+	}
+}
+
+/// Puts the unit into the transport, taking it off the map. Orders queued after entering are dropped.
+fn load(world: &mut World, id: EntityId, transport: EntityId) {
+	stop_movement(world, id);
+	world.order_queues.get_mut(id).unwrap().clear();
+	world.poses.remove(id);
+	world.spatial_hash.remove(id);
+	world.transports.get_mut(transport).unwrap().passengers.push(id);
+}
+
+/// Puts all passengers back on the map in a ring around the transport.
+/// Passengers that don't fit on passable ground in the ring come out where the transport stands.
+fn unload(world: &mut World, navigation: &Navigation, transport: EntityId) {
+	let center = match world.poses.get(transport) {
+		Some(pose) => pose.position,
+		None => return,
+	};
+	let passengers = match world.transports.get_mut(transport) {
+		Some(transport) => ::std::mem::replace(&mut transport.passengers, Vec::new()),
+		None => return,
+	};
+
+	stop_movement(world, transport);
+	let transport_radius = body_radius(world, transport);
+
+	for (i, &passenger) in passengers.iter().enumerate() {
+		let angle = i as Real / passengers.len() as Real * 2.0 * ::std::f32::consts::PI;
+		let distance = transport_radius + body_radius(world, passenger) + INTERACTION_DISTANCE;
+		let spot = vec2(center.x, center.z) + vec2(angle.cos(), angle.sin()) * distance;
+
+		let passable = match (world.movements.get(passenger), navigation.grid.cell_at(spot)) {
+			(Some(movement), Some(cell)) => navigation.grid.is_passable(cell, movement.class),
+			_ => false,
+		};
+		let position = if passable { vec3(spot.x, center.y, spot.y) } else { center };
+
+		world.poses.insert(passenger, Pose::new(position));
+		stop_movement(world, passenger);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Transport, WeaponDef, DamageType};
+	use ::game::test_util::{flat_map, spawn_soldier, spawn_building, simulate};
+	use std::rc::Rc;
+
+	fn sword() -> Rc<WeaponDef> {
+		Rc::new(WeaponDef {
+			name: "sword".to_string(),
+			range: 2.0,
+			cooldown: 1.0,
+			damage: 10.0,
+			damage_type: DamageType::Normal,
+			targets_units: true,
+			targets_buildings: true,
+			projectile: None,
+		})
+	}
+
+	fn command(units: Vec<EntityId>, order: Order, queued: bool) -> Command {
+		Command {
+			player: PlayerId(0),
+			units: units,
+			order: order,
+			queued: queued,
+			formation: Formation::Box,
+		}
+	}
+
+	fn move_order(x: Real, z: Real) -> Order {
+		Order::Move { target: vec3(x, 0.0, z), group_goal: None }
+	}
+
+	fn position(world: &World, id: EntityId) -> Vector2 {
+		let position = world.poses.get(id).unwrap().position;
+		vec2(position.x, position.z)
+	}
+
+	#[test]
+	fn test_queue_replace_and_append() {
+		let mut queue = OrderQueue::new();
+		queue.push(move_order(1.0, 1.0), false);
+		queue.push(move_order(2.0, 2.0), true);
+		assert_eq!(queue.orders().len(), 2);
+
+		queue.state = OrderState::Moving;
+		queue.push(Order::Hold, false);
+		assert_eq!(queue.orders().len(), 1);
+		assert_eq!(queue.current(), Some(Order::Hold));
+		assert_eq!(queue.state, OrderState::Pending);
+	}
+
+	#[test]
+	fn test_queued_moves() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], move_order(12.0, 4.0), false));
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], move_order(12.0, 12.0), true));
+		assert_eq!(world.order_queues.get(unit).unwrap().orders().len(), 2);
+
+		simulate(&mut world, &mut navigation, &heightfield, 20);
+		// still on the first leg
+		assert!((position(&world, unit).y - 4.0).abs() < 0.001);
+
+		simulate(&mut world, &mut navigation, &heightfield, 60);
+		assert!((position(&world, unit) - vec2(12.0, 12.0)).magnitude() < 0.01);
+		assert!(world.order_queues.get(unit).unwrap().is_empty());
+
+		// orders of other players' units are ignored
+		let enemy = spawn_soldier(&mut world, PlayerId(1), vec3(20.0, 0.0, 20.0), sword());
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![enemy], Order::Stop, false));
+		assert!(world.order_queues.get(enemy).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_stop_and_hold() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], move_order(28.0, 4.0), false));
+		simulate(&mut world, &mut navigation, &heightfield, 10);
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], Order::Stop, false));
+		simulate(&mut world, &mut navigation, &heightfield, 1);
+		let stopped = position(&world, unit);
+		simulate(&mut world, &mut navigation, &heightfield, 20);
+		assert!((position(&world, unit) - stopped).magnitude() < 0.001);
+		assert!(world.order_queues.get(unit).unwrap().is_empty());
+
+		// holding units let enemies walk by
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], Order::Hold, false));
+		spawn_soldier(&mut world, PlayerId(1), vec3(stopped.x + 4.0, 0.0, stopped.y), sword());
+		simulate(&mut world, &mut navigation, &heightfield, 20);
+		assert!((position(&world, unit) - stopped).magnitude() < 0.001);
+		assert_eq!(world.order_queues.get(unit).unwrap().state, OrderState::Holding);
+	}
+
+	#[test]
+	fn test_patrol() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], Order::Patrol { target: vec3(14.0, 0.0, 4.0), group_goal: None }, false));
+
+		let mut reached_target = false;
+		let mut returned = false;
+		for _ in 0..200 {
+			simulate(&mut world, &mut navigation, &heightfield, 1);
+			let position = position(&world, unit);
+			reached_target |= (position - vec2(14.0, 4.0)).magnitude() < 0.01;
+			returned |= reached_target && (position - vec2(4.0, 4.0)).magnitude() < 0.01;
+		}
+
+		assert!(reached_target && returned);
+		assert!(!world.order_queues.get(unit).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_attack_move_engages_and_resumes() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+		let enemy = spawn_soldier(&mut world, PlayerId(1), vec3(14.0, 0.0, 10.0), sword());
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], Order::AttackMove { target: vec3(26.0, 0.0, 4.0), group_goal: None }, false));
+		simulate(&mut world, &mut navigation, &heightfield, 60);
+
+		// went for the enemy instead of the target
+		assert_eq!(world.order_queues.get(unit).unwrap().target, Some(enemy));
+		assert!((position(&world, unit) - position(&world, enemy)).magnitude() <= attack_distance(&world, unit, enemy).unwrap());
+
+		world.destroy(enemy);
+		simulate(&mut world, &mut navigation, &heightfield, 100);
+		assert!((position(&world, unit) - vec2(26.0, 4.0)).magnitude() < 0.01);
+		assert!(world.order_queues.get(unit).unwrap().is_empty());
+
+		// plain move ignores enemies
+		let other = spawn_soldier(&mut world, PlayerId(1), vec3(20.0, 0.0, 8.0), sword());
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], move_order(14.0, 4.0), false));
+		simulate(&mut world, &mut navigation, &heightfield, 60);
+		assert!((position(&world, unit) - vec2(14.0, 4.0)).magnitude() < 0.01);
+		assert!(world.is_alive(other));
+	}
+
+	#[test]
+	fn test_attack_order() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let unit = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+		// far outside of the acquisition range
+		let enemy = spawn_soldier(&mut world, PlayerId(1), vec3(28.0, 0.0, 28.0), sword());
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![unit], Order::Attack(enemy), false));
+		simulate(&mut world, &mut navigation, &heightfield, 200);
+		assert!((position(&world, unit) - position(&world, enemy)).magnitude() <= attack_distance(&world, unit, enemy).unwrap());
+
+		world.destroy(enemy);
+		simulate(&mut world, &mut navigation, &heightfield, 1);
+		assert!(world.order_queues.get(unit).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_repair_and_enter() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let worker = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+
+		let building = spawn_building(&mut world, PlayerId(0), "barracks", vec3(14.0, 0.0, 4.0));
+		world.healths.get_mut(building).unwrap().current = 80.0;
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![worker], Order::Repair(building), false));
+		simulate(&mut world, &mut navigation, &heightfield, 40);
+		assert_eq!(world.order_queues.get(worker).unwrap().state, OrderState::Working);
+
+		simulate(&mut world, &mut navigation, &heightfield, 60);
+		assert_eq!(world.healths.get(building).unwrap().current, 100.0);
+		assert!(world.order_queues.get(worker).unwrap().is_empty());
+
+		let transport = spawn_soldier(&mut world, PlayerId(0), vec3(14.0, 0.0, 14.0), sword());
+		world.transports.insert(transport, Transport::new(1));
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![worker], Order::Enter(transport), false));
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![worker], move_order(4.0, 4.0), true));
+		simulate(&mut world, &mut navigation, &heightfield, 60);
+
+		assert_eq!(world.transports.get(transport).unwrap().passengers, vec![worker]);
+		assert!(world.poses.get(worker).is_none() && !world.spatial_hash.contains(worker));
+		assert!(world.order_queues.get(worker).unwrap().is_empty());
+
+		// passengers go down with the transport
+		world.destroy(transport);
+		assert!(!world.is_alive(worker));
+	}
+
+	#[test]
+	fn test_unload() {
+		let (heightfield, mut navigation) = flat_map(32);
+		let mut world = World::new();
+		let transport = spawn_soldier(&mut world, PlayerId(0), vec3(4.0, 0.0, 4.0), sword());
+		world.transports.insert(transport, Transport::new(2));
+		let passengers: Vec<EntityId> = (0..2).map(|i| spawn_soldier(&mut world, PlayerId(0), vec3(6.0, 0.0, 4.0 + i as Real), sword())).collect();
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(passengers.clone(), Order::Enter(transport), false));
+		simulate(&mut world, &mut navigation, &heightfield, 20);
+		assert_eq!(world.transports.get(transport).unwrap().passengers, passengers);
+
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![transport], Order::Unload { target: vec3(20.0, 0.0, 20.0), group_goal: None }, false));
+		simulate(&mut world, &mut navigation, &heightfield, 1);
+		// still driving there
+		assert_eq!(world.transports.get(transport).unwrap().passengers.len(), 2);
+
+		simulate(&mut world, &mut navigation, &heightfield, 120);
+		assert!(world.transports.get(transport).unwrap().passengers.is_empty());
+		assert!(world.order_queues.get(transport).unwrap().is_empty());
+		assert!((position(&world, transport) - vec2(20.0, 20.0)).magnitude() < 1.0);
+
+		for &passenger in passengers.iter() {
+			let distance = (position(&world, passenger) - position(&world, transport)).magnitude();
+			assert!(distance > 1.0 && distance < 4.0);
+		}
+		assert!((position(&world, passengers[0]) - position(&world, passengers[1])).magnitude() > 1.0);
+
+		// an empty transport drops the order
+		issue_command(&mut world, &navigation.grid, &heightfield, &command(vec![transport], Order::Unload { target: vec3(4.0, 0.0, 4.0), group_goal: None }, false));
+		simulate(&mut world, &mut navigation, &heightfield, 1);
+		assert!(world.order_queues.get(transport).unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_command_serialization() {
+		let mut world = World::new();
+		let units: Vec<EntityId> = (0..3).map(|_| world.spawn()).collect();
+
+		let commands = vec![
+			Command {
+				player: PlayerId(2),
+				units: units.clone(),
+				order: Order::AttackMove { target: vec3(1.5, 2.0, -3.25), group_goal: Some(vec3(4.0, 0.0, 4.0)) },
+				queued: true,
+				formation: Formation::Wedge,
+			},
+			command(units.clone(), Order::Attack(units[1]), false),
+			command(vec![units[0]], Order::Gather(units[2]), true),
+			command(vec![units[0]], Order::Hold, false),
+			command(vec![units[1]], Order::Unload { target: vec3(8.0, 0.0, 2.5), group_goal: None }, true),
+		];
+
+		for command in commands.iter() {
+			let text = command.to_toml().to_string();
+			let parsed = Command::from_toml(&text.parse::<toml::Value>().unwrap()).unwrap();
+			assert_eq!(&parsed, command);
+		}
+
+		let broken = "player = 0\nunits = []\n[order]\ntype = \"fly\"\n".parse::<toml::Value>().unwrap();
+		assert!(Command::from_toml(&broken).is_err());
+	}
+
+}
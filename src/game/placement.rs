@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+use toml;
+
+use ::game::{World, EntityId, PlayerId, Pose, Owner, Health, Building, DropOff, Obstacle, Producer};
+use ::game::{DataError, parse_data, load_data, data_entries, data_real, data_strings};
+use ::navigation::{Navigation, NavGrid, Cell, CellRect};
+use ::terrain::Heightfield;
+use ::math::*;
+
+/// Navigation grid cells a building takes, read from rows of '#' for taken and '.' for free cells.
+/// Characters of a row go along x, the first row is at the lowest z.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Footprint {
+	width: usize,
+	depth: usize,
+	cells: Vec<bool>,
+}
+
+impl Footprint {
+
+	pub fn from_rows(rows: &[&str]) -> Result<Footprint, String> {
+		let width = rows.first().map_or(0, |row| row.chars().count());
+		if width == 0 {
+			return Err("footprint has no cells".to_string());
+		}
+
+		let mut cells = Vec::with_capacity(width * rows.len());
+		for row in rows {
+			if row.chars().count() != width {
+				return Err("footprint rows differ in length".to_string());
+			}
+			for cell in row.chars() {
+				match cell {
+					'#' => cells.push(true),
+					'.' => cells.push(false),
+					_ => return Err(format!("unknown footprint cell '{}'", cell)),
+				}
+			}
+		}
+
+		Ok(Footprint {
+			width: width,
+			depth: rows.len(),
+			cells: cells,
+		})
+	}
+
+	/// Footprint taking the whole rectangle
+	pub fn rect(width: usize, depth: usize) -> Footprint {
+		Footprint {
+			width: width,
+			depth: depth,
+			cells: vec![true; width * depth],
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn depth(&self) -> usize {
+		self.depth
+	}
+
+	pub fn is_taken(&self, x: usize, z: usize) -> bool {
+		self.cells[z * self.width + x]
+	}
+
+	/// Grid cell of the lowest corner when the footprint is centered as close to the position as the grid allows
+	fn origin(&self, cell_size: Real, position: Vector3) -> (isize, isize) {
+		(
+			(position.x / cell_size - self.width as Real * 0.5).round() as isize,
+			(position.z / cell_size - self.depth as Real * 0.5).round() as isize,
+		)
+	}
+
+	/// Center of the footprint snapped to the grid, so its edges go along cell edges
+	pub fn snap(&self, cell_size: Real, position: Vector3) -> Vector3 {
+		let origin = self.origin(cell_size, position);
+		vec3(
+			(origin.0 as Real + self.width as Real * 0.5) * cell_size,
+			position.y,
+			(origin.1 as Real + self.depth as Real * 0.5) * cell_size,
+		)
+	}
+
+	/// Taken cells when centered at the position, they may be outside of the grid
+	pub fn cells_at(&self, cell_size: Real, position: Vector3) -> Vec<(isize, isize)> {
+		let origin = self.origin(cell_size, position);
+		let mut cells = Vec::new();
+		for z in 0..self.depth {
+			for x in 0..self.width {
+				if self.is_taken(x, z) {
+					cells.push((origin.0 + x as isize, origin.1 + z as isize));
+				}
+			}
+		}
+		cells
+	}
+
+}
+
+/// Kind of building players place
+#[derive(Clone, PartialEq, Debug)]
+pub struct BuildingDef {
+	pub name: String,
+	pub health: Real,
+	pub footprint: Footprint,
+	/// Steepest ground the building stands on
+	pub max_slope: Rad<Real>,
+	/// Level the ground under the building when it's placed
+	pub flatten: bool,
+	/// Workers bring gathered resources here
+	pub drop_off: bool,
+}
+
+impl BuildingDef {
+
+	fn from_toml(name: &str, value: &toml::Value) -> Result<BuildingDef, DataError> {
+		let context = format!("building '{}'", name);
+
+		let footprint = match Footprint::from_rows(&data_strings(value, "footprint", &context)?) {
+			Ok(footprint) => footprint,
+			Err(message) => return Err(DataError::Format(format!("{} of {}", message, context))),
+		};
+
+		Ok(BuildingDef {
+			name: name.to_string(),
+			health: data_real(value, "health", &context)?,
+			footprint: footprint,
+			max_slope: Deg(data_real(value, "max_slope", &context)?).into(),
+			flatten: value.get("flatten").and_then(|flatten| flatten.as_bool()).unwrap_or(false),
+			drop_off: value.get("drop_off").and_then(|drop_off| drop_off.as_bool()).unwrap_or(false),
+		})
+	}
+
+}
+
+/// Buildings described in the buildings data file
+pub struct BuildingData {
+	buildings: BTreeMap<String, Rc<BuildingDef>>,
+}
+
+impl BuildingData {
+
+	pub fn empty() -> Self {
+		BuildingData {
+			buildings: BTreeMap::new(),
+		}
+	}
+
+	pub fn parse(text: &str) -> Result<Self, DataError> {
+		BuildingData::from_toml(&parse_data(text)?)
+	}
+
+	pub fn load(path: &Path) -> Result<Self, DataError> {
+		BuildingData::from_toml(&load_data(path)?)
+	}
+
+	fn from_toml(value: &toml::Value) -> Result<Self, DataError> {
+		let mut data = BuildingData::empty();
+
+		for (name, building) in data_entries(value, "buildings")? {
+			data.buildings.insert(name.clone(), Rc::new(BuildingDef::from_toml(name, building)?));
+		}
+
+		Ok(data)
+	}
+
+	pub fn building(&self, name: &str) -> Option<Rc<BuildingDef>> {
+		self.buildings.get(name).cloned()
+	}
+
+	/// All buildings ordered by name
+	pub fn buildings(&self) -> Vec<Rc<BuildingDef>> {
+		self.buildings.values().cloned().collect()
+	}
+
+}
+
+/// Why a building can't go where the player wants it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PlacementError {
+	OutsideMap,
+	TooSteep,
+	/// The entity stands in the way
+	Occupied(EntityId),
+}
+
+/// Cells the building would take at the position, or why it can't be placed there
+pub fn check_placement(world: &World, grid: &NavGrid, def: &BuildingDef, position: Vector3) -> Result<Vec<Cell>, PlacementError> {
+	let mut cells = Vec::new();
+	for (x, y) in def.footprint.cells_at(grid.cell_size(), position) {
+		if !grid.contains(x, y) {
+			return Err(PlacementError::OutsideMap);
+		}
+		cells.push((x as usize, y as usize));
+	}
+
+	if cells.iter().any(|&cell| grid.slope(cell).0 > def.max_slope.0) {
+		return Err(PlacementError::TooSteep);
+	}
+
+	for (id, obstacle) in world.obstacles.iter() {
+		if obstacle.cells.iter().any(|cell| cells.contains(cell)) {
+			return Err(PlacementError::Occupied(id));
+		}
+	}
+
+	for &cell in cells.iter() {
+		let min = vec2(cell.0 as Real, cell.1 as Real) * grid.cell_size();
+		let max = min + vec2(grid.cell_size(), grid.cell_size());
+		// other obstacles were checked by their cells
+		let blocker = world.spatial_hash.query_rect(min, max).into_iter().find(|&id| !world.obstacles.contains(id));
+		if let Some(id) = blocker {
+			return Err(PlacementError::Occupied(id));
+		}
+	}
+
+	Ok(cells)
+}
+
+/// Puts a building of the player at the position, snapped to the grid. Its cells are blocked
+/// for navigation, and the ground under it is levelled if the building wants that.
+pub fn place_building(world: &mut World, navigation: &mut Navigation, heightfield: &mut Heightfield, def: &BuildingDef, player: PlayerId, position: Vector3) -> Result<EntityId, PlacementError> {
+	let cells = check_placement(world, &navigation.grid, def, position)?;
+	let cell_size = navigation.grid.cell_size();
+	let position = def.footprint.snap(cell_size, position);
+
+	if def.flatten {
+		let half_size = vec2(def.footprint.width() as Real, def.footprint.depth() as Real) * cell_size * 0.5;
+		let center = vec2(position.x, position.z);
+		heightfield.flatten(center - half_size, center + half_size);
+
+		// slopes change around the footprint too, where the ground meets the levelled area
+		let min_x = cells.iter().map(|cell| cell.0).min().unwrap();
+		let min_y = cells.iter().map(|cell| cell.1).min().unwrap();
+		let max_x = cells.iter().map(|cell| cell.0).max().unwrap();
+		let max_y = cells.iter().map(|cell| cell.1).max().unwrap();
+		navigation.grid.update_slopes(heightfield, CellRect {
+			min: (min_x.saturating_sub(1), min_y.saturating_sub(1)),
+			max: (max_x + 2, max_y + 2),
+		});
+	}
+
+	for &cell in cells.iter() {
+		navigation.grid.set_blocked(cell, true);
+	}
+
+	let building = world.spawn();
+	world.poses.insert(building, Pose::new(vec3(position.x, heightfield.height_at(position.x, position.z), position.z)));
+	world.owners.insert(building, Owner(player));
+	world.healths.insert(building, Health::new(def.health));
+	world.buildings.insert(building, Building::new(&def.name));
+	world.obstacles.insert(building, Obstacle { cells: cells });
+	// trained units come out past the corners of the footprint
+	let exit_distance = vec2(def.footprint.width() as Real, def.footprint.depth() as Real).magnitude() * cell_size * 0.5 + 0.5;
+	world.producers.insert(building, Producer::new(exit_distance));
+	if def.drop_off {
+		world.drop_offs.insert(building, DropOff);
+	}
+
+	Ok(building)
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Movement, update_spatial_hash, update_navigation};
+
+	const DATA: &str = r#"
+		[buildings.depot]
+		health = 500
+		footprint = ['###', '###']
+		max_slope = 20
+		flatten = true
+		drop_off = true
+
+		[buildings.tower]
+		health = 200
+		footprint = ['#']
+		max_slope = 5
+	"#;
+
+	/// Flat on the left half, a 45 degree ramp on the right
+	fn ramp_map() -> (Heightfield, Navigation) {
+		let heightfield = Heightfield::from_fn(32, 32, vec3(32.0, 32.0, 32.0), |x, _| if x < 16 { 0.0 } else { (x - 16) as Real / 32.0 });
+		let navigation = Navigation::new(NavGrid::from_heightfield(&heightfield, 1.0));
+		(heightfield, navigation)
+	}
+
+	#[test]
+	fn test_parse_data() {
+		let data = BuildingData::parse(DATA).unwrap();
+
+		let depot = data.building("depot").unwrap();
+		assert_eq!((depot.footprint.width(), depot.footprint.depth()), (3, 2));
+		assert_eq!(depot.footprint, Footprint::rect(3, 2));
+		assert!(depot.flatten && depot.drop_off);
+		assert!(!data.building("tower").unwrap().flatten);
+		assert_eq!(data.buildings().len(), 2);
+
+		assert!(BuildingData::parse("[buildings.wall]\nhealth = 1\nmax_slope = 5\nfootprint = [\"##\", \"#\"]").is_err());
+		assert!(BuildingData::parse("[buildings.wall]\nhealth = 1\nmax_slope = 5\nfootprint = [\"#x\"]").is_err());
+	}
+
+	#[test]
+	fn test_shipped_data() {
+		let data = BuildingData::load(Path::new("data/buildings.toml")).unwrap();
+		assert!(!data.buildings().is_empty());
+	}
+
+	#[test]
+	fn test_footprint_cells() {
+		let footprint = Footprint::from_rows(&["##.", "###"]).unwrap();
+		assert!(!footprint.is_taken(2, 0));
+
+		// odd widths center on a cell, even depths on a cell edge
+		let snapped = footprint.snap(1.0, vec3(5.3, 1.0, 7.2));
+		assert_eq!(snapped, vec3(5.5, 1.0, 7.0));
+		assert_eq!(footprint.cells_at(1.0, vec3(5.3, 1.0, 7.2)), vec![(4, 6), (5, 6), (4, 7), (5, 7), (6, 7)]);
+		assert_eq!(footprint.cells_at(1.0, snapped), footprint.cells_at(1.0, vec3(5.3, 1.0, 7.2)));
+	}
+
+	#[test]
+	fn test_placement_validation() {
+		let (mut heightfield, mut navigation) = ramp_map();
+		let data = BuildingData::parse(DATA).unwrap();
+		let depot = data.building("depot").unwrap();
+		let mut world = World::new();
+
+		assert!(check_placement(&world, &navigation.grid, &depot, vec3(8.0, 0.0, 8.0)).is_ok());
+		assert_eq!(check_placement(&world, &navigation.grid, &depot, vec3(24.0, 0.0, 8.0)), Err(PlacementError::TooSteep));
+		assert_eq!(check_placement(&world, &navigation.grid, &depot, vec3(0.5, 0.0, 8.0)), Err(PlacementError::OutsideMap));
+
+		let unit = world.spawn();
+		world.poses.insert(unit, Pose::new(vec3(4.0, 0.0, 4.0)));
+		world.movements.insert(unit, Movement::new(5.0));
+		update_spatial_hash(&mut world);
+		assert_eq!(check_placement(&world, &navigation.grid, &depot, vec3(4.5, 0.0, 5.0)), Err(PlacementError::Occupied(unit)));
+
+		let building = place_building(&mut world, &mut navigation, &mut heightfield, &depot, PlayerId(0), vec3(8.0, 0.0, 8.0)).unwrap();
+		assert_eq!(world.poses.get(building).unwrap().position, vec3(8.5, 0.0, 8.0));
+		assert!(world.drop_offs.contains(building));
+		assert!(navigation.grid.is_blocked((7, 7)) && navigation.grid.is_blocked((9, 8)));
+		assert!(!navigation.grid.is_blocked((10, 8)));
+
+		// buildings can't overlap, but may stand side by side
+		let tower = data.building("tower").unwrap();
+		assert_eq!(check_placement(&world, &navigation.grid, &tower, vec3(9.5, 0.0, 8.5)), Err(PlacementError::Occupied(building)));
+		assert!(check_placement(&world, &navigation.grid, &tower, vec3(10.5, 0.0, 8.5)).is_ok());
+
+		// cells are freed once the building is gone
+		world.destroy(building);
+		update_navigation(&mut world, &mut navigation);
+		assert!(!navigation.grid.is_blocked((7, 7)));
+		assert!(check_placement(&world, &navigation.grid, &tower, vec3(9.5, 0.0, 8.5)).is_ok());
+	}
+
+	#[test]
+	fn test_flattening() {
+		// gentle 10 degree slope
+		let rise = (10.0 as Real).to_radians().tan();
+		let mut heightfield = Heightfield::from_fn(32, 32, vec3(32.0, 32.0 * rise, 32.0), |x, _| x as Real / 32.0);
+		let mut navigation = Navigation::new(NavGrid::from_heightfield(&heightfield, 1.0));
+		let data = BuildingData::parse(DATA).unwrap();
+		let mut world = World::new();
+
+		// too steep for a tower that doesn't level the ground
+		assert_eq!(check_placement(&world, &navigation.grid, &data.building("tower").unwrap(), vec3(16.5, 0.0, 16.5)), Err(PlacementError::TooSteep));
+
+		let slope = navigation.grid.slope((16, 15));
+		let depot = place_building(&mut world, &mut navigation, &mut heightfield, &data.building("depot").unwrap(), PlayerId(0), vec3(16.5, 0.0, 16.0)).unwrap();
+		assert!(heightfield.slope_at(16.5, 16.0).0 < 0.001);
+		// cells at the edge still blend into the ramp around
+		assert!(navigation.grid.slope((16, 15)) < slope);
+
+		let position = world.poses.get(depot).unwrap().position;
+		assert!((position.y - heightfield.height_at(15.5, 15.5)).abs() < 0.001);
+		assert!((position.y - heightfield.height_at(17.5, 16.5)).abs() < 0.001);
+	}
+
+}
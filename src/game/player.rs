@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use ::game::{World, EntityId, Stockpile};
+use ::math::*;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct PlayerId(pub u8);
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct TeamId(pub u8);
+
+/// Colors players get by id, repeating after the last one
+pub const PLAYER_COLORS: [Vector3; 8] = [
+	Vector3 { x: 0.1, y: 0.3, z: 0.9 },
+	Vector3 { x: 0.9, y: 0.1, z: 0.1 },
+	Vector3 { x: 0.1, y: 0.7, z: 0.2 },
+	Vector3 { x: 0.9, y: 0.8, z: 0.1 },
+	Vector3 { x: 0.6, y: 0.2, z: 0.8 },
+	Vector3 { x: 0.1, y: 0.8, z: 0.8 },
+	Vector3 { x: 0.9, y: 0.5, z: 0.1 },
+	Vector3 { x: 0.9, y: 0.4, z: 0.7 },
+];
+
+/// Standing of two players towards each other
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stance {
+	/// Share vision and never fight
+	Ally,
+	/// Don't fight unless told to
+	Neutral,
+	Enemy,
+}
+
+/// Someone taking part in the game
+#[derive(Clone, Debug)]
+pub struct Player {
+	pub id: PlayerId,
+	pub team: TeamId,
+	/// Entities of the player are tinted with it
+	pub color: Vector3,
+	pub stockpile: Stockpile,
+	/// Upgrades the player finished researching
+	pub researched: BTreeSet<String>,
+}
+
+impl Player {
+
+	pub fn new(id: PlayerId, team: TeamId) -> Self {
+		Player {
+			id: id,
+			team: team,
+			color: PLAYER_COLORS[id.0 as usize % PLAYER_COLORS.len()],
+			stockpile: Stockpile::new(),
+			researched: BTreeSet::new(),
+		}
+	}
+
+}
+
+/// Everyone in the game and the stances between them.
+/// Teammates are always allies, players of different teams are enemies unless set otherwise.
+pub struct Players {
+	players: BTreeMap<PlayerId, Player>,
+	/// Stances set between players of different teams, by the lower id first
+	stances: BTreeMap<(PlayerId, PlayerId), Stance>,
+}
+
+impl Players {
+
+	pub fn new() -> Self {
+		Players {
+			players: BTreeMap::new(),
+			stances: BTreeMap::new(),
+		}
+	}
+
+	/// Adds the player, replacing one with the same id
+	pub fn add(&mut self, player: Player) {
+		self.players.insert(player.id, player);
+	}
+
+	pub fn get(&self, id: PlayerId) -> Option<&Player> {
+		self.players.get(&id)
+	}
+
+	pub fn get_mut(&mut self, id: PlayerId) -> Option<&mut Player> {
+		self.players.get_mut(&id)
+	}
+
+	/// Players in id order
+	pub fn iter(&self) -> ::std::collections::btree_map::Values<PlayerId, Player> {
+		self.players.values()
+	}
+
+	pub fn iter_mut(&mut self) -> ::std::collections::btree_map::ValuesMut<PlayerId, Player> {
+		self.players.values_mut()
+	}
+
+	pub fn stance(&self, a: PlayerId, b: PlayerId) -> Stance {
+		if a == b {
+			return Stance::Ally;
+		}
+
+		let team = |id| self.players.get(&id).map(|player| player.team);
+		match (team(a), team(b)) {
+			(Some(team_a), Some(team_b)) if team_a == team_b => return Stance::Ally,
+			_ => (),
+		}
+
+		self.stances.get(&(a.min(b), a.max(b))).cloned().unwrap_or(Stance::Enemy)
+	}
+
+	/// Sets the stance both ways, it has no effect between teammates
+	pub fn set_stance(&mut self, a: PlayerId, b: PlayerId, stance: Stance) {
+		self.stances.insert((a.min(b), a.max(b)), stance);
+	}
+
+	/// Other players allied to the player, they share vision
+	pub fn allies(&self, player: PlayerId) -> Vec<PlayerId> {
+		self.players.keys()
+			.cloned()
+			.filter(|&other| other != player && self.stance(player, other) == Stance::Ally)
+			.collect()
+	}
+
+}
+
+/// How a player sees an entity
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Relationship {
+	Own,
+	Ally,
+	Enemy,
+	/// Nobody's, like resource deposits, or of a neutral player
+	Neutral,
+}
+
+/// Relationship of the player to the entity, by the stance towards its owner
+pub fn relationship(world: &World, player: PlayerId, id: EntityId) -> Relationship {
+	match world.owners.get(id) {
+		Some(owner) if owner.0 == player => Relationship::Own,
+		Some(owner) => match world.players.stance(player, owner.0) {
+			Stance::Ally => Relationship::Ally,
+			Stance::Neutral => Relationship::Neutral,
+			Stance::Enemy => Relationship::Enemy,
+		},
+		None => Relationship::Neutral,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::Owner;
+
+	#[test]
+	fn test_stances() {
+		let mut players = Players::new();
+		players.add(Player::new(PlayerId(0), TeamId(0)));
+		players.add(Player::new(PlayerId(1), TeamId(0)));
+		players.add(Player::new(PlayerId(2), TeamId(1)));
+		players.add(Player::new(PlayerId(3), TeamId(2)));
+
+		assert_eq!(players.stance(PlayerId(0), PlayerId(1)), Stance::Ally);
+		assert_eq!(players.stance(PlayerId(0), PlayerId(2)), Stance::Enemy);
+
+		players.set_stance(PlayerId(3), PlayerId(0), Stance::Neutral);
+		players.set_stance(PlayerId(2), PlayerId(3), Stance::Ally);
+		// teammates stay allies whatever is set
+		players.set_stance(PlayerId(0), PlayerId(1), Stance::Enemy);
+		assert_eq!(players.stance(PlayerId(0), PlayerId(3)), Stance::Neutral);
+		assert_eq!(players.stance(PlayerId(0), PlayerId(1)), Stance::Ally);
+		assert_eq!(players.stance(PlayerId(0), PlayerId(7)), Stance::Enemy);
+
+		assert_eq!(players.allies(PlayerId(0)), vec![PlayerId(1)]);
+		assert_eq!(players.allies(PlayerId(3)), vec![PlayerId(2)]);
+		// nobody is allied to a player the game didn't set up
+		assert!(players.allies(PlayerId(9)).is_empty());
+		assert!(players.get(PlayerId(9)).is_none());
+	}
+
+	#[test]
+	fn test_relationship() {
+		let mut world = World::new();
+		world.players.add(Player::new(PlayerId(0), TeamId(0)));
+		world.players.add(Player::new(PlayerId(1), TeamId(0)));
+		world.players.add(Player::new(PlayerId(2), TeamId(1)));
+		world.players.add(Player::new(PlayerId(3), TeamId(2)));
+		world.players.set_stance(PlayerId(0), PlayerId(3), Stance::Neutral);
+
+		let entities: Vec<EntityId> = (0..5).map(|_| world.spawn()).collect();
+		for player in 0..4 {
+			world.owners.insert(entities[player], Owner(PlayerId(player as u8)));
+		}
+
+		let relationships: Vec<Relationship> = entities.iter().map(|&id| relationship(&world, PlayerId(0), id)).collect();
+		assert_eq!(relationships, vec![Relationship::Own, Relationship::Ally, Relationship::Enemy, Relationship::Neutral, Relationship::Neutral]);
+	}
+
+}
@@ -0,0 +1,693 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::rc::Rc;
+use toml;
+
+use ::game::{World, EntityId, PlayerId, Pose, Owner, Health, Movement, OrderQueue, Order, Selectable, Worker, Vision};
+use ::game::{Weapon, WeaponDef, Armor, ArmorType, CombatData, BuildingData, Cost};
+use ::game::{DataError, parse_data, load_data, data_entries, data_real, data_amount, data_str, data_strings};
+use ::math::*;
+
+/// Most items a building has queued at once
+pub const MAX_QUEUE_LENGTH: usize = 5;
+
+/// Kind of unit buildings train
+#[derive(Clone, Debug)]
+pub struct UnitDef {
+	pub name: String,
+	pub cost: Cost,
+	/// Seconds of training
+	pub build_time: Real,
+	/// Kinds of buildings that train the unit
+	pub produced_at: Vec<String>,
+	/// Buildings the player has to own and upgrades researched before training
+	pub requires: Vec<String>,
+	pub health: Real,
+	/// Units per second
+	pub speed: Real,
+	pub weapon: Option<Rc<WeaponDef>>,
+	pub armor: Option<Armor>,
+	pub vision: Real,
+	/// Height of the eyes above the ground, for line of sight over the terrain
+	pub eye_height: Real,
+	/// Size of the box picked when selecting the unit
+	pub selection_radius: Real,
+	pub selection_height: Real,
+	/// Gathers resources and repairs buildings
+	pub worker: bool,
+}
+
+impl UnitDef {
+
+	fn from_toml(name: &str, value: &toml::Value, combat: &CombatData) -> Result<UnitDef, DataError> {
+		let context = format!("unit '{}'", name);
+
+		let weapon = match value.get("weapon") {
+			Some(_) => {
+				let weapon = data_str(value, "weapon", &context)?;
+				match combat.weapon(weapon) {
+					Some(weapon) => Some(weapon),
+					None => return Err(DataError::Format(format!("unknown weapon '{}' of {}", weapon, context))),
+				}
+			},
+			None => None,
+		};
+
+		let armor = match value.get("armor") {
+			Some(_) => {
+				let armor_type = data_str(value, "armor", &context)?;
+				let armor_type = match ArmorType::from_name(armor_type) {
+					Some(armor_type) => armor_type,
+					None => return Err(DataError::Format(format!("unknown armor type '{}' of {}", armor_type, context))),
+				};
+				let amount = if value.get("armor_amount").is_some() { data_real(value, "armor_amount", &context)? } else { 0.0 };
+				Some(Armor::new(armor_type, amount))
+			},
+			None => None,
+		};
+
+		Ok(UnitDef {
+			name: name.to_string(),
+			cost: data_cost(value, &context)?,
+			build_time: data_real(value, "build_time", &context)?,
+			produced_at: data_strings(value, "produced_at", &context)?.into_iter().map(String::from).collect(),
+			requires: data_strings(value, "requires", &context)?.into_iter().map(String::from).collect(),
+			health: data_real(value, "health", &context)?,
+			speed: data_real(value, "speed", &context)?,
+			weapon: weapon,
+			armor: armor,
+			vision: data_real(value, "vision", &context)?,
+			eye_height: data_real(value, "eye_height", &context)?,
+			selection_radius: data_real(value, "selection_radius", &context)?,
+			selection_height: data_real(value, "selection_height", &context)?,
+			worker: value.get("worker").and_then(|worker| worker.as_bool()).unwrap_or(false),
+		})
+	}
+
+}
+
+/// Upgrade researched once per player, it unlocks units and upgrades that require it
+#[derive(Clone, PartialEq, Debug)]
+pub struct UpgradeDef {
+	pub name: String,
+	pub cost: Cost,
+	/// Seconds of research
+	pub build_time: Real,
+	/// Kinds of buildings that research the upgrade
+	pub produced_at: Vec<String>,
+	/// Buildings the player has to own and upgrades researched before this one
+	pub requires: Vec<String>,
+}
+
+impl UpgradeDef {
+
+	fn from_toml(name: &str, value: &toml::Value) -> Result<UpgradeDef, DataError> {
+		let context = format!("upgrade '{}'", name);
+
+		Ok(UpgradeDef {
+			name: name.to_string(),
+			cost: data_cost(value, &context)?,
+			build_time: data_real(value, "build_time", &context)?,
+			produced_at: data_strings(value, "produced_at", &context)?.into_iter().map(String::from).collect(),
+			requires: data_strings(value, "requires", &context)?.into_iter().map(String::from).collect(),
+		})
+	}
+
+}
+
+/// Resources under the `cost` table, free when there is none
+fn data_cost(value: &toml::Value, context: &str) -> Result<Cost, DataError> {
+	let mut cost = Cost::new();
+
+	let resources = match value.get("cost") {
+		Some(resources) => resources,
+		None => return Ok(cost),
+	};
+	let context = format!("cost of {}", context);
+	let names = match resources.as_table() {
+		Some(table) => table.keys(),
+		None => return Err(DataError::Format(format!("{} has to be a table", context))),
+	};
+	for name in names {
+		cost.insert(name.clone(), data_amount(resources, name, &context)?);
+	}
+
+	Ok(cost)
+}
+
+/// Something a building makes, it waits in the production queue
+#[derive(Clone, Debug)]
+pub enum ProductionItem {
+	Unit(Rc<UnitDef>),
+	Upgrade(Rc<UpgradeDef>),
+}
+
+impl ProductionItem {
+
+	pub fn name(&self) -> &str {
+		match *self {
+			ProductionItem::Unit(ref unit) => &unit.name,
+			ProductionItem::Upgrade(ref upgrade) => &upgrade.name,
+		}
+	}
+
+	pub fn cost(&self) -> &Cost {
+		match *self {
+			ProductionItem::Unit(ref unit) => &unit.cost,
+			ProductionItem::Upgrade(ref upgrade) => &upgrade.cost,
+		}
+	}
+
+	pub fn build_time(&self) -> Real {
+		match *self {
+			ProductionItem::Unit(ref unit) => unit.build_time,
+			ProductionItem::Upgrade(ref upgrade) => upgrade.build_time,
+		}
+	}
+
+	pub fn produced_at(&self) -> &[String] {
+		match *self {
+			ProductionItem::Unit(ref unit) => &unit.produced_at,
+			ProductionItem::Upgrade(ref upgrade) => &upgrade.produced_at,
+		}
+	}
+
+	pub fn requires(&self) -> &[String] {
+		match *self {
+			ProductionItem::Unit(ref unit) => &unit.requires,
+			ProductionItem::Upgrade(ref upgrade) => &upgrade.requires,
+		}
+	}
+
+}
+
+/// Units, upgrades and the tech tree between them, described in the production data file.
+/// Weapons and buildings are looked up by name, so those files are loaded first.
+pub struct ProductionData {
+	units: BTreeMap<String, Rc<UnitDef>>,
+	upgrades: BTreeMap<String, Rc<UpgradeDef>>,
+}
+
+impl ProductionData {
+
+	pub fn empty() -> Self {
+		ProductionData {
+			units: BTreeMap::new(),
+			upgrades: BTreeMap::new(),
+		}
+	}
+
+	pub fn parse(text: &str, combat: &CombatData, buildings: &BuildingData) -> Result<Self, DataError> {
+		ProductionData::from_toml(&parse_data(text)?, combat, buildings)
+	}
+
+	pub fn load(path: &Path, combat: &CombatData, buildings: &BuildingData) -> Result<Self, DataError> {
+		ProductionData::from_toml(&load_data(path)?, combat, buildings)
+	}
+
+	fn from_toml(value: &toml::Value, combat: &CombatData, buildings: &BuildingData) -> Result<Self, DataError> {
+		let mut data = ProductionData::empty();
+
+		for (name, unit) in data_entries(value, "units")? {
+			data.units.insert(name.clone(), Rc::new(UnitDef::from_toml(name, unit, combat)?));
+		}
+		for (name, upgrade) in data_entries(value, "upgrades")? {
+			data.upgrades.insert(name.clone(), Rc::new(UpgradeDef::from_toml(name, upgrade)?));
+		}
+
+		for item in data.items() {
+			for building in item.produced_at() {
+				if buildings.building(building).is_none() {
+					return Err(DataError::Format(format!("unknown building '{}' producing '{}'", building, item.name())));
+				}
+			}
+			for required in item.requires() {
+				if buildings.building(required).is_none() && !data.upgrades.contains_key(required) {
+					return Err(DataError::Format(format!("unknown prerequisite '{}' of '{}'", required, item.name())));
+				}
+			}
+		}
+		data.check_cycles()?;
+
+		Ok(data)
+	}
+
+	/// Upgrades requiring each other could never be researched
+	fn check_cycles(&self) -> Result<(), DataError> {
+		for (name, upgrade) in self.upgrades.iter() {
+			let mut seen = BTreeSet::new();
+			let mut stack: Vec<&UpgradeDef> = vec![upgrade];
+			while let Some(current) = stack.pop() {
+				for required in current.requires.iter() {
+					if required == name {
+						return Err(DataError::Format(format!("upgrade '{}' requires itself", name)));
+					}
+					if let Some(upgrade) = self.upgrades.get(required) {
+						if seen.insert(required) {
+							stack.push(upgrade);
+						}
+					}
+				}
+			}
+		}
+		Ok(())
+	}
+
+	pub fn unit(&self, name: &str) -> Option<Rc<UnitDef>> {
+		self.units.get(name).cloned()
+	}
+
+	pub fn upgrade(&self, name: &str) -> Option<Rc<UpgradeDef>> {
+		self.upgrades.get(name).cloned()
+	}
+
+	/// Units then upgrades, each sorted by name
+	pub fn items(&self) -> Vec<ProductionItem> {
+		self.units.values().map(|unit| ProductionItem::Unit(unit.clone()))
+			.chain(self.upgrades.values().map(|upgrade| ProductionItem::Upgrade(upgrade.clone())))
+			.collect()
+	}
+
+	/// Everything the kind of building makes, whether the player can make it yet or not
+	pub fn items_at(&self, building: &str) -> Vec<ProductionItem> {
+		self.items().into_iter()
+			.filter(|item| item.produced_at().iter().any(|name| name == building))
+			.collect()
+	}
+
+}
+
+/// Why a building can't take an item into its queue
+#[derive(Clone, PartialEq, Debug)]
+pub enum ProductionError {
+	/// The entity isn't a building of a kind that makes the item
+	NotProducedHere,
+	/// Buildings and upgrades the player still lacks
+	MissingPrerequisites(Vec<String>),
+	CannotAfford,
+	QueueFull,
+	/// The upgrade is researched or being researched already
+	AlreadyResearched,
+	/// The owner of the building isn't a player in the game
+	UnknownPlayer,
+}
+
+/// Finished production, for the interface and statistics
+#[derive(Clone, PartialEq, Debug)]
+pub enum ProductionEvent {
+	Trained { producer: EntityId, unit: EntityId },
+	Researched { player: PlayerId, upgrade: String },
+}
+
+fn owns_building(world: &World, player: PlayerId, kind: &str) -> bool {
+	world.buildings.iter().any(|(id, building)| building.kind == kind && world.owners.get(id).map_or(false, |owner| owner.0 == player))
+}
+
+fn is_research_queued(world: &World, player: PlayerId, upgrade: &str) -> bool {
+	world.producers.iter()
+		.filter(|&(id, _)| world.owners.get(id).map_or(false, |owner| owner.0 == player))
+		.any(|(_, producer)| producer.queue.iter().any(|item| match *item {
+			ProductionItem::Upgrade(ref queued) => queued.name == upgrade,
+			ProductionItem::Unit(_) => false,
+		}))
+}
+
+/// Buildings the player doesn't own and upgrades not researched yet, that the item requires
+pub fn missing_prerequisites(world: &World, player: PlayerId, item: &ProductionItem) -> Vec<String> {
+	item.requires().iter()
+		.filter(|&required| !world.is_researched(player, required) && !owns_building(world, player, required))
+		.cloned()
+		.collect()
+}
+
+/// Checks whether the building can queue the item for its owner right now
+pub fn can_produce(world: &World, producer: EntityId, item: &ProductionItem) -> Result<(), ProductionError> {
+	let (building, queue_length) = match (world.buildings.get(producer), world.producers.get(producer)) {
+		(Some(building), Some(producer)) => (building.kind.as_str(), producer.queue.len()),
+		_ => return Err(ProductionError::NotProducedHere),
+	};
+	let player = match world.owners.get(producer) {
+		Some(owner) => owner.0,
+		None => return Err(ProductionError::NotProducedHere),
+	};
+	let stockpile = match world.stockpile(player) {
+		Some(stockpile) => stockpile,
+		None => return Err(ProductionError::UnknownPlayer),
+	};
+
+	if !item.produced_at().iter().any(|name| name == building) {
+		return Err(ProductionError::NotProducedHere);
+	}
+
+	if let ProductionItem::Upgrade(ref upgrade) = *item {
+		if world.is_researched(player, &upgrade.name) || is_research_queued(world, player, &upgrade.name) {
+			return Err(ProductionError::AlreadyResearched);
+		}
+	}
+
+	let missing = missing_prerequisites(world, player, item);
+	if !missing.is_empty() {
+		return Err(ProductionError::MissingPrerequisites(missing));
+	}
+
+	if queue_length >= MAX_QUEUE_LENGTH {
+		return Err(ProductionError::QueueFull);
+	}
+
+	if !stockpile.can_afford(item.cost()) {
+		return Err(ProductionError::CannotAfford);
+	}
+
+	Ok(())
+}
+
+/// Puts the item at the end of the building's queue, the owner pays for it right away
+pub fn queue_production(world: &mut World, producer: EntityId, item: ProductionItem) -> Result<(), ProductionError> {
+	can_produce(world, producer, &item)?;
+
+	let player = world.owners.get(producer).unwrap().0;
+	world.stockpile_mut(player).unwrap().spend(item.cost());
+	world.producers.get_mut(producer).unwrap().queue.push_back(item);
+
+	Ok(())
+}
+
+/// Takes the item out of the queue and gives its full cost back. Cancelling the item
+/// in progress starts the next one from scratch.
+pub fn cancel_production(world: &mut World, producer: EntityId, index: usize) -> Option<ProductionItem> {
+	let item = {
+		let building = world.producers.get_mut(producer)?;
+		let item = building.queue.remove(index)?;
+		if index == 0 {
+			building.progress = 0.0;
+		}
+		item
+	};
+
+	// the cost is gone with the player when they left the game
+	if let Some(stockpile) = world.owners.get(producer).cloned().and_then(|owner| world.stockpile_mut(owner.0)) {
+		for (resource, &amount) in item.cost().iter() {
+			stockpile.add(resource, amount);
+		}
+	}
+
+	Some(item)
+}
+
+/// Point units trained by the building walk to, None keeps them at the building
+pub fn set_rally_point(world: &mut World, producer: EntityId, point: Option<Vector3>) -> bool {
+	match world.producers.get_mut(producer) {
+		Some(producer) => {
+			producer.rally_point = point;
+			true
+		},
+		None => false,
+	}
+}
+
+/// Works on the first item of every queue, called once per tick.
+/// Buildings of owners that aren't players in the game hold their queues.
+pub fn update_production(world: &mut World, dt: Real) {
+	for id in world.producers.ids() {
+		let player = match world.owners.get(id) {
+			Some(owner) if world.players.get(owner.0).is_some() => owner.0,
+			_ => continue,
+		};
+
+		let item = {
+			let producer = world.producers.get_mut(id).unwrap();
+			let build_time = match producer.queue.front() {
+				Some(item) => item.build_time(),
+				None => continue,
+			};
+
+			producer.progress += dt;
+			if producer.progress < build_time {
+				continue;
+			}
+			// time left over goes to the next item
+			producer.progress -= build_time;
+			producer.queue.pop_front().unwrap()
+		};
+
+		match item {
+			ProductionItem::Unit(unit) => {
+				let trained = spawn_trained_unit(world, id, &unit, player);
+				world.push_production_event(ProductionEvent::Trained { producer: id, unit: trained });
+			},
+			ProductionItem::Upgrade(upgrade) => {
+				world.players.get_mut(player).unwrap().researched.insert(upgrade.name.clone());
+				world.push_production_event(ProductionEvent::Researched { player: player, upgrade: upgrade.name.clone() });
+			},
+		}
+	}
+}
+
+/// Puts the unit next to the building on the side of the rally point, and sends it there
+fn spawn_trained_unit(world: &mut World, producer: EntityId, def: &UnitDef, player: PlayerId) -> EntityId {
+	let (rally_point, exit_distance) = {
+		let producer = world.producers.get(producer).unwrap();
+		(producer.rally_point, producer.exit_distance)
+	};
+	let center = world.poses.get(producer).map_or(vec3(0.0, 0.0, 0.0), |pose| pose.position);
+
+	let direction = match rally_point {
+		Some(point) if vec2(point.x - center.x, point.z - center.z).magnitude2() > 0.0 => vec3(point.x - center.x, 0.0, point.z - center.z).normalize(),
+		_ => vec3(0.0, 0.0, 1.0),
+	};
+
+	let unit = world.spawn();
+
+	let mut pose = Pose::new(center + direction * exit_distance);
+	pose.heading = Rad(direction.x.atan2(direction.z));
+	world.poses.insert(unit, pose);
+	world.owners.insert(unit, Owner(player));
+	world.healths.insert(unit, Health::new(def.health));
+	world.movements.insert(unit, Movement::new(def.speed));
+	if let Some(ref weapon) = def.weapon {
+		world.weapons.insert(unit, Weapon::new(weapon.clone()));
+	}
+	if let Some(armor) = def.armor {
+		world.armors.insert(unit, armor);
+	}
+	if def.worker {
+		world.workers.insert(unit, Worker::new());
+	}
+	world.selectables.insert(unit, Selectable::new(def.selection_radius, def.selection_height));
+	world.visions.insert(unit, Vision::new(def.vision, def.eye_height));
+
+	let mut orders = OrderQueue::new();
+	if let Some(point) = rally_point {
+		orders.push(Order::Move { target: point, group_goal: None }, false);
+	}
+	world.order_queues.insert(unit, orders);
+
+	unit
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::Producer;
+	use ::game::test_util;
+
+	const COMBAT_DATA: &str = r#"
+		[weapons.rifle]
+		range = 5
+		cooldown = 1
+		damage = 10
+		damage_type = "piercing"
+		targets = ["units"]
+	"#;
+
+	const BUILDING_DATA: &str = r#"
+		[buildings.barracks]
+		health = 500
+		footprint = ['###', '###', '###']
+		max_slope = 20
+
+		[buildings.tower]
+		health = 200
+		footprint = ['#']
+		max_slope = 5
+	"#;
+
+	const DATA: &str = r#"
+		[units.soldier]
+		cost = { gold = 40 }
+		build_time = 2
+		produced_at = ["barracks"]
+		health = 100
+		speed = 5
+		vision = 10
+		eye_height = 2
+		selection_radius = 1
+		selection_height = 2
+		weapon = "rifle"
+		armor = "light"
+		armor_amount = 1
+
+		[units.grenadier]
+		cost = { gold = 60, wood = 20 }
+		build_time = 3
+		produced_at = ["barracks"]
+		requires = ["tower", "explosives"]
+		health = 120
+		speed = 4
+		vision = 10
+		eye_height = 2
+		selection_radius = 1
+		selection_height = 2
+
+		[upgrades.explosives]
+		cost = { gold = 50 }
+		build_time = 5
+		produced_at = ["barracks"]
+	"#;
+
+	fn parse(text: &str) -> Result<ProductionData, DataError> {
+		let combat = CombatData::parse(COMBAT_DATA).unwrap();
+		let buildings = BuildingData::parse(BUILDING_DATA).unwrap();
+		ProductionData::parse(text, &combat, &buildings)
+	}
+
+	fn spawn_producer(world: &mut World, player: PlayerId, kind: &str, position: Vector3) -> EntityId {
+		let building = test_util::spawn_building(world, player, kind, position);
+		world.producers.insert(building, Producer::new(2.0));
+		building
+	}
+
+	/// Quarter second steps, so build times add up without rounding errors
+	fn simulate(world: &mut World, seconds: Real) {
+		for _ in 0..(seconds * 4.0).round() as usize {
+			update_production(world, 0.25);
+		}
+	}
+
+	#[test]
+	fn test_parse_data() {
+		let data = parse(DATA).unwrap();
+
+		let soldier = data.unit("soldier").unwrap();
+		assert_eq!(soldier.cost.get("gold"), Some(&40));
+		assert_eq!(soldier.weapon.as_ref().unwrap().name, "rifle");
+		assert_eq!(soldier.armor.unwrap().armor_type, ArmorType::Light);
+		assert!(!soldier.worker);
+		assert_eq!(soldier.selection_radius, 1.0);
+		assert!(parse(&DATA.replace("eye_height = 2", "")).is_err());
+		assert_eq!(data.upgrade("explosives").unwrap().requires, Vec::<String>::new());
+
+		let names: Vec<String> = data.items_at("barracks").iter().map(|item| item.name().to_string()).collect();
+		assert_eq!(names, vec!["grenadier", "soldier", "explosives"]);
+		assert!(data.items_at("tower").is_empty());
+
+		let unit = "build_time = 1\nhealth = 1\nspeed = 1\nvision = 1\neye_height = 1\nselection_radius = 1\nselection_height = 1";
+		assert!(parse(&format!("[units.a]\n{}\nweapon = \"laser\"", unit)).is_err());
+		assert!(parse(&format!("[units.a]\n{}\nproduced_at = [\"castle\"]", unit)).is_err());
+		assert!(parse(&format!("[units.a]\n{}\nrequires = [\"magic\"]", unit)).is_err());
+		assert!(parse(&format!("[units.a]\n{}\ncost = {{ gold = -5 }}", unit)).is_err());
+		assert!(parse("[upgrades.a]\nbuild_time = 1\nrequires = [\"b\"]\n[upgrades.b]\nbuild_time = 1\nrequires = [\"a\"]").is_err());
+	}
+
+	#[test]
+	fn test_shipped_data() {
+		let combat = CombatData::load(Path::new("data/combat.toml")).unwrap();
+		let buildings = BuildingData::load(Path::new("data/buildings.toml")).unwrap();
+		let data = ProductionData::load(Path::new("data/production.toml"), &combat, &buildings).unwrap();
+		assert!(!data.items().is_empty());
+	}
+
+	#[test]
+	fn test_queue_and_cancel() {
+		let data = parse(DATA).unwrap();
+		let soldier = ProductionItem::Unit(data.unit("soldier").unwrap());
+		let mut world = World::new();
+		let barracks = spawn_producer(&mut world, PlayerId(0), "barracks", vec3(0.0, 0.0, 0.0));
+		let tower = spawn_producer(&mut world, PlayerId(0), "tower", vec3(5.0, 0.0, 0.0));
+
+		assert_eq!(can_produce(&world, barracks, &soldier), Err(ProductionError::UnknownPlayer));
+		test_util::add_players(&mut world, 1);
+		assert_eq!(can_produce(&world, barracks, &soldier), Err(ProductionError::CannotAfford));
+		world.stockpile_mut(PlayerId(0)).unwrap().add("gold", 100);
+		assert_eq!(can_produce(&world, tower, &soldier), Err(ProductionError::NotProducedHere));
+
+		assert!(queue_production(&mut world, barracks, soldier.clone()).is_ok());
+		assert!(queue_production(&mut world, barracks, soldier.clone()).is_ok());
+		assert_eq!(queue_production(&mut world, barracks, soldier.clone()), Err(ProductionError::CannotAfford));
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("gold"), 20);
+
+		// the first soldier is half done, cancelling it starts the second one over
+		simulate(&mut world, 1.0);
+		assert_eq!(cancel_production(&mut world, barracks, 0).map(|item| item.name().to_string()), Some("soldier".to_string()));
+		assert_eq!(world.stockpile(PlayerId(0)).unwrap().amount("gold"), 60);
+		assert_eq!(world.producers.get(barracks).unwrap().progress, 0.0);
+		assert!(cancel_production(&mut world, barracks, 1).is_none());
+
+		world.stockpile_mut(PlayerId(0)).unwrap().add("gold", 1000);
+		while world.producers.get(barracks).unwrap().queue.len() < MAX_QUEUE_LENGTH {
+			queue_production(&mut world, barracks, soldier.clone()).unwrap();
+		}
+		assert_eq!(queue_production(&mut world, barracks, soldier.clone()), Err(ProductionError::QueueFull));
+	}
+
+	#[test]
+	fn test_training() {
+		let data = parse(DATA).unwrap();
+		let mut world = World::new();
+		test_util::add_players(&mut world, 1);
+		let barracks = spawn_producer(&mut world, PlayerId(0), "barracks", vec3(10.0, 0.0, 10.0));
+		world.stockpile_mut(PlayerId(0)).unwrap().add("gold", 100);
+		queue_production(&mut world, barracks, ProductionItem::Unit(data.unit("soldier").unwrap())).unwrap();
+		assert!(set_rally_point(&mut world, barracks, Some(vec3(20.0, 0.0, 10.0))));
+
+		simulate(&mut world, 1.5);
+		assert!(world.take_production_events().is_empty());
+
+		simulate(&mut world, 0.5);
+		let events = world.take_production_events();
+		let unit = match events.as_slice() {
+			&[ProductionEvent::Trained { producer, unit }] if producer == barracks => unit,
+			_ => panic!("unexpected events {:?}", events),
+		};
+
+		// out on the side of the rally point, heading there
+		assert_eq!(world.poses.get(unit).unwrap().position, vec3(12.0, 0.0, 10.0));
+		assert_eq!(world.owners.get(unit), Some(&Owner(PlayerId(0))));
+		assert_eq!(world.healths.get(unit).unwrap().max, 100.0);
+		assert!(world.weapons.contains(unit) && world.armors.contains(unit));
+		assert_eq!(world.order_queues.get(unit).unwrap().current(), Some(Order::Move { target: vec3(20.0, 0.0, 10.0), group_goal: None }));
+		assert!(world.producers.get(barracks).unwrap().queue.is_empty());
+	}
+
+	#[test]
+	fn test_tech_tree() {
+		let data = parse(DATA).unwrap();
+		let grenadier = ProductionItem::Unit(data.unit("grenadier").unwrap());
+		let explosives = ProductionItem::Upgrade(data.upgrade("explosives").unwrap());
+		let mut world = World::new();
+		test_util::add_players(&mut world, 2);
+		let barracks = spawn_producer(&mut world, PlayerId(0), "barracks", vec3(0.0, 0.0, 0.0));
+		world.stockpile_mut(PlayerId(0)).unwrap().add("gold", 1000);
+		world.stockpile_mut(PlayerId(0)).unwrap().add("wood", 1000);
+
+		assert_eq!(can_produce(&world, barracks, &grenadier), Err(ProductionError::MissingPrerequisites(vec!["tower".to_string(), "explosives".to_string()])));
+
+		// someone else's tower doesn't count, an own one does though it makes nothing
+		test_util::spawn_building(&mut world, PlayerId(1), "tower", vec3(5.0, 0.0, 0.0));
+		assert_eq!(missing_prerequisites(&world, PlayerId(0), &grenadier).len(), 2);
+		test_util::spawn_building(&mut world, PlayerId(0), "tower", vec3(-5.0, 0.0, 0.0));
+		assert_eq!(missing_prerequisites(&world, PlayerId(0), &grenadier), vec!["explosives".to_string()]);
+
+		queue_production(&mut world, barracks, explosives.clone()).unwrap();
+		assert_eq!(can_produce(&world, barracks, &explosives), Err(ProductionError::AlreadyResearched));
+
+		simulate(&mut world, 5.0);
+		assert!(world.is_researched(PlayerId(0), "explosives"));
+		assert!(!world.is_researched(PlayerId(1), "explosives"));
+		assert_eq!(world.take_production_events(), vec![ProductionEvent::Researched { player: PlayerId(0), upgrade: "explosives".to_string() }]);
+		assert_eq!(can_produce(&world, barracks, &explosives), Err(ProductionError::AlreadyResearched));
+		assert_eq!(can_produce(&world, barracks, &grenadier), Ok(()));
+	}
+
+}
@@ -0,0 +1,410 @@
+use ::game::{World, EntityId, PlayerId, CombatData, CombatEvent, DamageType, WeaponDef, ProjectileDef};
+use ::game::{Relationship, apply_damage, body_radius, body_height, relationship};
+use ::terrain::Heightfield;
+use ::math::*;
+
+/// Units per second squared, pulls projectiles down
+pub const GRAVITY: Real = 9.81;
+
+/// Projectiles move at most this far between hit checks, so they don't skip thin units
+const MAX_STEP_LENGTH: Real = 0.25;
+
+/// Projectiles still flying after this many seconds are dropped
+const MAX_FLIGHT_TIME: Real = 20.0;
+
+/// Steps refining where a projectile crossed the terrain
+const IMPACT_REFINE_STEPS: usize = 8;
+
+/// Shell in flight, simulated with gravity until it hits the terrain or a unit
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Projectile {
+	pub position: Vector3,
+	pub velocity: Vector3,
+	/// Entity that fired it, never hit by its own projectile
+	pub source: Option<EntityId>,
+	/// Player that fired it, the projectile passes by entities of the player and allies and splash spares them
+	pub player: Option<PlayerId>,
+	pub damage: Real,
+	pub damage_type: DamageType,
+	pub splash_radius: Real,
+	/// Seconds since launch
+	pub age: Real,
+}
+
+/// Elevation to fire at with the speed to hit a point `distance` away and `height` higher.
+/// There are two solutions, the high one lobs the shot. None when the point is out of reach.
+pub fn launch_angle(distance: Real, height: Real, speed: Real, high_arc: bool) -> Option<Rad<Real>> {
+	let speed2 = speed * speed;
+
+	if distance < 0.0001 {
+		// straight up or down
+		return if height <= 0.0 {
+			Some(Rad(-::std::f32::consts::FRAC_PI_2))
+		} else if speed2 >= 2.0 * GRAVITY * height {
+			Some(Rad(::std::f32::consts::FRAC_PI_2))
+		} else {
+			None
+		};
+	}
+
+	let discriminant = speed2 * speed2 - GRAVITY * (GRAVITY * distance * distance + 2.0 * height * speed2);
+	if discriminant < 0.0 {
+		return None;
+	}
+
+	let root = if high_arc { discriminant.sqrt() } else { -discriminant.sqrt() };
+	Some(Rad(((speed2 + root) / (GRAVITY * distance)).atan()))
+}
+
+/// Launch velocity with the speed that brings a projectile from `from` to `to`
+pub fn launch_velocity(from: Vector3, to: Vector3, speed: Real, high_arc: bool) -> Option<Vector3> {
+	let offset = to - from;
+	let horizontal = vec2(offset.x, offset.z);
+	let distance = horizontal.magnitude();
+
+	let angle = launch_angle(distance, offset.y, speed, high_arc)?;
+	let direction = if distance > 0.0 { horizontal / distance } else { vec2(0.0, 0.0) };
+	let forward = angle.0.cos() * speed;
+
+	Some(vec3(direction.x * forward, angle.0.sin() * speed, direction.y * forward))
+}
+
+/// Launches a projectile from `from` that comes down at `to`, false when `to` is out of reach
+pub fn launch_projectile(world: &mut World, source: Option<EntityId>, from: Vector3, to: Vector3, damage: Real, damage_type: DamageType, def: &ProjectileDef) -> bool {
+	let velocity = match launch_velocity(from, to, def.speed, def.high_arc) {
+		Some(velocity) => velocity,
+		None => return false,
+	};
+
+	let player = source.and_then(|source| world.owners.get(source)).map(|owner| owner.0);
+	world.projectiles.push(Projectile {
+		position: from,
+		velocity: velocity,
+		source: source,
+		player: player,
+		damage: damage,
+		damage_type: damage_type,
+		splash_radius: def.splash_radius,
+		age: 0.0,
+	});
+
+	true
+}
+
+/// Fires the projectile of the weapon from the middle of the shooter at the middle of the target
+pub fn fire_projectile(world: &mut World, id: EntityId, target: EntityId, weapon: &WeaponDef) -> bool {
+	let def = match weapon.projectile {
+		Some(def) => def,
+		None => return false,
+	};
+
+	let (from, to) = match (world.poses.get(id), world.poses.get(target)) {
+		(Some(shooter), Some(pose)) => (
+			shooter.position + vec3(0.0, body_height(world, id) * 0.5, 0.0),
+			pose.position + vec3(0.0, body_height(world, target) * 0.5, 0.0),
+		),
+		_ => return false,
+	};
+
+	launch_projectile(world, Some(id), from, to, weapon.damage, weapon.damage_type, &def)
+}
+
+enum Flight {
+	Flying,
+	Impact(Vector3, Option<EntityId>),
+	/// Left the map or flew for too long
+	Lost,
+}
+
+/// Moves projectiles and blows up the ones that hit something, called once per tick after combat
+pub fn update_projectiles(world: &mut World, heightfield: &Heightfield, data: &CombatData, dt: Real) {
+	let projectiles = ::std::mem::replace(&mut world.projectiles, Vec::new());
+	let mut flying = Vec::with_capacity(projectiles.len());
+
+	for mut projectile in projectiles {
+		match fly(world, heightfield, &mut projectile, dt) {
+			Flight::Flying => flying.push(projectile),
+			Flight::Impact(position, hit) => explode(world, data, &projectile, position, hit),
+			Flight::Lost => {}
+		}
+	}
+
+	world.projectiles = flying;
+}
+
+fn fly(world: &World, heightfield: &Heightfield, projectile: &mut Projectile, dt: Real) -> Flight {
+	let gravity = vec3(0.0, -GRAVITY, 0.0);
+	let steps = ((projectile.velocity.magnitude() * dt / MAX_STEP_LENGTH).ceil() as usize).max(1);
+	let step = dt / steps as Real;
+
+	for _ in 0..steps {
+		let previous = projectile.position;
+		projectile.position += projectile.velocity * step + gravity * (0.5 * step * step);
+		projectile.velocity += gravity * step;
+
+		let position = projectile.position;
+		if !heightfield.contains(position.x, position.z) {
+			return Flight::Lost;
+		}
+
+		if let Some(hit) = body_at(world, position, projectile) {
+			return Flight::Impact(position, Some(hit));
+		}
+
+		if position.y <= heightfield.height_at(position.x, position.z) {
+			return Flight::Impact(terrain_crossing(heightfield, previous, position), None);
+		}
+	}
+
+	projectile.age += dt;
+	if projectile.age > MAX_FLIGHT_TIME {
+		Flight::Lost
+	} else {
+		Flight::Flying
+	}
+}
+
+/// Whether the projectile leaves the entity alone, it's the shooter or on the shooter's side
+fn is_spared(world: &World, projectile: &Projectile, id: EntityId) -> bool {
+	if Some(id) == projectile.source {
+		return true;
+	}
+	match projectile.player.map(|player| relationship(world, player, id)) {
+		Some(Relationship::Own) | Some(Relationship::Ally) => true,
+		_ => false,
+	}
+}
+
+/// Entity the projectile can hit whose body contains the point, lowest id first
+fn body_at(world: &World, point: Vector3, projectile: &Projectile) -> Option<EntityId> {
+	world.spatial_hash.query_radius(vec2(point.x, point.z), 0.0).into_iter()
+		.filter(|&id| !is_spared(world, projectile, id))
+		.find(|&id| match world.poses.get(id) {
+			Some(pose) => point.y >= pose.position.y && point.y <= pose.position.y + body_height(world, id),
+			None => false,
+		})
+}
+
+/// Point on the segment from above the terrain at `above` to below it at `below` where it meets the ground
+fn terrain_crossing(heightfield: &Heightfield, above: Vector3, below: Vector3) -> Vector3 {
+	let clearance = |point: Vector3| point.y - heightfield.height_at(point.x, point.z);
+
+	let mut above = above;
+	let mut below = below;
+	for _ in 0..IMPACT_REFINE_STEPS {
+		let middle = (above + below) * 0.5;
+		if clearance(middle) > 0.0 {
+			above = middle;
+		} else {
+			below = middle;
+		}
+	}
+
+	let point = (above + below) * 0.5;
+	vec3(point.x, heightfield.height_at(point.x, point.z), point.z)
+}
+
+/// Hurts the entity hit directly in full and everything around with damage falling off
+/// linearly to nothing at the edge of the splash
+fn explode(world: &mut World, data: &CombatData, projectile: &Projectile, position: Vector3, hit: Option<EntityId>) {
+	world.push_combat_event(CombatEvent::Impact { position: position, splash_radius: projectile.splash_radius, hit: hit });
+
+	if let Some(hit) = hit {
+		apply_damage(world, data, hit, projectile.source, projectile.damage, projectile.damage_type);
+	}
+
+	if projectile.splash_radius <= 0.0 {
+		return;
+	}
+
+	let center = vec2(position.x, position.z);
+	for id in world.spatial_hash.query_radius(center, projectile.splash_radius) {
+		if Some(id) == hit || !world.healths.contains(id) || is_spared(world, projectile, id) {
+			continue;
+		}
+
+		let position = match world.poses.get(id) {
+			Some(pose) => vec2(pose.position.x, pose.position.z),
+			None => continue,
+		};
+		let edge_distance = ((position - center).magnitude() - body_radius(world, id)).max(0.0);
+		let falloff = 1.0 - edge_distance / projectile.splash_radius;
+
+		if falloff > 0.0 {
+			apply_damage(world, data, id, projectile.source, projectile.damage * falloff, projectile.damage_type);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Weapon, update_spatial_hash};
+	use ::game::test_util::{flat_heightfield, spawn_unit, simulate_combat};
+
+	const DATA: &str = r#"
+		[weapons.mortar]
+		range = 30.0
+		cooldown = 5.0
+		damage = 40.0
+		damage_type = "explosive"
+		targets = ["units"]
+		projectile_speed = 20.0
+		splash_radius = 3.0
+		high_arc = true
+	"#;
+
+	/// Where a free flying projectile comes back down to the height it's aimed at
+	fn landing_point(from: Vector3, velocity: Vector3, height: Real) -> Vector3 {
+		let dt = 0.0001;
+		let mut position = from;
+		let mut velocity = velocity;
+		loop {
+			position += velocity * dt;
+			velocity.y -= GRAVITY * dt;
+			if velocity.y < 0.0 && position.y <= height {
+				return position;
+			}
+		}
+	}
+
+	#[test]
+	fn test_launch_solution() {
+		let from = vec3(0.0, 1.0, 0.0);
+		let to = vec3(12.0, 3.0, 16.0);
+
+		let low = launch_velocity(from, to, 20.0, false).unwrap();
+		let high = launch_velocity(from, to, 20.0, true).unwrap();
+		assert!(high.y > low.y);
+		assert!((low.magnitude() - 20.0).abs() < 0.001);
+
+		for &velocity in [low, high].iter() {
+			let landing = landing_point(from, velocity, to.y);
+			assert!((vec2(landing.x, landing.z) - vec2(to.x, to.z)).magnitude() < 0.05);
+		}
+
+		// 45 degrees reaches the furthest
+		let max_distance = 20.0 * 20.0 / GRAVITY;
+		let angle = launch_angle(max_distance, 0.0, 20.0, false).unwrap();
+		assert!((angle.0 - ::std::f32::consts::FRAC_PI_4).abs() < 0.01);
+		assert!(launch_angle(max_distance + 1.0, 0.0, 20.0, false).is_none());
+	}
+
+	#[test]
+	fn test_terrain_impact() {
+		// ramp going up along X
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 8.0, 64.0), |x, _| x as Real / 64.0);
+		let data = CombatData::empty();
+		let mut world = World::new();
+
+		let def = ProjectileDef { speed: 25.0, splash_radius: 0.0, high_arc: false };
+		let target = vec3(40.0, heightfield.height_at(40.0, 20.0), 20.0);
+		assert!(launch_projectile(&mut world, None, vec3(10.0, 2.0, 20.0), target, 10.0, DamageType::Normal, &def));
+
+		simulate_combat(&mut world, &heightfield, &data, 100);
+		assert!(world.projectiles.is_empty());
+
+		let events = world.take_combat_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			CombatEvent::Impact { position, hit, .. } => {
+				assert_eq!(hit, None);
+				assert!((position - target).magnitude() < 0.1);
+			}
+			_ => panic!("expected an impact"),
+		}
+	}
+
+	#[test]
+	fn test_unit_in_the_way() {
+		let heightfield = flat_heightfield(64);
+		let data = CombatData::empty();
+		let mut world = World::new();
+		let wall = spawn_unit(&mut world, PlayerId(1), vec3(20.0, 0.0, 20.0));
+
+		// flat shot straight through the unit
+		let def = ProjectileDef { speed: 40.0, splash_radius: 0.0, high_arc: false };
+		assert!(launch_projectile(&mut world, None, vec3(10.0, 0.5, 20.0), vec3(30.0, 0.0, 20.0), 10.0, DamageType::Normal, &def));
+		simulate_combat(&mut world, &heightfield, &data, 40);
+
+		assert_eq!(world.healths.get(wall).unwrap().current, 90.0);
+		match world.take_combat_events()[0] {
+			CombatEvent::Impact { position, hit, .. } => {
+				assert_eq!(hit, Some(wall));
+				assert!((position.x - 19.5).abs() < MAX_STEP_LENGTH);
+			}
+			_ => panic!("expected an impact"),
+		}
+	}
+
+	#[test]
+	fn test_friends_in_the_way() {
+		let heightfield = flat_heightfield(64);
+		let data = CombatData::empty();
+		let mut world = World::new();
+		let shooter = spawn_unit(&mut world, PlayerId(0), vec3(10.0, 0.0, 20.0));
+		let friend = spawn_unit(&mut world, PlayerId(0), vec3(16.0, 0.0, 20.0));
+		let enemy = spawn_unit(&mut world, PlayerId(1), vec3(22.0, 0.0, 20.0));
+
+		// the shot flies past the friend and hits the enemy behind
+		let def = ProjectileDef { speed: 40.0, splash_radius: 0.0, high_arc: false };
+		assert!(launch_projectile(&mut world, Some(shooter), vec3(10.0, 0.5, 20.0), vec3(30.0, 0.0, 20.0), 10.0, DamageType::Normal, &def));
+		simulate_combat(&mut world, &heightfield, &data, 40);
+
+		assert_eq!(world.healths.get(friend).unwrap().current, 100.0);
+		assert_eq!(world.healths.get(enemy).unwrap().current, 90.0);
+	}
+
+	#[test]
+	fn test_splash_falloff() {
+		let data = CombatData::empty();
+		let mut world = World::new();
+		let shooter = spawn_unit(&mut world, PlayerId(0), vec3(10.0, 0.0, 30.0));
+		let friend = spawn_unit(&mut world, PlayerId(0), vec3(31.0, 0.0, 30.0));
+		// body edges 0, 1 and 2 away from the impact, then one out of reach
+		let enemies: Vec<EntityId> = [vec3(30.5, 0.0, 30.0), vec3(28.5, 0.0, 30.0), vec3(30.0, 0.0, 32.5), vec3(34.0, 0.0, 30.0)].iter()
+			.map(|&position| spawn_unit(&mut world, PlayerId(1), position))
+			.collect();
+		update_spatial_hash(&mut world);
+
+		let projectile = Projectile {
+			position: vec3(30.0, 0.0, 30.0),
+			velocity: vec3(0.0, 0.0, 0.0),
+			source: Some(shooter),
+			player: Some(PlayerId(0)),
+			damage: 30.0,
+			damage_type: DamageType::Explosive,
+			splash_radius: 3.0,
+			age: 0.0,
+		};
+		explode(&mut world, &data, &projectile, vec3(30.0, 0.0, 30.0), None);
+
+		let damage = |id: EntityId| 100.0 - world.healths.get(id).unwrap().current;
+		assert!((damage(enemies[0]) - 30.0).abs() < 0.001);
+		assert!((damage(enemies[1]) - 20.0).abs() < 0.001);
+		assert!((damage(enemies[2]) - 10.0).abs() < 0.001);
+		assert_eq!(damage(enemies[3]), 0.0);
+		assert_eq!(damage(friend), 0.0);
+	}
+
+	#[test]
+	fn test_weapon_fires_projectiles() {
+		let heightfield = flat_heightfield(64);
+		let data = CombatData::parse(DATA).unwrap();
+		let mut world = World::new();
+		let mortar = spawn_unit(&mut world, PlayerId(0), vec3(10.0, 0.0, 10.0));
+		world.weapons.insert(mortar, Weapon::new(data.weapon("mortar").unwrap()));
+		let target = spawn_unit(&mut world, PlayerId(1), vec3(30.0, 0.0, 25.0));
+
+		simulate_combat(&mut world, &heightfield, &data, 2);
+		assert_eq!(world.projectiles.len(), 1);
+		assert_eq!(world.healths.get(target).unwrap().current, 100.0);
+
+		simulate_combat(&mut world, &heightfield, &data, 88);
+		assert!(world.projectiles.is_empty());
+		assert_eq!(world.healths.get(target).unwrap().current, 60.0);
+		assert!(world.take_combat_events().iter().any(|event| *event == CombatEvent::Hit { target: target, attacker: Some(mortar), damage: 40.0 }));
+	}
+
+}
@@ -0,0 +1,96 @@
+use ::game::{World, FogOfWar, PlayerId, Visibility, CombatEvent, is_entity_visible};
+use ::gfx::scene::{Scene, MeshInstance, FogMap, Flash, FOG_EXPLORED};
+use ::math::*;
+
+/// Orange light where projectiles come down
+const IMPACT_FLASH_COLOR: Vector3 = Vector3 { x: 4.0, y: 2.0, z: 0.5 };
+
+/// Seconds an impact flash takes to fade out
+const IMPACT_FLASH_DURATION: Real = 0.4;
+
+/// Impacts without splash still light up this far around them
+const IMPACT_FLASH_MIN_RADIUS: Real = 1.5;
+
+/// Creates, moves and removes mesh instances so the graphics scene
+/// shows the current state of the world.
+/// Called once per simulation tick, so the scene keeps the last two
+/// tick spatials to interpolate between.
+pub fn sync_graphics_scene(world: &mut World, scene: &mut Scene) {
+	for instance in world.take_removed_mesh_instances() {
+		scene.remove_mesh_instance(&instance);
+	}
+
+	for (id, renderable) in world.renderables.iter_mut() {
+		let spatial = match world.poses.get(id) {
+			Some(pose) => pose.spatial(),
+			None => {
+				// taken off the map, e.g. loaded into a transport
+				if let Some(instance) = renderable.instance.take() {
+					scene.remove_mesh_instance(&instance);
+				}
+				continue;
+			}
+		};
+
+		// the owner or their color can change after the instance is made
+		let team_color = match world.owners.get(id) {
+			Some(owner) => world.players.get(owner.0).map(|player| player.color),
+			None => None,
+		};
+
+		if renderable.instance.is_none() {
+			let mut instance = MeshInstance::new(renderable.mesh.clone(), spatial);
+			instance.team_color = team_color;
+			renderable.instance = Some(scene.add_mesh_instance(instance));
+		} else if let Some(ref instance) = renderable.instance {
+			let mut instance = instance.0.borrow_mut();
+			instance.update_spatial(spatial);
+			instance.team_color = team_color;
+		}
+	}
+}
+
+/// Shows the fog of war of the player: hands the grid to the renderer
+/// and hides mesh instances of entities the player doesn't see
+pub fn sync_fog_of_war(world: &World, fog: &FogOfWar, player: PlayerId, scene: &mut Scene) {
+	for (id, renderable) in world.renderables.iter() {
+		if let Some(ref instance) = renderable.instance {
+			instance.0.borrow_mut().visible = is_entity_visible(world, fog, player, id);
+		}
+	}
+
+	// a player without any vision yet has explored nothing
+	let values: Vec<u8> = match fog.grid(player) {
+		Some(grid) => grid.cells().iter().map(|&cell| match cell {
+			Visibility::Unexplored => 0,
+			Visibility::Explored => FOG_EXPLORED,
+			Visibility::Visible => 255,
+		}).collect(),
+		None => vec![0; fog.width() * fog.height()],
+	};
+
+	let version = match scene.fog {
+		Some(ref map) if map.values == values => return,
+		Some(ref map) => map.version + 1,
+		None => 0,
+	};
+
+	scene.fog = Some(FogMap {
+		width: fog.width(),
+		height: fog.height(),
+		size: vec2(fog.width() as Real, fog.height() as Real) * fog.cell_size(),
+		values: values,
+		version: version,
+	});
+}
+
+/// Turns impacts of the tick into flashes in the scene, the ones the player doesn't see stay dark
+pub fn sync_combat_effects(events: &[CombatEvent], fog: Option<&FogOfWar>, player: PlayerId, scene: &mut Scene) {
+	for event in events {
+		if let CombatEvent::Impact { position, splash_radius, .. } = *event {
+			if fog.map_or(true, |fog| fog.is_visible(player, position)) {
+				scene.flashes.push(Flash::new(position, splash_radius.max(IMPACT_FLASH_MIN_RADIUS), IMPACT_FLASH_COLOR, IMPACT_FLASH_DURATION));
+			}
+		}
+	}
+}
@@ -0,0 +1,276 @@
+use std::collections::BTreeSet;
+
+use ::app::{Input, Key, Modifiers};
+use ::game::{World, EntityId, PlayerId, Owner, FogOfWar, is_entity_visible};
+use ::gfx::scene::CameraRenderParams;
+use ::math::*;
+
+/// Cursor has to move further than this many pixels for a click to become a box selection
+const DRAG_THRESHOLD: Real = 4.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SelectionMode {
+	Replace,
+	Add,
+	Toggle,
+}
+
+impl SelectionMode {
+
+	/// Ctrl toggles, Shift adds, plain click replaces
+	pub fn from_modifiers(modifiers: Modifiers) -> Self {
+		if modifiers.ctrl {
+			SelectionMode::Toggle
+		} else if modifiers.shift {
+			SelectionMode::Add
+		} else {
+			SelectionMode::Replace
+		}
+	}
+
+}
+
+/// Units selected by the local player
+pub struct Selection {
+	selected: BTreeSet<EntityId>,
+	drag_start: Option<Vector2>,
+	drag_end: Vector2,
+}
+
+impl Selection {
+
+	pub fn new() -> Self {
+		Selection {
+			selected: BTreeSet::new(),
+			drag_start: None,
+			drag_end: vec2(0.0, 0.0),
+		}
+	}
+
+	pub fn selected(&self) -> &BTreeSet<EntityId> {
+		&self.selected
+	}
+
+	pub fn is_selected(&self, id: EntityId) -> bool {
+		self.selected.contains(&id)
+	}
+
+	pub fn clear(&mut self) {
+		self.selected.clear();
+	}
+
+	/// Corners of the box being dragged in pixels, if it is big enough to be a box
+	pub fn drag_rect(&self) -> Option<(Vector2, Vector2)> {
+		match self.drag_start {
+			Some(start) if (self.drag_end - start).magnitude() >= DRAG_THRESHOLD => Some((start, self.drag_end)),
+			_ => None,
+		}
+	}
+
+	/// Click picks the unit under the cursor, drag selects owned units inside the box.
+	/// Units hidden in the fog can't be picked, without fog every unit can.
+	pub fn update(&mut self, input: &Input, camera: &CameraRenderParams, world: &World, fog: Option<&FogOfWar>, player: PlayerId) {
+		self.drop_lost(world, fog, player);
+
+		let cursor = input.cursor_position();
+		self.drag_end = cursor;
+
+		if input.is_key_pressed(Key::Select) && input.is_cursor_in_window() {
+			self.drag_start = Some(cursor);
+		}
+
+		if input.is_key_released(Key::Select) {
+			let mode = SelectionMode::from_modifiers(input.modifiers());
+
+			if let Some((start, end)) = self.drag_rect() {
+				let units = units_in_screen_rect(world, camera, start, end, player);
+				self.apply(&units, mode);
+			} else if self.drag_start.is_some() {
+				let units: Vec<EntityId> = pick_unit(world, camera, cursor, fog, player).into_iter().collect();
+				self.apply(&units, mode);
+			}
+
+			self.drag_start = None;
+		}
+	}
+
+	/// Deselects units that died or went out of sight
+	fn drop_lost(&mut self, world: &World, fog: Option<&FogOfWar>, player: PlayerId) {
+		let kept: BTreeSet<EntityId> = self.selected.iter()
+			.cloned()
+			.filter(|&id| world.is_alive(id) && fog.map_or(true, |fog| is_entity_visible(world, fog, player, id)))
+			.collect();
+		self.selected = kept;
+	}
+
+	pub fn apply(&mut self, units: &[EntityId], mode: SelectionMode) {
+		match mode {
+			SelectionMode::Replace => {
+				self.selected = units.iter().cloned().collect();
+			}
+			SelectionMode::Add => {
+				self.selected.extend(units.iter().cloned());
+			}
+			SelectionMode::Toggle => for &unit in units {
+				if !self.selected.remove(&unit) {
+					self.selected.insert(unit);
+				}
+			},
+		}
+	}
+
+}
+
+/// Nearest unit under the point of the frame, given in pixels, that the player sees.
+/// Units in the fog are skipped, so they don't hide units behind them either.
+pub fn pick_unit(world: &World, camera: &CameraRenderParams, point: Vector2, fog: Option<&FogOfWar>, player: PlayerId) -> Option<EntityId> {
+	let ray = camera.screen_point_to_ray(point.x, point.y);
+	let mut nearest: Option<(Real, EntityId)> = None;
+
+	for (id, selectable) in world.selectables.iter() {
+		let position = match world.poses.get(id) {
+			Some(pose) => pose.position,
+			None => continue,
+		};
+		if fog.map_or(false, |fog| !is_entity_visible(world, fog, player, id)) {
+			continue;
+		}
+
+		if let Some(distance) = intersect_ray_aabb(&ray, &selectable.bounds(position)) {
+			let is_nearer = match nearest {
+				Some((nearest_distance, _)) => distance < nearest_distance,
+				None => true,
+			};
+			if is_nearer {
+				nearest = Some((distance, id));
+			}
+		}
+	}
+
+	nearest.map(|(_, id)| id)
+}
+
+/// Units of the player touching the screen rectangle with corners `a` and `b` in pixels
+pub fn units_in_screen_rect(world: &World, camera: &CameraRenderParams, a: Vector2, b: Vector2, player: PlayerId) -> Vec<EntityId> {
+	let frustum = camera.screen_rect_frustum((a.x, a.y), (b.x, b.y));
+
+	world.selectables.iter()
+		.filter(|&(id, _)| world.owners.get(id) == Some(&Owner(player)))
+		.filter_map(|(id, selectable)| world.poses.get(id).map(|pose| (id, selectable.bounds(pose.position))))
+		.filter(|&(_, ref bounds)| intersect_frustum_aabb(&frustum, bounds) != IntersectionTestResult::Outside)
+		.map(|(id, _)| id)
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Vision, update_visibility};
+	use ::game::test_util::{spawn_selectable, flat_heightfield};
+	use ::gfx::scene::{Camera, CameraProjection};
+
+	// looks straight down from above, pixel (x, y) sees world point (x - 50, 0, 50 - y)
+	fn top_down_camera() -> CameraRenderParams {
+		let mut camera = Camera {
+			z_near: 1.0,
+			z_far: 1000.0,
+			fov_y: Deg(90.0),
+			projection: CameraProjection::Perspective,
+			.. Default::default()
+		};
+		camera.spatial.position = vec3(0.0, 50.0, 0.0);
+		camera.spatial.rotation = Quaternion::from_angle_x(Deg(90.0));
+
+		CameraRenderParams::new(&camera, (100, 100))
+	}
+
+	#[test]
+	fn test_pick_and_box() {
+		let mut world = World::new();
+		let a = spawn_selectable(&mut world, PlayerId(0), vec3(-20.0, 0.0, 20.0));
+		let b = spawn_selectable(&mut world, PlayerId(0), vec3(20.0, 0.0, 20.0));
+		let enemy = spawn_selectable(&mut world, PlayerId(1), vec3(-20.0, 0.0, -20.0));
+		let camera = top_down_camera();
+
+		assert_eq!(pick_unit(&world, &camera, vec2(30.0, 30.0), None, PlayerId(0)), Some(a));
+		assert_eq!(pick_unit(&world, &camera, vec2(70.0, 30.0), None, PlayerId(0)), Some(b));
+		assert_eq!(pick_unit(&world, &camera, vec2(30.0, 70.0), None, PlayerId(0)), Some(enemy));
+		assert_eq!(pick_unit(&world, &camera, vec2(50.0, 50.0), None, PlayerId(0)), None);
+
+		assert_eq!(units_in_screen_rect(&world, &camera, vec2(0.0, 0.0), vec2(100.0, 50.0), PlayerId(0)), vec![a, b]);
+		// enemy units are never box selected
+		assert_eq!(units_in_screen_rect(&world, &camera, vec2(50.0, 100.0), vec2(0.0, 0.0), PlayerId(0)), vec![a]);
+		assert_eq!(units_in_screen_rect(&world, &camera, vec2(40.0, 40.0), vec2(60.0, 60.0), PlayerId(0)), vec![]);
+	}
+
+	#[test]
+	fn test_pick_nearest() {
+		let mut world = World::new();
+		let low = spawn_selectable(&mut world, PlayerId(0), vec3(0.0, 0.0, 0.0));
+		let high = spawn_selectable(&mut world, PlayerId(0), vec3(0.0, 10.0, 0.0));
+
+		assert_eq!(pick_unit(&world, &top_down_camera(), vec2(50.0, 50.0), None, PlayerId(0)), Some(high));
+
+		world.destroy(high);
+		assert_eq!(pick_unit(&world, &top_down_camera(), vec2(50.0, 50.0), None, PlayerId(0)), Some(low));
+	}
+
+	#[test]
+	fn test_fog_hides_picks() {
+		// the camera sees -50..50, the map covers 0..64 of it
+		let heightfield = flat_heightfield(64);
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		let mut world = World::new();
+		let scout = spawn_selectable(&mut world, PlayerId(0), vec3(10.0, 0.0, 10.0));
+		world.visions.insert(scout, Vision::new(8.0, 2.0));
+		let seen = spawn_selectable(&mut world, PlayerId(1), vec3(14.0, 0.0, 10.0));
+		let hidden = spawn_selectable(&mut world, PlayerId(1), vec3(40.0, 0.0, 40.0));
+		update_visibility(&world, &mut fog, &heightfield);
+		let camera = top_down_camera();
+
+		assert_eq!(pick_unit(&world, &camera, vec2(64.0, 40.0), Some(&fog), PlayerId(0)), Some(seen));
+		assert_eq!(pick_unit(&world, &camera, vec2(90.0, 10.0), Some(&fog), PlayerId(0)), None);
+		assert_eq!(pick_unit(&world, &camera, vec2(90.0, 10.0), None, PlayerId(0)), Some(hidden));
+		// the enemy sees its own unit
+		assert_eq!(pick_unit(&world, &camera, vec2(90.0, 10.0), Some(&fog), PlayerId(1)), Some(hidden));
+
+		// a selected enemy walking into the fog is deselected
+		let mut selection = Selection::new();
+		selection.apply(&[scout, seen], SelectionMode::Replace);
+		world.poses.get_mut(seen).unwrap().position = vec3(30.0, 0.0, 10.0);
+		update_visibility(&world, &mut fog, &heightfield);
+		selection.drop_lost(&world, Some(&fog), PlayerId(0));
+		assert_eq!(selection.selected().iter().cloned().collect::<Vec<_>>(), vec![scout]);
+	}
+
+	#[test]
+	fn test_selection_modes() {
+		let mut world = World::new();
+		let a = world.spawn();
+		let b = world.spawn();
+
+		let mut selection = Selection::new();
+
+		selection.apply(&[a], SelectionMode::Replace);
+		assert_eq!(selection.selected().iter().cloned().collect::<Vec<_>>(), vec![a]);
+
+		selection.apply(&[b], SelectionMode::Add);
+		assert!(selection.is_selected(a) && selection.is_selected(b));
+
+		selection.apply(&[a], SelectionMode::Toggle);
+		assert!(!selection.is_selected(a) && selection.is_selected(b));
+
+		selection.apply(&[a, b], SelectionMode::Toggle);
+		assert!(selection.is_selected(a) && !selection.is_selected(b));
+
+		selection.apply(&[], SelectionMode::Replace);
+		assert!(selection.selected().is_empty());
+
+		let shift = Modifiers { shift: true, .. Default::default() };
+		let ctrl_shift = Modifiers { shift: true, ctrl: true, .. Default::default() };
+		assert_eq!(SelectionMode::from_modifiers(shift), SelectionMode::Add);
+		assert_eq!(SelectionMode::from_modifiers(ctrl_shift), SelectionMode::Toggle);
+	}
+
+}
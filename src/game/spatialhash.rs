@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+
+use ::game::{World, EntityId, ComponentStorage};
+use ::math::*;
+
+type HashCell = (i32, i32);
+
+struct Entry {
+	position: Vector2,
+	radius: Real,
+	cell: HashCell,
+}
+
+/// Uniform grid over the XZ plane for finding entities near a point.
+///
+/// Entities are kept in the cell of their center and queries are widened by
+/// the largest radius ever inserted, so entities of any size are found.
+/// Results are sorted, so they never depend on the hash map order.
+pub struct SpatialHash {
+	cell_size: Real,
+	cells: HashMap<HashCell, Vec<EntityId>>,
+	entries: ComponentStorage<Entry>,
+	max_radius: Real,
+}
+
+impl SpatialHash {
+
+	pub fn new(cell_size: Real) -> Self {
+		SpatialHash {
+			cell_size: cell_size,
+			cells: HashMap::new(),
+			entries: ComponentStorage::new(),
+			max_radius: 0.0,
+		}
+	}
+
+	pub fn cell_size(&self) -> Real {
+		self.cell_size
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.iter().count()
+	}
+
+	pub fn contains(&self, id: EntityId) -> bool {
+		self.entries.contains(id)
+	}
+
+	pub fn position(&self, id: EntityId) -> Option<Vector2> {
+		self.entries.get(id).map(|entry| entry.position)
+	}
+
+	/// Adds the entity, or moves and resizes it if it's already there
+	pub fn insert(&mut self, id: EntityId, position: Vector2, radius: Real) {
+		self.max_radius = self.max_radius.max(radius);
+
+		if let Some(entry) = self.entries.get_mut(id) {
+			entry.radius = radius;
+		}
+
+		if self.entries.contains(id) {
+			self.set_position(id, position);
+			return;
+		}
+
+		let cell = self.cell_at(position);
+		self.cells.entry(cell).or_insert_with(Vec::new).push(id);
+		self.entries.insert(id, Entry {
+			position: position,
+			radius: radius,
+			cell: cell,
+		});
+	}
+
+	/// Moves the entity, only touches the cells when it crosses into another one
+	pub fn set_position(&mut self, id: EntityId, position: Vector2) {
+		let cell = self.cell_at(position);
+
+		let previous_cell = match self.entries.get_mut(id) {
+			Some(entry) => {
+				entry.position = position;
+				if entry.cell == cell {
+					return;
+				}
+				::std::mem::replace(&mut entry.cell, cell)
+			}
+			None => return,
+		};
+
+		self.remove_from_cell(id, previous_cell);
+		self.cells.entry(cell).or_insert_with(Vec::new).push(id);
+	}
+
+	pub fn remove(&mut self, id: EntityId) {
+		if let Some(entry) = self.entries.remove(id) {
+			self.remove_from_cell(id, entry.cell);
+		}
+	}
+
+	pub fn ids(&self) -> Vec<EntityId> {
+		self.entries.ids()
+	}
+
+	/// Entities touching the circle, sorted by id
+	pub fn query_radius(&self, center: Vector2, radius: Real) -> Vec<EntityId> {
+		let reach = radius + self.max_radius;
+		let mut result = Vec::new();
+
+		self.visit_cells(center - vec2(reach, reach), center + vec2(reach, reach), |id, entry| {
+			let distance = radius + entry.radius;
+			if (entry.position - center).magnitude2() <= distance * distance {
+				result.push(id);
+			}
+		});
+
+		result.sort();
+		result
+	}
+
+	/// Entities touching the rectangle, sorted by id
+	pub fn query_rect(&self, min: Vector2, max: Vector2) -> Vec<EntityId> {
+		let reach = vec2(self.max_radius, self.max_radius);
+		let mut result = Vec::new();
+
+		self.visit_cells(min - reach, max + reach, |id, entry| {
+			let closest = vec2(
+				entry.position.x.max(min.x).min(max.x),
+				entry.position.y.max(min.y).min(max.y),
+			);
+			if (entry.position - closest).magnitude2() <= entry.radius * entry.radius {
+				result.push(id);
+			}
+		});
+
+		result.sort();
+		result
+	}
+
+	/// Entities hit by the ray within `max_distance`, nearest first
+	pub fn query_ray(&self, origin: Vector2, direction: Vector2, max_distance: Real) -> Vec<EntityId> {
+		if direction.magnitude2() == 0.0 {
+			return Vec::new();
+		}
+
+		let direction = direction.normalize();
+		let end = origin + direction * max_distance;
+		let reach = self.max_radius;
+		let cell_reach = self.cell_size * 0.5 * ::std::f32::consts::SQRT_2 + reach;
+
+		let min = vec2(origin.x.min(end.x), origin.y.min(end.y)) - vec2(reach, reach);
+		let max = vec2(origin.x.max(end.x), origin.y.max(end.y)) + vec2(reach, reach);
+
+		let mut hits = Vec::new();
+		let (x0, y0) = self.cell_at(min);
+		let (x1, y1) = self.cell_at(max);
+
+		for y in y0..(y1 + 1) {
+			for x in x0..(x1 + 1) {
+				// skip cells of the bounding box too far from the segment
+				let center = vec2((x as Real + 0.5) * self.cell_size, (y as Real + 0.5) * self.cell_size);
+				let t = (center - origin).dot(direction).max(0.0).min(max_distance);
+				if (origin + direction * t - center).magnitude() > cell_reach {
+					continue;
+				}
+
+				let ids = match self.cells.get(&(x, y)) {
+					Some(ids) => ids,
+					None => continue,
+				};
+
+				for &id in ids {
+					let entry = self.entries.get(id).unwrap();
+					if let Some(distance) = intersect_ray_circle(origin, direction, entry.position, entry.radius) {
+						if distance <= max_distance {
+							hits.push((distance, id));
+						}
+					}
+				}
+			}
+		}
+
+		hits.sort_by(|a, b| a.partial_cmp(b).unwrap());
+		hits.into_iter().map(|(_, id)| id).collect()
+	}
+
+	fn cell_at(&self, position: Vector2) -> HashCell {
+		((position.x / self.cell_size).floor() as i32, (position.y / self.cell_size).floor() as i32)
+	}
+
+	fn remove_from_cell(&mut self, id: EntityId, cell: HashCell) {
+		let is_empty = match self.cells.get_mut(&cell) {
+			Some(ids) => {
+				if let Some(position) = ids.iter().position(|&other| other == id) {
+					ids.swap_remove(position);
+				}
+				ids.is_empty()
+			}
+			None => false,
+		};
+
+		if is_empty {
+			self.cells.remove(&cell);
+		}
+	}
+
+	fn visit_cells<F>(&self, min: Vector2, max: Vector2, mut visit: F)
+		where F: FnMut(EntityId, &Entry)
+	{
+		let (x0, y0) = self.cell_at(min);
+		let (x1, y1) = self.cell_at(max);
+
+		for y in y0..(y1 + 1) {
+			for x in x0..(x1 + 1) {
+				if let Some(ids) = self.cells.get(&(x, y)) {
+					for &id in ids {
+						visit(id, self.entries.get(id).unwrap());
+					}
+				}
+			}
+		}
+	}
+
+}
+
+/// Distance along the normalized direction to the circle, zero when starting inside
+fn intersect_ray_circle(origin: Vector2, direction: Vector2, center: Vector2, radius: Real) -> Option<Real> {
+	let offset = origin - center;
+	let b = offset.dot(direction);
+	let c = offset.magnitude2() - radius * radius;
+
+	if c <= 0.0 {
+		return Some(0.0);
+	}
+	if b > 0.0 {
+		return None;
+	}
+
+	let discriminant = b * b - c;
+	if discriminant < 0.0 {
+		None
+	} else {
+		Some(-b - discriminant.sqrt())
+	}
+}
+
+/// Radius entities take on the ground: the movement body, or the selection box
+pub fn body_radius(world: &World, id: EntityId) -> Real {
+	if let Some(movement) = world.movements.get(id) {
+		movement.radius
+	} else if let Some(selectable) = world.selectables.get(id) {
+		selectable.radius
+	} else {
+		0.0
+	}
+}
+
+/// Height of the entity above its position: the selection box, or as tall as it is wide
+pub fn body_height(world: &World, id: EntityId) -> Real {
+	match world.selectables.get(id) {
+		Some(selectable) => selectable.height,
+		None => body_radius(world, id) * 2.0,
+	}
+}
+
+/// Brings the spatial hash up to date with entity poses, called once per tick
+pub fn update_spatial_hash(world: &mut World) {
+	for id in world.spatial_hash.ids() {
+		if !world.poses.contains(id) {
+			world.spatial_hash.remove(id);
+		}
+	}
+
+	for (id, pose) in world.poses.iter() {
+		let radius = body_radius(world, id);
+		world.spatial_hash.insert(id, vec2(pose.position.x, pose.position.z), radius);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Pose, Movement};
+
+	// deterministic pseudo random positions
+	fn random_positions(count: usize, size: Real) -> Vec<Vector2> {
+		let mut state: u32 = 12345;
+		let mut next = || {
+			state = state.wrapping_mul(1103515245).wrapping_add(12345);
+			((state >> 8) as Real) / ((1 << 24) as Real) * size
+		};
+		(0..count).map(|_| vec2(next(), next())).collect()
+	}
+
+	fn spawn_units(world: &mut World, positions: &[Vector2]) -> Vec<EntityId> {
+		positions.iter().map(|position| {
+			let unit = world.spawn();
+			world.poses.insert(unit, Pose::new(vec3(position.x, 0.0, position.y)));
+			world.movements.insert(unit, Movement::new(5.0));
+			unit
+		}).collect()
+	}
+
+	#[test]
+	fn test_insert_move_remove() {
+		let mut world = World::new();
+		let a = world.spawn();
+		let b = world.spawn();
+
+		let mut hash = SpatialHash::new(4.0);
+		hash.insert(a, vec2(1.0, 1.0), 0.5);
+		hash.insert(b, vec2(10.0, 1.0), 0.5);
+		assert_eq!(hash.len(), 2);
+		assert_eq!(hash.query_radius(vec2(0.0, 0.0), 2.0), vec![a]);
+
+		hash.set_position(a, vec2(9.0, 2.0));
+		assert!(hash.query_radius(vec2(0.0, 0.0), 2.0).is_empty());
+		assert_eq!(hash.query_radius(vec2(9.5, 1.5), 1.0), vec![a, b]);
+
+		// negative coordinates work too
+		hash.set_position(b, vec2(-5.0, -5.0));
+		assert_eq!(hash.query_radius(vec2(-5.5, -5.5), 0.5), vec![b]);
+
+		hash.remove(a);
+		assert!(!hash.contains(a));
+		assert!(hash.query_radius(vec2(9.0, 2.0), 5.0).is_empty());
+		assert_eq!(hash.len(), 1);
+	}
+
+	#[test]
+	fn test_queries_match_brute_force() {
+		let mut world = World::new();
+		let positions = random_positions(500, 100.0);
+		let units = spawn_units(&mut world, &positions);
+
+		let mut hash = SpatialHash::new(3.0);
+		for (i, &unit) in units.iter().enumerate() {
+			// a few big entities bigger than a cell
+			hash.insert(unit, positions[i], if i % 50 == 0 { 5.0 } else { 0.5 });
+		}
+
+		for &(center, radius) in [(vec2(50.0, 50.0), 10.0), (vec2(0.0, 0.0), 7.0), (vec2(73.0, 21.0), 0.1)].iter() {
+			let expected: Vec<EntityId> = units.iter().enumerate()
+				.filter(|&(i, _)| (positions[i] - center).magnitude() <= radius + if i % 50 == 0 { 5.0 } else { 0.5 })
+				.map(|(_, &unit)| unit)
+				.collect();
+			assert_eq!(hash.query_radius(center, radius), expected);
+		}
+
+		let (min, max) = (vec2(20.0, 30.0), vec2(45.0, 38.0));
+		let in_rect = hash.query_rect(min, max);
+		for (i, &unit) in units.iter().enumerate() {
+			let radius = if i % 50 == 0 { 5.0 } else { 0.5 };
+			let p = positions[i];
+			let inside = p.x > min.x - radius && p.x < max.x + radius && p.y > min.y - radius && p.y < max.y + radius;
+			let deep_inside = p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y;
+			if deep_inside {
+				assert!(in_rect.contains(&unit));
+			}
+			if !inside {
+				assert!(!in_rect.contains(&unit));
+			}
+		}
+	}
+
+	#[test]
+	fn test_ray_query() {
+		let mut world = World::new();
+		let ids: Vec<EntityId> = (0..4).map(|_| world.spawn()).collect();
+
+		let mut hash = SpatialHash::new(2.0);
+		hash.insert(ids[0], vec2(10.0, 0.3), 0.5);
+		hash.insert(ids[1], vec2(4.0, -0.2), 0.5);
+		hash.insert(ids[2], vec2(6.0, 3.0), 0.5);
+		hash.insert(ids[3], vec2(30.0, 0.0), 0.5);
+
+		assert_eq!(hash.query_ray(vec2(0.0, 0.0), vec2(2.0, 0.0), 20.0), vec![ids[1], ids[0]]);
+		assert_eq!(hash.query_ray(vec2(0.0, 0.0), vec2(2.0, 1.0), 20.0), vec![ids[2]]);
+		assert!(hash.query_ray(vec2(0.0, 0.0), vec2(-1.0, 0.0), 20.0).is_empty());
+	}
+
+	#[test]
+	fn test_update_from_world() {
+		let mut world = World::new();
+		let units = spawn_units(&mut world, &[vec2(1.0, 1.0), vec2(2.0, 1.0)]);
+		update_spatial_hash(&mut world);
+		assert_eq!(world.spatial_hash.query_radius(vec2(1.5, 1.0), 1.0), units);
+
+		world.poses.get_mut(units[0]).unwrap().position = vec3(50.0, 0.0, 50.0);
+		world.destroy(units[1]);
+		update_spatial_hash(&mut world);
+
+		assert!(world.spatial_hash.query_radius(vec2(1.5, 1.0), 1.0).is_empty());
+		assert_eq!(world.spatial_hash.query_radius(vec2(50.0, 50.0), 0.1), vec![units[0]]);
+		assert_eq!(world.spatial_hash.len(), 1);
+	}
+
+}
@@ -0,0 +1,120 @@
+use std::rc::Rc;
+
+use ::game::{World, EntityId, Player, PlayerId, TeamId, Owner, Pose, Health, Movement, OrderQueue, Building, ResourceNode, ResourceDef, CombatData};
+use ::game::{Worker, Weapon, WeaponDef, Selectable, Vision, EconomyData};
+use ::game::{update_navigation, update_orders, update_movement, update_economy, update_combat, update_projectiles, update_spatial_hash};
+use ::navigation::{Navigation, NavGrid};
+use ::terrain::Heightfield;
+use ::math::*;
+
+/// Length of a simulated tick
+pub const DT: Real = 0.05;
+
+/// Flat square heightfield with 1 unit cells
+pub fn flat_heightfield(size: usize) -> Heightfield {
+	Heightfield::from_fn(size, size, vec3(size as Real, 1.0, size as Real), |_, _| 0.0)
+}
+
+/// Flat square map and its navigation
+pub fn flat_map(size: usize) -> (Heightfield, Navigation) {
+	let heightfield = flat_heightfield(size);
+	let navigation = Navigation::new(NavGrid::from_heightfield(&heightfield, 1.0));
+	(heightfield, navigation)
+}
+
+/// Players 0 to count - 1, each on a team of their own
+pub fn add_players(world: &mut World, count: u8) {
+	for id in 0..count {
+		world.players.add(Player::new(PlayerId(id), TeamId(id)));
+	}
+}
+
+/// Entity of the player standing at the position, with nothing else to it
+pub fn spawn(world: &mut World, player: PlayerId, position: Vector3) -> EntityId {
+	let id = world.spawn();
+	world.poses.insert(id, Pose::new(position));
+	world.owners.insert(id, Owner(player));
+	id
+}
+
+/// Unit with 100 health that walks and takes orders
+pub fn spawn_unit(world: &mut World, player: PlayerId, position: Vector3) -> EntityId {
+	let unit = spawn(world, player, position);
+	world.healths.insert(unit, Health::new(100.0));
+	world.movements.insert(unit, Movement::new(5.0));
+	world.order_queues.insert(unit, OrderQueue::new());
+	unit
+}
+
+/// Unit that gathers and repairs
+pub fn spawn_worker(world: &mut World, player: PlayerId, position: Vector3) -> EntityId {
+	let worker = spawn_unit(world, player, position);
+	world.workers.insert(worker, Worker::new());
+	worker
+}
+
+/// Unit armed with the weapon
+pub fn spawn_soldier(world: &mut World, player: PlayerId, position: Vector3, weapon: Rc<WeaponDef>) -> EntityId {
+	let soldier = spawn_unit(world, player, position);
+	world.weapons.insert(soldier, Weapon::new(weapon));
+	soldier
+}
+
+/// Entity of the player that can be clicked, 1 in radius and 2 high
+pub fn spawn_selectable(world: &mut World, player: PlayerId, position: Vector3) -> EntityId {
+	let id = spawn(world, player, position);
+	world.selectables.insert(id, Selectable::new(1.0, 2.0));
+	id
+}
+
+/// Entity of the player that sees around it
+pub fn spawn_observer(world: &mut World, player: PlayerId, position: Vector3, radius: Real, eye_height: Real) -> EntityId {
+	let id = spawn(world, player, position);
+	world.visions.insert(id, Vision::new(radius, eye_height));
+	id
+}
+
+/// Building of the kind with 100 health
+pub fn spawn_building(world: &mut World, player: PlayerId, kind: &str, position: Vector3) -> EntityId {
+	let building = spawn(world, player, position);
+	world.buildings.insert(building, Building::new(kind));
+	world.healths.insert(building, Health::new(100.0));
+	building
+}
+
+/// Resource node holding the amount, nobody owns it
+pub fn spawn_node(world: &mut World, resource: Rc<ResourceDef>, amount: u32, position: Vector3) -> EntityId {
+	let node = world.spawn();
+	world.poses.insert(node, Pose::new(position));
+	world.resource_nodes.insert(node, ResourceNode::new(resource, amount));
+	node
+}
+
+/// Gold taking a second to gather, 10 per trip
+pub fn gold() -> Rc<ResourceDef> {
+	EconomyData::parse("[resources.gold]\ngather_time = 1\ncarry_amount = 10").unwrap().resource("gold").unwrap()
+}
+
+/// Piercing weapon that shoots units 5 away
+pub fn rifle() -> Rc<WeaponDef> {
+	CombatData::parse("[weapons.rifle]\nrange = 5\ncooldown = 1\ndamage = 10\ndamage_type = \"piercing\"\ntargets = [\"units\"]").unwrap().weapon("rifle").unwrap()
+}
+
+/// Runs orders, movement and the economy for the ticks
+pub fn simulate(world: &mut World, navigation: &mut Navigation, heightfield: &Heightfield, ticks: usize) {
+	for _ in 0..ticks {
+		update_navigation(world, navigation);
+		update_orders(world, navigation, DT);
+		update_movement(world, &navigation.grid, heightfield, DT);
+		update_economy(world, DT);
+	}
+}
+
+/// Runs weapons and projectiles for the ticks, nobody moves
+pub fn simulate_combat(world: &mut World, heightfield: &Heightfield, data: &CombatData, ticks: usize) {
+	for _ in 0..ticks {
+		update_spatial_hash(world);
+		update_combat(world, data, DT);
+		update_projectiles(world, heightfield, data, DT);
+	}
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+
+use ::game::{World, EntityId, PlayerId, Vision, Relationship, relationship};
+use ::terrain::Heightfield;
+use ::math::*;
+
+/// Height above the ground a line of sight has to reach for a cell to count as seen,
+/// keeps flat ground from hiding itself between samples
+const TARGET_HEIGHT: Real = 0.5;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Visibility {
+	/// Never seen
+	Unexplored,
+	/// Seen before, but nobody looks there now
+	Explored,
+	Visible,
+}
+
+/// What one player knows about the map. Cells cover the terrain starting at the origin,
+/// rows go along z.
+pub struct VisibilityGrid {
+	width: usize,
+	height: usize,
+	cell_size: Real,
+	cells: Vec<Visibility>,
+}
+
+impl VisibilityGrid {
+
+	pub fn new(width: usize, height: usize, cell_size: Real) -> Self {
+		VisibilityGrid {
+			width: width,
+			height: height,
+			cell_size: cell_size,
+			cells: vec![Visibility::Unexplored; width * height],
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn cell_size(&self) -> Real {
+		self.cell_size
+	}
+
+	/// Row-major cells, first row at z = 0
+	pub fn cells(&self) -> &[Visibility] {
+		&self.cells
+	}
+
+	pub fn cell(&self, x: usize, y: usize) -> Visibility {
+		self.cells[y * self.width + x]
+	}
+
+	/// Cell containing the position, None outside the grid
+	pub fn cell_at(&self, position: Vector3) -> Option<(usize, usize)> {
+		cell_at(self.width, self.height, self.cell_size, position)
+	}
+
+	/// Outside of the grid is never explored
+	pub fn visibility_at(&self, position: Vector3) -> Visibility {
+		match self.cell_at(position) {
+			Some((x, y)) => self.cell(x, y),
+			None => Visibility::Unexplored,
+		}
+	}
+
+	pub fn is_visible(&self, position: Vector3) -> bool {
+		self.visibility_at(position) == Visibility::Visible
+	}
+
+	/// Marks what was visible as explored, before vision is computed anew
+	fn fade(&mut self) {
+		for cell in self.cells.iter_mut() {
+			if *cell == Visibility::Visible {
+				*cell = Visibility::Explored;
+			}
+		}
+	}
+
+	/// Makes the cells marked in the sight visible
+	fn reveal(&mut self, sight: &[bool]) {
+		for (cell, &seen) in self.cells.iter_mut().zip(sight) {
+			if seen {
+				*cell = Visibility::Visible;
+			}
+		}
+	}
+
+}
+
+/// Cells a vision source sees. Kept until the source moves to another cell,
+/// its vision changes or the terrain does.
+struct View {
+	cell: (usize, usize),
+	radius: Real,
+	eye_height: Real,
+	terrain_version: u64,
+	cells: Vec<usize>,
+}
+
+/// Visibility grids of all players over one terrain
+pub struct FogOfWar {
+	width: usize,
+	height: usize,
+	cell_size: Real,
+	/// Indexed by player id, created when the player first gets vision
+	grids: Vec<VisibilityGrid>,
+	/// Cells the player's own sources see this tick, indexed by player id,
+	/// merged into the grids of the player and allies once all sources are in
+	sight: Vec<Vec<bool>>,
+	views: HashMap<EntityId, View>,
+}
+
+impl FogOfWar {
+
+	pub fn new(heightfield: &Heightfield, cell_size: Real) -> Self {
+		FogOfWar {
+			width: ((heightfield.scale.x / cell_size).ceil() as usize).max(1),
+			height: ((heightfield.scale.z / cell_size).ceil() as usize).max(1),
+			cell_size: cell_size,
+			grids: Vec::new(),
+			sight: Vec::new(),
+			views: HashMap::new(),
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn cell_size(&self) -> Real {
+		self.cell_size
+	}
+
+	pub fn grid(&self, player: PlayerId) -> Option<&VisibilityGrid> {
+		self.grids.get(player.0 as usize)
+	}
+
+	fn grid_mut(&mut self, player: PlayerId) -> &mut VisibilityGrid {
+		while self.grids.len() <= player.0 as usize {
+			self.grids.push(VisibilityGrid::new(self.width, self.height, self.cell_size));
+		}
+		&mut self.grids[player.0 as usize]
+	}
+
+	pub fn visibility_at(&self, player: PlayerId, position: Vector3) -> Visibility {
+		match self.grid(player) {
+			Some(grid) => grid.visibility_at(position),
+			None => Visibility::Unexplored,
+		}
+	}
+
+	pub fn is_visible(&self, player: PlayerId, position: Vector3) -> bool {
+		self.visibility_at(player, position) == Visibility::Visible
+	}
+
+	/// Brings the view of the vision source up to date, looking anew only when the cached one is outdated
+	fn update_view(&mut self, heightfield: &Heightfield, id: EntityId, cell: (usize, usize), vision: &Vision) {
+		let outdated = match self.views.get(&id) {
+			Some(view) => view.cell != cell || view.radius != vision.radius || view.eye_height != vision.eye_height || view.terrain_version != heightfield.version(),
+			None => true,
+		};
+		if !outdated {
+			return;
+		}
+
+		let cells = self.viewshed(heightfield, cell, vision.radius, vision.eye_height);
+		self.views.insert(id, View {
+			cell: cell,
+			radius: vision.radius,
+			eye_height: vision.eye_height,
+			terrain_version: heightfield.version(),
+			cells: cells,
+		});
+	}
+
+	/// Adds what the vision source sees to the sight of its owner
+	fn mark_view(&mut self, owner: PlayerId, id: EntityId) {
+		let cells = self.width * self.height;
+		while self.sight.len() <= owner.0 as usize {
+			self.sight.push(vec![false; cells]);
+		}
+		let FogOfWar { ref mut sight, ref views, .. } = *self;
+		let sight = &mut sight[owner.0 as usize];
+		for &index in &views[&id].cells {
+			sight[index] = true;
+		}
+	}
+
+	/// Shows the player what the owner's sources see
+	fn share_sight(&mut self, owner: PlayerId, player: PlayerId) {
+		self.grid_mut(player);
+		let FogOfWar { ref mut grids, ref sight, .. } = *self;
+		grids[player.0 as usize].reveal(&sight[owner.0 as usize]);
+	}
+
+	/// Indices of the cells the terrain doesn't hide from an eye above the center of the cell.
+	/// Cells are visited ring by ring going out from the eye, each keeps the steepest slope of the ground
+	/// between it and the eye, taken from the two cells of the inner ring the sight line passes between.
+	/// So every cell is looked at once, instead of sampling a line of sight to each.
+	fn viewshed(&self, heightfield: &Heightfield, cell: (usize, usize), radius: Real, eye_height: Real) -> Vec<usize> {
+		let rings = (radius / self.cell_size).ceil().max(0.0) as isize;
+		let side = 2 * rings + 1;
+		let center = |x: isize, y: isize| vec2((x as Real + 0.5) * self.cell_size, (y as Real + 0.5) * self.cell_size);
+
+		let (eye_x, eye_y) = (cell.0 as isize, cell.1 as isize);
+		let eye_center = center(eye_x, eye_y);
+		let eye_level = heightfield.height_at(eye_center.x, eye_center.y) + eye_height;
+
+		// steepest slope on the way from the eye, by offset from the eye cell
+		let mut horizons = vec![::std::f32::NEG_INFINITY; (side * side) as usize];
+		let local = |dx: isize, dy: isize| ((dy + rings) * side + dx + rings) as usize;
+
+		let mut cells = vec![cell.1 * self.width + cell.0];
+
+		for ring in 1..rings + 1 {
+			for (dx, dy) in ring_offsets(ring) {
+				let (x, y) = (eye_x + dx, eye_y + dy);
+				if x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize {
+					continue;
+				}
+
+				let position = center(x, y);
+				let distance = (position - eye_center).magnitude();
+				let ground = heightfield.height_at(position.x, position.y);
+				let horizon = inner_horizon(&horizons, &local, dx, dy, ring);
+
+				horizons[local(dx, dy)] = horizon.max((ground - eye_level) / distance);
+				if distance <= radius && (ground + TARGET_HEIGHT - eye_level) / distance >= horizon {
+					cells.push(y as usize * self.width + x as usize);
+				}
+			}
+		}
+
+		cells
+	}
+
+}
+
+/// Cell containing the position in a grid of the size, None outside the grid
+fn cell_at(width: usize, height: usize, cell_size: Real, position: Vector3) -> Option<(usize, usize)> {
+	let x = (position.x / cell_size).floor();
+	let y = (position.z / cell_size).floor();
+	if x < 0.0 || y < 0.0 || x >= width as Real || y >= height as Real {
+		return None;
+	}
+	Some((x as usize, y as usize))
+}
+
+/// Offsets of the cells on the square ring around a cell
+fn ring_offsets(ring: isize) -> Vec<(isize, isize)> {
+	let mut offsets = Vec::with_capacity(8 * ring as usize);
+	for d in -ring..ring + 1 {
+		offsets.push((d, -ring));
+		offsets.push((d, ring));
+	}
+	for d in -ring + 1..ring {
+		offsets.push((-ring, d));
+		offsets.push((ring, d));
+	}
+	offsets
+}
+
+/// Steepest slope between the eye and the cell at the offset, interpolated between the two cells
+/// of the next ring in that the sight line to the cell crosses
+fn inner_horizon<F>(horizons: &[Real], local: &F, dx: isize, dy: isize, ring: isize) -> Real
+	where F: Fn(isize, isize) -> usize
+{
+	if ring == 1 {
+		return ::std::f32::NEG_INFINITY;
+	}
+
+	let along_x = dx.abs() >= dy.abs();
+	let (major, minor) = if along_x { (dx, dy) } else { (dy, dx) };
+	let inner_major = major - major.signum();
+	let inner_minor = minor as Real * (ring - 1) as Real / ring as Real;
+
+	let low = inner_minor.floor();
+	let t = inner_minor - low;
+	let (a, b) = (low as isize, low as isize + if t > 0.0 { 1 } else { 0 });
+	let horizon = |minor: isize| if along_x { horizons[local(inner_major, minor)] } else { horizons[local(minor, inner_major)] };
+
+	horizon(a) * (1.0 - t) + horizon(b) * t
+}
+
+/// Recomputes what every player sees from the vision of their own and allied entities.
+/// Cells seen before stay explored.
+pub fn update_visibility(world: &World, fog: &mut FogOfWar, heightfield: &Heightfield) {
+	for grid in fog.grids.iter_mut() {
+		grid.fade();
+	}
+	for sight in fog.sight.iter_mut() {
+		for seen in sight.iter_mut() {
+			*seen = false;
+		}
+	}
+
+	for (id, vision) in world.visions.iter() {
+		let (owner, position) = match (world.owners.get(id), world.poses.get(id)) {
+			(Some(owner), Some(pose)) => (owner.0, pose.position),
+			_ => continue,
+		};
+		let cell = match cell_at(fog.width, fog.height, fog.cell_size, position) {
+			Some(cell) => cell,
+			None => continue,
+		};
+
+		fog.update_view(heightfield, id, cell, vision);
+		fog.mark_view(owner, id);
+	}
+
+	// allies are looked up once per player, not per source
+	for owner in 0..fog.sight.len() {
+		let owner = PlayerId(owner as u8);
+		fog.share_sight(owner, owner);
+		for ally in world.players.allies(owner) {
+			fog.share_sight(owner, ally);
+		}
+	}
+
+	// views of sources that are gone
+	fog.views.retain(|&id, _| world.visions.contains(id));
+}
+
+/// Whether the player sees the entity. Own and allied entities are always seen,
+/// others only inside the player's vision.
+pub fn is_entity_visible(world: &World, fog: &FogOfWar, player: PlayerId, id: EntityId) -> bool {
+	match relationship(world, player, id) {
+		Relationship::Own | Relationship::Ally => return true,
+		_ => (),
+	}
+
+	match world.poses.get(id) {
+		Some(pose) => fog.is_visible(player, pose.position),
+		None => false,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::game::{Vision, Player, TeamId, Stance};
+	use ::game::test_util::spawn_observer;
+
+	/// 40x40 map, flat apart from a 10 high wall across x = 20
+	fn walled_heightfield() -> Heightfield {
+		Heightfield::from_fn(40, 40, vec3(40.0, 10.0, 40.0), |x, _| if x == 20 { 1.0 } else { 0.0 })
+	}
+
+	/// Whether the terrain leaves the straight line from the eye to the target clear,
+	/// sampling the ground every `step` units
+	fn line_of_sight(heightfield: &Heightfield, eye: Vector3, target: Vector3, step: Real) -> bool {
+		let offset = target - eye;
+		let distance = vec2(offset.x, offset.z).magnitude();
+		let steps = (distance / step).ceil() as usize;
+
+		for i in 1..steps {
+			let point = eye + offset * (i as Real / steps as Real);
+			if heightfield.height_at(point.x, point.z) > point.y {
+				return false;
+			}
+		}
+
+		true
+	}
+
+	#[test]
+	fn test_vision_radius() {
+		let heightfield = Heightfield::from_fn(40, 40, vec3(40.0, 10.0, 40.0), |_, _| 0.0);
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		spawn_observer(&mut world, PlayerId(0), vec3(10.0, 0.0, 10.0), 5.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert!(fog.is_visible(PlayerId(0), vec3(10.0, 0.0, 10.0)));
+		assert!(fog.is_visible(PlayerId(0), vec3(14.0, 0.0, 10.0)));
+		assert!(!fog.is_visible(PlayerId(0), vec3(16.0, 0.0, 10.0)));
+		assert_eq!(fog.visibility_at(PlayerId(0), vec3(30.0, 0.0, 30.0)), Visibility::Unexplored);
+
+		// other players see nothing of it
+		assert_eq!(fog.visibility_at(PlayerId(1), vec3(10.0, 0.0, 10.0)), Visibility::Unexplored);
+		assert!(fog.grid(PlayerId(1)).is_none());
+	}
+
+	#[test]
+	fn test_hills_block_vision() {
+		let heightfield = walled_heightfield();
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		let observer = spawn_observer(&mut world, PlayerId(0), vec3(15.0, 0.0, 20.0), 12.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert!(fog.is_visible(PlayerId(0), vec3(18.0, 0.0, 20.0)));
+		assert!(!fog.is_visible(PlayerId(0), vec3(25.0, 0.0, 20.0)));
+
+		// from high enough the wall doesn't hide the ground behind it
+		world.visions.insert(observer, Vision::new(12.0, 30.0));
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert!(fog.is_visible(PlayerId(0), vec3(25.0, 0.0, 20.0)));
+	}
+
+	#[test]
+	fn test_explored_cells_stay_explored() {
+		let heightfield = walled_heightfield();
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		let observer = spawn_observer(&mut world, PlayerId(0), vec3(5.0, 0.0, 5.0), 3.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+		world.poses.get_mut(observer).unwrap().position = vec3(5.0, 0.0, 30.0);
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert_eq!(fog.visibility_at(PlayerId(0), vec3(5.0, 0.0, 5.0)), Visibility::Explored);
+		assert_eq!(fog.visibility_at(PlayerId(0), vec3(5.0, 0.0, 30.0)), Visibility::Visible);
+
+		world.destroy(observer);
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert_eq!(fog.visibility_at(PlayerId(0), vec3(5.0, 0.0, 30.0)), Visibility::Explored);
+	}
+
+	#[test]
+	fn test_entity_visibility() {
+		let heightfield = walled_heightfield();
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		let observer = spawn_observer(&mut world, PlayerId(0), vec3(15.0, 0.0, 20.0), 12.0, 2.0);
+		let near_enemy = spawn_observer(&mut world, PlayerId(1), vec3(17.0, 0.0, 20.0), 0.0, 2.0);
+		let hidden_enemy = spawn_observer(&mut world, PlayerId(1), vec3(25.0, 0.0, 20.0), 0.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert!(is_entity_visible(&world, &fog, PlayerId(0), observer));
+		assert!(is_entity_visible(&world, &fog, PlayerId(0), near_enemy));
+		assert!(!is_entity_visible(&world, &fog, PlayerId(0), hidden_enemy));
+		// own entities are seen even in the fog
+		assert!(is_entity_visible(&world, &fog, PlayerId(1), hidden_enemy));
+	}
+
+	#[test]
+	fn test_sweep_matches_line_of_sight() {
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 12.0, 64.0), |x, y| ((x as Real * 0.3).sin() * (y as Real * 0.2).cos() + 1.0) * 0.5);
+		let fog = FogOfWar::new(&heightfield, 1.0);
+
+		let mut cells = 0;
+		let mut mismatches = 0;
+		for &(eye_x, eye_y) in [(10, 10), (32, 40), (50, 20), (2, 60)].iter() {
+			let seen = fog.viewshed(&heightfield, (eye_x, eye_y), 20.0, 2.0);
+			let eye_center = vec2(eye_x as Real + 0.5, eye_y as Real + 0.5);
+			let eye = vec3(eye_center.x, heightfield.height_at(eye_center.x, eye_center.y) + 2.0, eye_center.y);
+
+			for y in 0..64 {
+				for x in 0..64 {
+					let center = vec2(x as Real + 0.5, y as Real + 0.5);
+					if (center - eye_center).magnitude() > 20.0 {
+						continue;
+					}
+					let target = vec3(center.x, heightfield.height_at(center.x, center.y) + TARGET_HEIGHT, center.y);
+					cells += 1;
+					if line_of_sight(&heightfield, eye, target, 0.25) != seen.contains(&(y * 64 + x)) {
+						mismatches += 1;
+					}
+				}
+			}
+		}
+
+		// the sweep interpolates between sight lines, so it may only disagree on a few cells at the edges of shadows
+		assert!(mismatches * 20 < cells, "{} of {} cells differ", mismatches, cells);
+	}
+
+	#[test]
+	fn test_views_follow_terrain_and_moves() {
+		let mut heightfield = walled_heightfield();
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		let observer = spawn_observer(&mut world, PlayerId(0), vec3(15.2, 0.0, 20.2), 12.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+		assert!(!fog.is_visible(PlayerId(0), vec3(25.0, 0.0, 20.0)));
+
+		// moving inside the cell keeps the view
+		let version = fog.views[&observer].terrain_version;
+		world.poses.get_mut(observer).unwrap().position = vec3(15.8, 0.0, 20.8);
+		update_visibility(&world, &mut fog, &heightfield);
+		assert_eq!(fog.views[&observer].cell, (15, 20));
+
+		// razing the wall shows what's behind it without moving
+		heightfield.flatten(vec2(0.0, 0.0), vec2(40.0, 40.0));
+		update_visibility(&world, &mut fog, &heightfield);
+		assert!(fog.views[&observer].terrain_version > version);
+		assert!(fog.is_visible(PlayerId(0), vec3(25.0, 0.0, 20.0)));
+
+		world.destroy(observer);
+		update_visibility(&world, &mut fog, &heightfield);
+		assert!(fog.views.is_empty());
+	}
+
+	#[test]
+	fn test_shared_vision() {
+		let heightfield = walled_heightfield();
+		let mut world = World::new();
+		let mut fog = FogOfWar::new(&heightfield, 1.0);
+		world.players.add(Player::new(PlayerId(0), TeamId(0)));
+		world.players.add(Player::new(PlayerId(1), TeamId(0)));
+		world.players.add(Player::new(PlayerId(2), TeamId(1)));
+		spawn_observer(&mut world, PlayerId(1), vec3(5.0, 0.0, 5.0), 5.0, 2.0);
+
+		update_visibility(&world, &mut fog, &heightfield);
+
+		// teammates see what allies see, enemies don't
+		assert!(fog.is_visible(PlayerId(0), vec3(5.0, 0.0, 5.0)));
+		assert!(!fog.is_visible(PlayerId(2), vec3(5.0, 0.0, 5.0)));
+
+		world.players.set_stance(PlayerId(1), PlayerId(2), Stance::Ally);
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert!(fog.is_visible(PlayerId(2), vec3(5.0, 0.0, 5.0)));
+
+		// shared sight fades like own sight once the alliance is over
+		world.players.set_stance(PlayerId(1), PlayerId(2), Stance::Enemy);
+		update_visibility(&world, &mut fog, &heightfield);
+
+		assert_eq!(fog.visibility_at(PlayerId(2), vec3(5.0, 0.0, 5.0)), Visibility::Explored);
+		assert!(fog.is_visible(PlayerId(0), vec3(5.0, 0.0, 5.0)));
+	}
+
+}
@@ -0,0 +1,374 @@
+use ::game::{Pose, Owner, Health, Movement, Renderable, Selectable, OrderQueue, SpatialHash};
+use ::game::{Worker, ResourceNode, DropOff, Transport, Building, Obstacle, Producer, Weapon, Armor, Vision, CombatEvent, Projectile};
+use ::game::{PlayerId, Players, Stockpile, ProductionEvent};
+use ::gfx::scene::MeshInstanceHandle;
+use ::navigation::Cell;
+use ::math::*;
+
+/// Side of a spatial hash cell, a few unit sizes
+const SPATIAL_HASH_CELL_SIZE: Real = 4.0;
+
+/// Generational entity index, stale ids of destroyed entities never match
+/// a newly spawned entity reusing the same slot
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct EntityId {
+	index: u32,
+	generation: u32,
+}
+
+impl EntityId {
+
+	/// Rebuilds an id received over the network or read from a replay
+	pub fn from_parts(index: u32, generation: u32) -> Self {
+		EntityId {
+			index: index,
+			generation: generation,
+		}
+	}
+
+	pub fn index(&self) -> usize {
+		self.index as usize
+	}
+
+	pub fn generation(&self) -> u32 {
+		self.generation
+	}
+
+}
+
+/// Component values indexed by entity slot.
+/// Iteration goes in slot order, so systems visit entities deterministically.
+pub struct ComponentStorage<T> {
+	slots: Vec<Option<(u32, T)>>,
+}
+
+impl<T> ComponentStorage<T> {
+
+	pub fn new() -> Self {
+		ComponentStorage {
+			slots: Vec::new(),
+		}
+	}
+
+	pub fn insert(&mut self, id: EntityId, component: T) {
+		let index = id.index();
+		while self.slots.len() <= index {
+			self.slots.push(None);
+		}
+		self.slots[index] = Some((id.generation, component));
+	}
+
+	pub fn remove(&mut self, id: EntityId) -> Option<T> {
+		if self.contains(id) {
+			self.slots[id.index()].take().map(|(_, component)| component)
+		} else {
+			None
+		}
+	}
+
+	pub fn contains(&self, id: EntityId) -> bool {
+		self.get(id).is_some()
+	}
+
+	pub fn get(&self, id: EntityId) -> Option<&T> {
+		match self.slots.get(id.index()) {
+			Some(&Some((generation, ref component))) if generation == id.generation => Some(component),
+			_ => None,
+		}
+	}
+
+	pub fn get_mut(&mut self, id: EntityId) -> Option<&mut T> {
+		match self.slots.get_mut(id.index()) {
+			Some(&mut Some((generation, ref mut component))) if generation == id.generation => Some(component),
+			_ => None,
+		}
+	}
+
+	pub fn ids(&self) -> Vec<EntityId> {
+		self.iter().map(|(id, _)| id).collect()
+	}
+
+	pub fn iter(&self) -> Iter<T> {
+		Iter {
+			slots: self.slots.iter().enumerate(),
+		}
+	}
+
+	pub fn iter_mut(&mut self) -> IterMut<T> {
+		IterMut {
+			slots: self.slots.iter_mut().enumerate(),
+		}
+	}
+
+}
+
+pub struct Iter<'a, T: 'a> {
+	slots: ::std::iter::Enumerate<::std::slice::Iter<'a, Option<(u32, T)>>>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+	type Item = (EntityId, &'a T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while let Some((index, slot)) = self.slots.next() {
+			if let Some((generation, ref component)) = *slot {
+				return Some((EntityId { index: index as u32, generation: generation }, component));
+			}
+		}
+		None
+	}
+}
+
+pub struct IterMut<'a, T: 'a> {
+	slots: ::std::iter::Enumerate<::std::slice::IterMut<'a, Option<(u32, T)>>>,
+}
+
+impl<'a, T> Iterator for IterMut<'a, T> {
+	type Item = (EntityId, &'a mut T);
+
+	fn next(&mut self) -> Option<Self::Item> {
+		while let Some((index, slot)) = self.slots.next() {
+			if let Some((generation, ref mut component)) = *slot {
+				return Some((EntityId { index: index as u32, generation: generation }, component));
+			}
+		}
+		None
+	}
+}
+
+/// Game side state of everything that exists on the map.
+/// Knows nothing about the renderer apart from mesh handles,
+/// so it can be simulated and tested headless.
+pub struct World {
+	generations: Vec<u32>,
+	alive: Vec<bool>,
+	free_indices: Vec<u32>,
+
+	pub poses: ComponentStorage<Pose>,
+	pub owners: ComponentStorage<Owner>,
+	pub healths: ComponentStorage<Health>,
+	pub movements: ComponentStorage<Movement>,
+	pub renderables: ComponentStorage<Renderable>,
+	pub selectables: ComponentStorage<Selectable>,
+	pub order_queues: ComponentStorage<OrderQueue>,
+	pub workers: ComponentStorage<Worker>,
+	pub resource_nodes: ComponentStorage<ResourceNode>,
+	pub drop_offs: ComponentStorage<DropOff>,
+	pub transports: ComponentStorage<Transport>,
+	pub buildings: ComponentStorage<Building>,
+	pub obstacles: ComponentStorage<Obstacle>,
+	pub producers: ComponentStorage<Producer>,
+	pub weapons: ComponentStorage<Weapon>,
+	pub armors: ComponentStorage<Armor>,
+	pub visions: ComponentStorage<Vision>,
+
+	/// Entity positions as of the last `update_spatial_hash`
+	pub spatial_hash: SpatialHash,
+	/// Projectiles in flight, in the order they were fired
+	pub projectiles: Vec<Projectile>,
+	pub players: Players,
+
+	removed_mesh_instances: Vec<MeshInstanceHandle>,
+	freed_cells: Vec<Cell>,
+	combat_events: Vec<CombatEvent>,
+	production_events: Vec<ProductionEvent>,
+}
+
+impl World {
+
+	pub fn new() -> Self {
+		World {
+			generations: Vec::new(),
+			alive: Vec::new(),
+			free_indices: Vec::new(),
+
+			poses: ComponentStorage::new(),
+			owners: ComponentStorage::new(),
+			healths: ComponentStorage::new(),
+			movements: ComponentStorage::new(),
+			renderables: ComponentStorage::new(),
+			selectables: ComponentStorage::new(),
+			order_queues: ComponentStorage::new(),
+			workers: ComponentStorage::new(),
+			resource_nodes: ComponentStorage::new(),
+			drop_offs: ComponentStorage::new(),
+			transports: ComponentStorage::new(),
+			buildings: ComponentStorage::new(),
+			obstacles: ComponentStorage::new(),
+			producers: ComponentStorage::new(),
+			weapons: ComponentStorage::new(),
+			armors: ComponentStorage::new(),
+			visions: ComponentStorage::new(),
+
+			spatial_hash: SpatialHash::new(SPATIAL_HASH_CELL_SIZE),
+			projectiles: Vec::new(),
+			players: Players::new(),
+
+			removed_mesh_instances: Vec::new(),
+			freed_cells: Vec::new(),
+			combat_events: Vec::new(),
+			production_events: Vec::new(),
+		}
+	}
+
+	pub fn spawn(&mut self) -> EntityId {
+		match self.free_indices.pop() {
+			Some(index) => {
+				self.alive[index as usize] = true;
+				EntityId {
+					index: index,
+					generation: self.generations[index as usize],
+				}
+			}
+			None => {
+				self.generations.push(0);
+				self.alive.push(true);
+				EntityId {
+					index: (self.generations.len() - 1) as u32,
+					generation: 0,
+				}
+			}
+		}
+	}
+
+	pub fn is_alive(&self, id: EntityId) -> bool {
+		id.index() < self.alive.len() && self.alive[id.index()] && self.generations[id.index()] == id.generation
+	}
+
+	pub fn destroy(&mut self, id: EntityId) {
+		if !self.is_alive(id) {
+			return;
+		}
+
+		self.poses.remove(id);
+		self.owners.remove(id);
+		self.healths.remove(id);
+		self.movements.remove(id);
+		self.selectables.remove(id);
+		self.order_queues.remove(id);
+		self.workers.remove(id);
+		self.resource_nodes.remove(id);
+		self.drop_offs.remove(id);
+		self.buildings.remove(id);
+		self.producers.remove(id);
+		self.weapons.remove(id);
+		self.armors.remove(id);
+		self.visions.remove(id);
+		self.spatial_hash.remove(id);
+
+		if let Some(obstacle) = self.obstacles.remove(id) {
+			self.freed_cells.extend(obstacle.cells);
+		}
+
+		if let Some(renderable) = self.renderables.remove(id) {
+			if let Some(instance) = renderable.instance {
+				self.removed_mesh_instances.push(instance);
+			}
+		}
+
+		self.alive[id.index()] = false;
+		self.generations[id.index()] += 1;
+		self.free_indices.push(id.index() as u32);
+
+		if let Some(transport) = self.transports.remove(id) {
+			for passenger in transport.passengers {
+				self.destroy(passenger);
+			}
+		}
+	}
+
+	pub fn entities(&self) -> Vec<EntityId> {
+		(0..self.alive.len())
+			.filter(|&index| self.alive[index])
+			.map(|index| EntityId {
+				index: index as u32,
+				generation: self.generations[index],
+			})
+			.collect()
+	}
+
+	/// Mesh instances of destroyed entities that still have to be removed from the scene
+	pub fn take_removed_mesh_instances(&mut self) -> Vec<MeshInstanceHandle> {
+		::std::mem::replace(&mut self.removed_mesh_instances, Vec::new())
+	}
+
+	/// Grid cells of destroyed obstacles that still have to be unblocked
+	pub fn take_freed_cells(&mut self) -> Vec<Cell> {
+		::std::mem::replace(&mut self.freed_cells, Vec::new())
+	}
+
+	pub fn stockpile(&self, player: PlayerId) -> Option<&Stockpile> {
+		self.players.get(player).map(|player| &player.stockpile)
+	}
+
+	pub fn stockpile_mut(&mut self, player: PlayerId) -> Option<&mut Stockpile> {
+		self.players.get_mut(player).map(|player| &mut player.stockpile)
+	}
+
+	pub fn is_researched(&self, player: PlayerId, upgrade: &str) -> bool {
+		self.players.get(player).map_or(false, |player| player.researched.contains(upgrade))
+	}
+
+	pub fn push_combat_event(&mut self, event: CombatEvent) {
+		self.combat_events.push(event);
+	}
+
+	/// Combat events since the last call
+	pub fn take_combat_events(&mut self) -> Vec<CombatEvent> {
+		::std::mem::replace(&mut self.combat_events, Vec::new())
+	}
+
+	pub fn push_production_event(&mut self, event: ProductionEvent) {
+		self.production_events.push(event);
+	}
+
+	/// Finished units and upgrades since the last call
+	pub fn take_production_events(&mut self) -> Vec<ProductionEvent> {
+		::std::mem::replace(&mut self.production_events, Vec::new())
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn test_spawn_destroy() {
+		let mut world = World::new();
+
+		let a = world.spawn();
+		let b = world.spawn();
+		world.healths.insert(a, Health::new(10.0));
+		world.healths.insert(b, Health::new(20.0));
+
+		world.destroy(a);
+		assert!(!world.is_alive(a));
+		assert!(world.healths.get(a).is_none());
+
+		// slot is reused, but the stale id stays dead
+		let c = world.spawn();
+		assert_eq!(c.index(), a.index());
+		assert!(c != a);
+		assert!(!world.is_alive(a));
+		assert!(world.healths.get(c).is_none());
+
+		world.healths.insert(c, Health::new(30.0));
+		assert!(world.healths.get(a).is_none());
+		assert_eq!(world.entities(), vec![c, b]);
+	}
+
+	#[test]
+	fn test_storage_iteration_order() {
+		let mut world = World::new();
+		let ids: Vec<EntityId> = (0..5).map(|_| world.spawn()).collect();
+
+		for &id in ids.iter().rev() {
+			world.poses.insert(id, Pose::new(vec3(id.index() as Real, 0.0, 0.0)));
+		}
+
+		assert_eq!(world.poses.ids(), ids);
+	}
+
+}
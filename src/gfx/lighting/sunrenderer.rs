@@ -1,4 +1,4 @@
-use glium::{Display, Program, Surface, VertexBuffer, IndexBuffer, DrawParameters};
+use glium::{Display, Program, Surface, VertexBuffer, IndexBuffer, DrawParameters, Texture2d};
 use glium::texture::DepthTexture2d;
 use glium::index::PrimitiveType;
 
@@ -52,11 +52,16 @@ impl SunRenderer {
 			uniform mat4 u_inverse_view_matrix;
 			uniform sampler2D u_shadow_map;
 			uniform mat4 u_shadow_map_view_projection_matrix;
+			uniform sampler2D u_fog_map;
+			uniform vec2 u_fog_size;
 
 			out vec4 color;
 
 			#define PI 3.1415926
 
+			// brightness of the ground that was explored but isn't in sight now
+			#define EXPLORED_BRIGHTNESS 0.4
+
 			// phong (lambertian) diffuse term
 			float phong_diffuse()
 			{
@@ -138,6 +143,16 @@ impl SunRenderer {
 				return position;
 			}
 
+			// fog map stores 0 for unexplored, 0.5 for explored and 1 for visible
+			float fog_brightness(in vec3 position) {
+				vec2 fog_coord = position.xz / u_fog_size;
+				if (fog_coord.x < 0.0 || fog_coord.x > 1.0 || fog_coord.y < 0.0 || fog_coord.y > 1.0) {
+					return 1.0;
+				}
+				float fog = texture(u_fog_map, fog_coord).r;
+				return clamp(fog * 2.0, 0.0, 1.0) * EXPLORED_BRIGHTNESS + clamp(fog * 2.0 - 1.0, 0.0, 1.0) * (1.0 - EXPLORED_BRIGHTNESS);
+			}
+
 			void main() {
 				vec4 albedo_metallic = texture(u_albedo_metallic_map, v_position);
 				vec4 normal_roughness = texture(u_normal_roughness_map, v_position);
@@ -167,7 +182,7 @@ impl SunRenderer {
 				|| shadow_map_coord.y > 1.0
 				|| shadow_map_coord.y < 0.0 ? 1.0 : 0.0);
 
-				color = vec4(light * shadow + emission.rgb, 1);
+				color = vec4((light * shadow + emission.rgb) * fog_brightness(position), 1);
 			}
 		"#;
 
@@ -206,7 +221,9 @@ impl SunRenderer {
 		camera: &CameraRenderParams, 
 		sun: &Sun, 
 		shadow_map: &DepthTexture2d, 
-		shadow_map_view_projection_matrix: Matrix4
+		shadow_map_view_projection_matrix: Matrix4,
+		fog_map: &Texture2d,
+		fog_size: Vector2
 	) {
 
 		let uniforms = uniform! {
@@ -223,6 +240,9 @@ impl SunRenderer {
 
 			u_shadow_map: shadow_map,
 			u_shadow_map_view_projection_matrix: matrix4_to_array(shadow_map_view_projection_matrix),
+
+			u_fog_map: fog_map,
+			u_fog_size: [fog_size.x, fog_size.y],
 		};
 
 		target.draw(&self.vertex_buffer, &self.index_buffer, &self.shader, &uniforms, draw_parameters).unwrap();
@@ -7,6 +7,9 @@ use ::gfx::scene::MeshInstance;
 use ::gfx::rendering::{RenderParams, RenderPassType};
 use ::math::*;
 
+/// How much of the albedo the team color replaces
+const TEAM_COLOR_AMOUNT: Real = 0.5;
+
 pub struct MeshRenderer {
 	shader: Program,
 	shadow_map_shader: Program,
@@ -47,6 +50,9 @@ impl MeshRenderer {
 					uniform sampler2D u_albedo_map;
 					uniform sampler2D u_roughness_map;
 					uniform sampler2D u_metallic_map;
+					uniform vec3 u_emission;
+					uniform vec3 u_team_color;
+					uniform float u_team_color_amount;
 
 					out vec4 o_albedo_metallic;
 					out vec4 o_normal_roughness;
@@ -54,9 +60,11 @@ impl MeshRenderer {
 
 					void main() {
 						vec3 packed_normal = (v_normal + vec3(1.0)) * 0.5;
-						o_albedo_metallic = vec4(texture(u_albedo_map, v_uv).rgb, texture(u_metallic_map, v_uv).r);
+						vec3 albedo = texture(u_albedo_map, v_uv).rgb;
+						albedo = mix(albedo, u_team_color, u_team_color_amount);
+						o_albedo_metallic = vec4(albedo, texture(u_metallic_map, v_uv).r);
 						o_normal_roughness = vec4(packed_normal, texture(u_roughness_map, v_uv).r);
-						o_emission = vec4(0.0);
+						o_emission = vec4(u_emission, 0.0);
 					}
 				"#;
 
@@ -87,11 +95,13 @@ impl MeshRenderer {
 		}
 	}
 
-	pub fn draw_mesh_instance<F: Surface>(&self, target: &mut F, params: &RenderParams, object: &MeshInstance) {
+	/// `flash` is light from flashes around the instance, added to its own emission
+	pub fn draw_mesh_instance<F: Surface>(&self, target: &mut F, params: &RenderParams, object: &MeshInstance, flash: Vector3) {
 		let mesh = object.mesh.asset.borrow();
 		let (vertex_buffer, index_buffer) = mesh.get_buffers();
 
-		let model_transform = object.spatial.transform_matrix();
+		let spatial = object.interpolated_spatial(params.interpolation_alpha);
+		let model_transform = spatial.transform_matrix();
 
 		let transform = params.camera.view_projection_matrix * model_transform;
 
@@ -100,12 +110,22 @@ impl MeshRenderer {
 		let roughness_map = material.roughness_map.asset.borrow();
 		let metallic_map = material.metallic_map.asset.borrow();
 
+		let (team_color, team_color_amount) = match object.team_color {
+			Some(color) => (color, TEAM_COLOR_AMOUNT),
+			None => (vec3(1.0, 1.0, 1.0), 0.0),
+		};
+
+		let emission = object.emission + flash;
+
 		let uniforms = uniform! {
 			transform: matrix4_to_array(transform),
-			normal_transform: matrix3_to_array(object.spatial.rotation_matrix()),
+			normal_transform: matrix3_to_array(spatial.rotation_matrix()),
 			u_albedo_map: albedo_map.deref(),
 			u_roughness_map: roughness_map.deref(),
 			u_metallic_map: metallic_map.deref(),
+			u_emission: [emission.x, emission.y, emission.z],
+			u_team_color: [team_color.x, team_color.y, team_color.z],
+			u_team_color_amount: team_color_amount,
 		};
 
 		let mut draw_parameters = params.draw_parameters.clone();
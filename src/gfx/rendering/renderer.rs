@@ -1,10 +1,12 @@
 use glium::glutin::{EventsLoop, WindowBuilder, ContextBuilder};
 use glium::{Surface, Display, Rect, DrawParameters, Texture2d, SyncFence};
-use glium::texture::DepthTexture2d;
+use glium::texture::{DepthTexture2d, RawImage2d, MipmapsOption, UncompressedFloatFormat};
 use glium::framebuffer::MultiOutputFrameBuffer;
 
+use std::cell::RefCell;
 
-use ::gfx::scene::{Scene, CameraRenderParams, Camera};
+
+use ::gfx::scene::{Scene, CameraRenderParams, Camera, FogMap};
 use ::gfx::rendering::{MeshRenderer, GBuffer, RenderParams, RenderPassType};
 use ::gfx::lighting::SunRenderer;
 use ::gfx::terrain::TessTerrainRenderer;
@@ -20,6 +22,10 @@ pub struct Renderer {
 	g_buffer: GBuffer,
 
 	sun_renderer: SunRenderer,
+	/// Used when the scene has no fog of war, everything is in sight
+	clear_fog_texture: Texture2d,
+	/// Fog of war of the scene and the version of the fog map last written to it
+	fog_texture: RefCell<Option<(Texture2d, u64)>>,
 }
 
 impl Renderer {
@@ -39,12 +45,16 @@ impl Renderer {
 
 		let g_buffer = GBuffer::new(&display, (1024 * 2, 768 * 2));
 
+		let clear_fog_texture = Texture2d::new(&display, RawImage2d::from_raw_rgba(vec![255u8; 4], (1, 1))).unwrap();
+
 		Renderer {
 			display: display,
 			mesh_renderer: mesh_renderer,
 			terrain_renderer: terrain_renderer,
 			g_buffer: g_buffer,
 			sun_renderer: sun_renderer,
+			clear_fog_texture: clear_fog_texture,
+			fog_texture: RefCell::new(None),
 		}
 	}
 
@@ -52,7 +62,8 @@ impl Renderer {
 		&self.display
 	}
 
-	pub fn render(&self, scene: &Scene) {		
+	/// `alpha` is how far the frame is between the previous and the current simulation tick
+	pub fn render(&self, scene: &Scene, alpha: Real) {		
 
 		let viewport = (1024 * 2, 768 * 2);
 
@@ -77,6 +88,7 @@ impl Renderer {
 				camera: camera,
 				draw_parameters: draw_parameters.clone(),
 				pass_type: RenderPassType::GBuffer,
+				interpolation_alpha: alpha,
 			};
 
 			self.draw_scene(&mut target, &render_parameters, scene);
@@ -111,6 +123,7 @@ impl Renderer {
 					camera: shadow_camera_params,
 					draw_parameters: shadow_draw_parameters,
 					pass_type: RenderPassType::ShadowMap,
+					interpolation_alpha: alpha,
 				};
 
 				self.draw_scene(&mut shadow_map_target, &shadow_render_parameters, scene);
@@ -118,7 +131,16 @@ impl Renderer {
 				let fence = SyncFence::new(&self.display).unwrap();
 				fence.wait();
 
-				self.sun_renderer.draw_sun_lighting(&mut target, &draw_parameters, &self.g_buffer, &camera, sun, &shadow_map, shadow_camera_params.view_projection_matrix);
+				if let Some(ref fog) = scene.fog {
+					self.update_fog_texture(fog);
+				}
+				let fog_texture = self.fog_texture.borrow();
+				let (fog_map, fog_size) = match (fog_texture.as_ref(), scene.fog.as_ref()) {
+					(Some(&(ref texture, _)), Some(fog)) => (texture, fog.size),
+					_ => (&self.clear_fog_texture, vec2(1.0, 1.0)),
+				};
+
+				self.sun_renderer.draw_sun_lighting(&mut target, &draw_parameters, &self.g_buffer, &camera, sun, &shadow_map, shadow_camera_params.view_projection_matrix, fog_map, fog_size);
 			}
 
 			target.finish().unwrap();
@@ -126,9 +148,45 @@ impl Renderer {
 
 	}
 
+	/// Uploads the fog of war when it changed since the last frame.
+	/// The texture is only made anew when the map size changes.
+	fn update_fog_texture(&self, fog: &FogMap) {
+		let (width, height) = (fog.width as u32, fog.height as u32);
+		let mut fog_texture = self.fog_texture.borrow_mut();
+
+		let resized = match *fog_texture {
+			Some((ref texture, _)) => texture.width() != width || texture.height() != height,
+			None => true,
+		};
+		if !resized && fog_texture.as_ref().map_or(false, |&(_, version)| version == fog.version) {
+			return;
+		}
+
+		let pixels: Vec<u8> = fog.values.iter().flat_map(|&value| vec![value, value, value, 255]).collect();
+		let image = RawImage2d::from_raw_rgba(pixels, (width, height));
+
+		if resized {
+			let texture = Texture2d::empty_with_format(&self.display, UncompressedFloatFormat::U8U8U8U8, MipmapsOption::NoMipmap, width, height).unwrap();
+			*fog_texture = Some((texture, fog.version));
+		}
+
+		if let Some((ref texture, ref mut version)) = *fog_texture {
+			texture.write(Rect { left: 0, bottom: 0, width: width, height: height }, image);
+			*version = fog.version;
+		}
+	}
+
 	fn draw_scene<Target: Surface>(&self, target: &mut Target, render_parameters: &RenderParams, scene: &Scene) {
 		for entity_ref in scene.get_mesh_instances() {
-			self.mesh_renderer.draw_mesh_instance(target, &render_parameters, &entity_ref.0);
+			let instance = entity_ref.0.borrow();
+			// Instances hidden by the fog of war are skipped here rather than in the sun composite pass:
+			// by then the G-buffer only holds the nearest surface per pixel, so a hidden unit has already
+			// covered the ground behind it and masking it would leave a dark hole in its shape.
+			// Skipping the shadow pass too keeps hidden units from giving themselves away by their shadows.
+			if instance.visible {
+				let flash = scene.flash_emission_at(instance.interpolated_spatial(render_parameters.interpolation_alpha).position);
+				self.mesh_renderer.draw_mesh_instance(target, &render_parameters, &instance, flash);
+			}
 		}
 
 		if let Some(ref terrain) = scene.terrain {
@@ -1,6 +1,7 @@
 use glium::DrawParameters;
 
 use ::gfx::scene::{CameraRenderParams};
+use ::math::Real;
 
 pub enum RenderPassType {
 	GBuffer,
@@ -11,4 +12,6 @@ pub struct RenderParams<'a> {
 	pub camera: CameraRenderParams,
 	pub draw_parameters: DrawParameters<'a>,
 	pub pass_type: RenderPassType,
+	/// Position between the previous and the current simulation tick, 0..1
+	pub interpolation_alpha: Real,
 }
@@ -52,6 +52,7 @@ pub struct CameraRenderParams {
 	pub inverse_view_matrix: Matrix4,
 	pub inverse_projection_matrix: Matrix4,
 	pub frustum: Frustum,
+	pub frame_size: (u32, u32),
 }
 
 impl CameraRenderParams {
@@ -140,9 +141,65 @@ impl CameraRenderParams {
 			inverse_view_matrix: view_matrix.inverse_transform().unwrap(),
 			inverse_projection_matrix: projection_matrix.inverse_transform().unwrap(),
 			frustum: world_space_frustum,
+			frame_size: frame_size,
 		}
 	}
 
+	/// Builds a world space ray going through the point of the frame,
+	/// given in pixels with origin at the top left corner.
+	/// Ray starts at the near plane.
+	pub fn screen_point_to_ray(&self, px: Real, py: Real) -> Ray {
+		let x = px / (self.frame_size.0 as Real) * 2.0 - 1.0;
+		let y = 1.0 - py / (self.frame_size.1 as Real) * 2.0;
+
+		let near = self.unproject(vec3(x, y, -1.0));
+		let far = self.unproject(vec3(x, y, 1.0));
+
+		Ray::new(near, far - near)
+	}
+
+	/// Part of the frustum seen through a rectangle of the frame,
+	/// corners are given in pixels like in `screen_point_to_ray`.
+	/// Near and far planes are the same as the whole frustum has.
+	pub fn screen_rect_frustum(&self, a: (Real, Real), b: (Real, Real)) -> Frustum {
+		let min = (a.0.min(b.0), a.1.min(b.1));
+		let max = (a.0.max(b.0), a.1.max(b.1));
+
+		let top_left = self.screen_point_to_ray(min.0, min.1);
+		let top_right = self.screen_point_to_ray(max.0, min.1);
+		let bottom_left = self.screen_point_to_ray(min.0, max.1);
+		let bottom_right = self.screen_point_to_ray(max.0, max.1);
+
+		let inside = self.screen_point_to_ray((min.0 + max.0) * 0.5, (min.1 + max.1) * 0.5).point_at(1.0);
+
+		let side = |first: &Ray, second: &Ray| {
+			let plane = Plane::from_points(first.origin, first.point_at(1.0), second.origin);
+			if plane.oriented_distance(inside) < 0.0 {
+				Plane {
+					normal: -plane.normal,
+					d: -plane.d,
+				}
+			} else {
+				plane
+			}
+		};
+
+		Frustum {
+			left: side(&top_left, &bottom_left),
+			right: side(&top_right, &bottom_right),
+			top: side(&top_left, &top_right),
+			bottom: side(&bottom_left, &bottom_right),
+			near: self.frustum.near,
+			far: self.frustum.far,
+		}
+	}
+
+	fn unproject(&self, ndc: Vector3) -> Vector3 {
+		let view = self.inverse_projection_matrix * ndc.extend(1.0);
+		let world = self.inverse_view_matrix * (view / view.w);
+		world.truncate()
+	}
+
 }
 
 #[cfg(test)]
@@ -276,5 +333,76 @@ mod tests {
 
 	}
 
+	fn assert_near(a: Vector3, b: Vector3) {
+		assert!((a - b).magnitude() < 0.001, "{:?} != {:?}", a, b);
+	}
+
+	#[test]
+	fn test_screen_point_to_ray() {
+		let mut camera = Camera {
+			z_near: 1.0,
+			z_far: 1000.0,
+			fov_y: Deg(90.0),
+			projection: CameraProjection::Perspective,
+			.. Default::default()
+		};
+		camera.spatial.position = vec3(10.0, 0.0, 0.0);
+
+		let camera_params = CameraRenderParams::new(&camera, (100, 100));
+
+		let center = camera_params.screen_point_to_ray(50.0, 50.0);
+		assert_near(center.origin, vec3(10.0, 0.0, 1.0));
+		assert_near(center.direction, vec3(0.0, 0.0, 1.0));
+
+		let top_left = camera_params.screen_point_to_ray(0.0, 0.0);
+		assert_near(top_left.origin, vec3(9.0, 1.0, 1.0));
+		assert_near(top_left.direction, vec3(-1.0, 1.0, 1.0).normalize());
+
+		camera.spatial.rotation = Quaternion::from_angle_x(Deg(90.0));
+		let camera_params = CameraRenderParams::new(&camera, (100, 100));
+
+		let down = camera_params.screen_point_to_ray(50.0, 50.0);
+		assert_near(down.direction, vec3(0.0, -1.0, 0.0));
+	}
+
+	#[test]
+	fn test_screen_rect_frustum() {
+		let camera = Camera {
+			z_near: 1.0,
+			z_far: 1000.0,
+			fov_y: Deg(90.0),
+			projection: CameraProjection::Perspective,
+			.. Default::default()
+		};
+		let camera_params = CameraRenderParams::new(&camera, (100, 100));
+
+		// left half of the screen, corners given in any order
+		let frustum = camera_params.screen_rect_frustum((50.0, 100.0), (0.0, 0.0));
+		let unit_box = |center| AABB3::from_center_size(center, vec3(0.5, 0.5, 0.5));
+
+		assert_eq!(intersect_frustum_aabb(&frustum, &unit_box(vec3(-5.0, 0.0, 10.0))), IntersectionTestResult::Inside);
+		assert_eq!(intersect_frustum_aabb(&frustum, &unit_box(vec3(5.0, 0.0, 10.0))), IntersectionTestResult::Outside);
+		assert_eq!(intersect_frustum_aabb(&frustum, &unit_box(vec3(0.0, 0.0, 10.0))), IntersectionTestResult::Intersect);
+		assert_eq!(intersect_frustum_aabb(&frustum, &unit_box(vec3(-5.0, 0.0, 2000.0))), IntersectionTestResult::Outside);
+
+		// small box around the center
+		let frustum = camera_params.screen_rect_frustum((45.0, 45.0), (55.0, 55.0));
+		assert!(intersect_frustum_aabb(&frustum, &unit_box(vec3(0.0, 0.0, 10.0))) != IntersectionTestResult::Outside);
+		assert_eq!(intersect_frustum_aabb(&frustum, &unit_box(vec3(0.0, 5.0, 10.0))), IntersectionTestResult::Outside);
+	}
+
+	#[test]
+	fn test_ortho_screen_point_to_ray() {
+		let camera = Camera::ortho(10.0);
+		let camera_params = CameraRenderParams::new(&camera, (100, 100));
+
+		let center = camera_params.screen_point_to_ray(50.0, 50.0);
+		let top = camera_params.screen_point_to_ray(50.0, 0.0);
+
+		assert_near(center.direction, vec3(0.0, 0.0, 1.0));
+		assert_near(top.direction, vec3(0.0, 0.0, 1.0));
+		assert_near(top.origin - center.origin, vec3(0.0, 5.0, 0.0));
+	}
+
 }
 
@@ -0,0 +1,65 @@
+use ::math::*;
+
+/// Burst of light, e.g. where a shell came down. Meshes within the radius glow with it
+/// while it fades out.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Flash {
+	pub position: Vector3,
+	pub radius: Real,
+	pub color: Vector3,
+	/// Seconds until it's gone
+	pub duration: Real,
+	/// Seconds since it went off
+	pub age: Real,
+}
+
+impl Flash {
+
+	pub fn new(position: Vector3, radius: Real, color: Vector3, duration: Real) -> Self {
+		Flash {
+			position: position,
+			radius: radius,
+			color: color,
+			duration: duration,
+			age: 0.0,
+		}
+	}
+
+	pub fn is_over(&self) -> bool {
+		self.age >= self.duration
+	}
+
+	/// Light given to a mesh at the point, falling off with the distance and the age
+	pub fn emission_at(&self, point: Vector3) -> Vector3 {
+		if self.is_over() || self.radius <= 0.0 {
+			return vec3(0.0, 0.0, 0.0);
+		}
+
+		let falloff = 1.0 - (point - self.position).magnitude() / self.radius;
+		let fade = 1.0 - self.age / self.duration;
+		self.color * (falloff.max(0.0) * fade)
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn test_fade() {
+		let mut flash = Flash::new(vec3(10.0, 0.0, 10.0), 4.0, vec3(2.0, 1.0, 0.0), 1.0);
+		assert_eq!(flash.emission_at(vec3(10.0, 0.0, 10.0)), vec3(2.0, 1.0, 0.0));
+		assert_eq!(flash.emission_at(vec3(12.0, 0.0, 10.0)), vec3(1.0, 0.5, 0.0));
+		assert_eq!(flash.emission_at(vec3(15.0, 0.0, 10.0)), vec3(0.0, 0.0, 0.0));
+
+		flash.age = 0.5;
+		assert_eq!(flash.emission_at(vec3(10.0, 0.0, 10.0)), vec3(1.0, 0.5, 0.0));
+
+		flash.age = 1.0;
+		assert!(flash.is_over());
+		assert_eq!(flash.emission_at(vec3(10.0, 0.0, 10.0)), vec3(0.0, 0.0, 0.0));
+	}
+
+}
@@ -0,0 +1,16 @@
+use ::math::*;
+
+/// Fog of war as the viewing player knows it, darkens the ground that isn't in sight
+pub struct FogMap {
+	pub width: usize,
+	pub height: usize,
+	/// Size of the covered area in world units, starting at the origin
+	pub size: Vector2,
+	/// How much of every cell is seen: 0 unexplored, `FOG_EXPLORED` explored, 255 visible.
+	/// Row-major, first row at z = 0.
+	pub values: Vec<u8>,
+	/// Goes up every time the values change, the renderer uploads them again then
+	pub version: u64,
+}
+
+pub const FOG_EXPLORED: u8 = 128;
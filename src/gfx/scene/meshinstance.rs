@@ -1,10 +1,54 @@
-use ::math::Spatial;
+use ::math::{Real, Spatial, Vector3, vec3};
 use ::gfx::resources::Mesh;
 use ::assets::Asset;
 
 pub struct MeshInstance {
+	/// Spatial at the latest simulation tick
 	pub spatial: Spatial,
+	/// Spatial at the tick before, rendering interpolates from it to `spatial`
+	pub previous_spatial: Spatial,
 	pub is_static: bool,
+	/// Hidden instances are not drawn at all, e.g. enemies in the fog of war
+	pub visible: bool,
+	/// Light the mesh gives off itself, written to the emission channel of the G-buffer
+	pub emission: Vector3,
+	/// Color of the owner's team the albedo is tinted with, so players tell their units apart
+	pub team_color: Option<Vector3>,
 	pub mesh: Asset<Mesh>,
 }
 
+impl MeshInstance {
+
+	pub fn new(mesh: Asset<Mesh>, spatial: Spatial) -> Self {
+		MeshInstance {
+			spatial: spatial,
+			previous_spatial: spatial,
+			is_static: false,
+			visible: true,
+			emission: vec3(0.0, 0.0, 0.0),
+			team_color: None,
+			mesh: mesh,
+		}
+	}
+
+	/// Moves the instance at the end of a simulation tick
+	pub fn update_spatial(&mut self, spatial: Spatial) {
+		self.previous_spatial = self.spatial;
+		self.spatial = spatial;
+	}
+
+	/// Moves the instance without interpolating from the old position
+	pub fn teleport(&mut self, spatial: Spatial) {
+		self.previous_spatial = spatial;
+		self.spatial = spatial;
+	}
+
+	pub fn interpolated_spatial(&self, alpha: Real) -> Spatial {
+		if self.is_static {
+			self.spatial
+		} else {
+			self.previous_spatial.lerp(&self.spatial, alpha)
+		}
+	}
+
+}
@@ -1,9 +1,13 @@
 mod camera;
+mod flash;
+mod fog;
 mod meshinstance;
 mod scene;
 mod sun;
 
 pub use self::camera::*;
+pub use self::flash::*;
+pub use self::fog::*;
 pub use self::meshinstance::*;
 pub use self::scene::*;
 pub use self::sun::*;
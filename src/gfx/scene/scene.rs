@@ -1,15 +1,16 @@
 use std::rc::Rc;
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::hash::{Hash, Hasher};
 
 
-use ::gfx::scene::{Camera, MeshInstance, Sun};
+use ::gfx::scene::{Camera, MeshInstance, Sun, FogMap, Flash};
 use ::terrain::Terrain;
 use ::assets::Asset;
 use ::math::*;
 
 #[derive(Clone)]
-pub struct MeshInstanceHandle(pub Rc<MeshInstance>);
+pub struct MeshInstanceHandle(pub Rc<RefCell<MeshInstance>>);
 
 impl PartialEq for MeshInstanceHandle {
 	fn eq(&self, other: &MeshInstanceHandle) -> bool {
@@ -32,6 +33,8 @@ pub struct Scene {
 	mesh_instances: HashSet<MeshInstanceHandle>,
 	pub terrain: Option<Asset<Terrain>>,
 	pub sun: Option<Sun>,
+	pub fog: Option<FogMap>,
+	pub flashes: Vec<Flash>,
 	pub ambient_light: Vector3, 
 }
 
@@ -43,21 +46,40 @@ impl Scene {
 			mesh_instances: HashSet::new(),
 			terrain: None,
 			sun: None,
+			fog: None,
+			flashes: Vec::new(),
 			ambient_light: vec3(0.1, 0.1, 0.1),
 		}
 	}
 
 	pub fn add_mesh_instance(&mut self, instance: MeshInstance) -> MeshInstanceHandle {
-		let handle = MeshInstanceHandle(Rc::new(instance));
+		let handle = MeshInstanceHandle(Rc::new(RefCell::new(instance)));
 		self.mesh_instances.insert(handle.clone());
 
 		handle
 	}
 
+	pub fn remove_mesh_instance(&mut self, handle: &MeshInstanceHandle) -> bool {
+		self.mesh_instances.remove(handle)
+	}
+
 	pub fn get_mesh_instances(&self) -> &HashSet<MeshInstanceHandle> {
 		return &self.mesh_instances;
 	}
 
+	/// Ages the flashes by the frame time and drops the ones that faded out
+	pub fn update_flashes(&mut self, delta_time: Real) {
+		for flash in self.flashes.iter_mut() {
+			flash.age += delta_time;
+		}
+		self.flashes.retain(|flash| !flash.is_over());
+	}
+
+	/// Light all flashes give to a mesh at the point
+	pub fn flash_emission_at(&self, point: Vector3) -> Vector3 {
+		self.flashes.iter().fold(vec3(0.0, 0.0, 0.0), |sum, flash| sum + flash.emission_at(point))
+	}
+
 	pub fn camera(&self) -> &Camera {
 		return &self.camera
 	}
@@ -215,6 +215,7 @@ impl TerrainRenderer {
 
 	fn draw_terrain_node<Target: Surface>(&self, target: &mut Target, params: &RenderParams, terrain: &Terrain, node: &RenderNode) {
 		let transform = params.camera.view_projection_matrix;
+		let scale = terrain.scale();
 
 		let map = terrain.map.asset.borrow();
 		let material = terrain.materials[0].asset.borrow();
@@ -224,7 +225,7 @@ impl TerrainRenderer {
 
 		let uniforms = uniform! {
 			u_transform: matrix4_to_array(transform),
-			u_scale: [terrain.scale.x, terrain.scale.y, terrain.scale.z],
+			u_scale: [scale.x, scale.y, scale.z],
 			u_map: map.deref(),
 			u_albedo_map: albedo.deref(),
 			u_lod_offset: [node.offset.0 as Real * inv_lod, node.offset.1 as Real * inv_lod],
@@ -248,7 +249,7 @@ impl TerrainRenderer {
 
 		let uniforms = uniform! {
 			u_transform: matrix4_to_array(transform),
-			u_scale: [terrain.scale.x, terrain.scale.y, terrain.scale.z],
+			u_scale: [scale.x, scale.y, scale.z],
 			u_map: map.deref(),
 			u_albedo_map: albedo.deref(),
 			u_lod_offset: [node.offset.0 as Real * inv_lod, node.offset.1 as Real * inv_lod],
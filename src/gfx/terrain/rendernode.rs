@@ -12,10 +12,10 @@ pub struct RenderNode {
 impl RenderNode {
 
 	pub fn bounds(&self, terrain: &Terrain) -> AABB3 {
-		let step = terrain.scale / (self.lod as Real);
+		let step = terrain.scale() / (self.lod as Real);
 		AABB3 { 
 			min: vec3(step.x * (self.offset.0 as Real), 		0.0, 				step.z * (self.offset.1 as Real)),
-			max: vec3(step.x * ((self.offset.0 + 1) as Real), 	terrain.scale.y, 	step.z * ((self.offset.1 + 1) as Real)),
+			max: vec3(step.x * ((self.offset.0 + 1) as Real), 	terrain.scale().y, 	step.z * ((self.offset.1 + 1) as Real)),
 		}
 	}
 
@@ -34,9 +34,9 @@ impl RenderNode {
 			
 			if intersect_frustum_aabb(&camera.frustum, &node.bounds(terrain)) != IntersectionTestResult::Outside {
 				
-				let node_size = terrain.scale.x / (node.lod as Real);
+				let node_size = terrain.scale().x / (node.lod as Real);
 
-				if node_size < 50.0 || terrain.scale.x / (node.lod as Real) * 1.3 < camera.spatial.position.distance(node.bounds(terrain).center()) {
+				if node_size < 50.0 || terrain.scale().x / (node.lod as Real) * 1.3 < camera.spatial.position.distance(node.bounds(terrain).center()) {
 					result.push(node);
 				} else {
 					let lod = node.lod * 2;
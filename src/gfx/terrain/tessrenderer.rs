@@ -194,6 +194,7 @@ impl TessTerrainRenderer {
 
 	pub fn draw_terrain<Target: Surface>(&self, target: &mut Target, params: &RenderParams, terrain: &Terrain) {
 		let transform = params.camera.view_projection_matrix;
+		let scale = terrain.scale();
 
 		let map = terrain.map.asset.borrow();
 		let material = terrain.materials[0].asset.borrow();
@@ -201,7 +202,7 @@ impl TessTerrainRenderer {
 
 		let uniforms = uniform! {
 			u_transform: matrix4_to_array(transform),
-			u_scale: [terrain.scale.x, terrain.scale.y, terrain.scale.z],
+			u_scale: [scale.x, scale.y, scale.z],
 			u_map: map.deref(),
 			u_albedo_map: albedo.deref(),
 			u_lines_highlight: 0.0 as Real,
@@ -223,7 +224,7 @@ impl TessTerrainRenderer {
 
 		let uniforms = uniform! {
 			u_transform: matrix4_to_array(transform),
-			u_scale: [terrain.scale.x, terrain.scale.y, terrain.scale.z],
+			u_scale: [scale.x, scale.y, scale.z],
 			u_map: map.deref(),
 			u_albedo_map: albedo.deref(),
 			u_lines_highlight: 1.0 as Real,
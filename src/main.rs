@@ -4,11 +4,14 @@ extern crate cgmath;
 extern crate assimp;
 #[macro_use] extern crate enum_map;
 #[macro_use] extern crate enum_map_derive;
+extern crate toml;
 
 mod app;
 mod assets;
+mod game;
 mod gfx;
 mod math;
+mod navigation;
 mod terrain;
 
 use app::App;
@@ -2,10 +2,12 @@ pub mod prelude;
 mod aabb;
 mod frustum;
 mod plane;
+mod ray;
 mod spatial;
 
 pub use self::prelude::*;
 pub use self::aabb::*;
 pub use self::frustum::*;
 pub use self::plane::*;
+pub use self::ray::*;
 pub use self::spatial::*;
@@ -0,0 +1,164 @@
+use ::math::prelude::*;
+use ::math::{Plane, AABB3};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+	pub origin: Vector3,
+	pub direction: Vector3,
+}
+
+impl Ray {
+
+	pub fn new(origin: Vector3, direction: Vector3) -> Self {
+		Ray {
+			origin: origin,
+			direction: direction.normalize(),
+		}
+	}
+
+	pub fn point_at(&self, distance: Real) -> Vector3 {
+		self.origin + self.direction * distance
+	}
+
+}
+
+const EPSILON: Real = 0.000001;
+
+/// Returns distance along the ray to the plane, if the ray hits it in front of the origin
+pub fn intersect_ray_plane(ray: &Ray, plane: &Plane) -> Option<Real> {
+	let denominator = dot(plane.normal, ray.direction);
+	if denominator.abs() < EPSILON {
+		return None;
+	}
+
+	let distance = -plane.oriented_distance(ray.origin) / denominator;
+	if distance >= 0.0 {
+		Some(distance)
+	} else {
+		None
+	}
+}
+
+/// Returns distance along the ray to the first point inside the box,
+/// zero if the ray starts inside of it
+pub fn intersect_ray_aabb(ray: &Ray, aabb: &AABB3) -> Option<Real> {
+	let mut t_min: Real = 0.0;
+	let mut t_max = ::std::f32::INFINITY;
+
+	for axis in 0..3 {
+		let origin = ray.origin[axis];
+		let direction = ray.direction[axis];
+		let min = aabb.min[axis];
+		let max = aabb.max[axis];
+
+		if direction.abs() < EPSILON {
+			if origin < min || origin > max {
+				return None;
+			}
+		} else {
+			let inv_direction = 1.0 / direction;
+			let mut t0 = (min - origin) * inv_direction;
+			let mut t1 = (max - origin) * inv_direction;
+			if t0 > t1 {
+				::std::mem::swap(&mut t0, &mut t1);
+			}
+
+			t_min = t_min.max(t0);
+			t_max = t_max.min(t1);
+
+			if t_min > t_max {
+				return None;
+			}
+		}
+	}
+
+	Some(t_min)
+}
+
+/// Möller–Trumbore ray-triangle intersection, both triangle sides are hit
+pub fn intersect_ray_triangle(ray: &Ray, a: Vector3, b: Vector3, c: Vector3) -> Option<Real> {
+	let edge1 = b - a;
+	let edge2 = c - a;
+
+	let p = ray.direction.cross(edge2);
+	let determinant = dot(edge1, p);
+	if determinant.abs() < EPSILON {
+		return None;
+	}
+
+	let inv_determinant = 1.0 / determinant;
+
+	let s = ray.origin - a;
+	let u = dot(s, p) * inv_determinant;
+	if u < 0.0 || u > 1.0 {
+		return None;
+	}
+
+	let q = s.cross(edge1);
+	let v = dot(ray.direction, q) * inv_determinant;
+	if v < 0.0 || u + v > 1.0 {
+		return None;
+	}
+
+	let distance = dot(edge2, q) * inv_determinant;
+	if distance >= 0.0 {
+		Some(distance)
+	} else {
+		None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::math::vec3;
+
+	#[test]
+	fn test_ray_plane() {
+		let plane = Plane::from_points(vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+
+		let down = Ray::new(vec3(5.0, 10.0, 5.0), vec3(0.0, -1.0, 0.0));
+		assert_eq!(intersect_ray_plane(&down, &plane), Some(10.0));
+
+		let up = Ray::new(vec3(5.0, 10.0, 5.0), vec3(0.0, 1.0, 0.0));
+		assert_eq!(intersect_ray_plane(&up, &plane), None);
+
+		let parallel = Ray::new(vec3(5.0, 10.0, 5.0), vec3(1.0, 0.0, 0.0));
+		assert_eq!(intersect_ray_plane(&parallel, &plane), None);
+	}
+
+	#[test]
+	fn test_ray_aabb() {
+		let aabb = AABB3::from_center_size(vec3(0.0, 0.0, 10.0), vec3(2.0, 2.0, 2.0));
+
+		let hit = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 1.0));
+		assert_eq!(intersect_ray_aabb(&hit, &aabb), Some(9.0));
+
+		let miss = Ray::new(vec3(0.0, 5.0, 0.0), vec3(0.0, 0.0, 1.0));
+		assert_eq!(intersect_ray_aabb(&miss, &aabb), None);
+
+		let behind = Ray::new(vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, -1.0));
+		assert_eq!(intersect_ray_aabb(&behind, &aabb), None);
+
+		let inside = Ray::new(vec3(0.0, 0.0, 10.0), vec3(1.0, 0.0, 0.0));
+		assert_eq!(intersect_ray_aabb(&inside, &aabb), Some(0.0));
+	}
+
+	#[test]
+	fn test_ray_triangle() {
+		let a = vec3(-1.0, 0.0, -1.0);
+		let b = vec3(1.0, 0.0, -1.0);
+		let c = vec3(0.0, 0.0, 1.0);
+
+		let hit = Ray::new(vec3(0.0, 3.0, 0.0), vec3(0.0, -1.0, 0.0));
+		assert_eq!(intersect_ray_triangle(&hit, a, b, c), Some(3.0));
+
+		let back_side = Ray::new(vec3(0.0, -3.0, 0.0), vec3(0.0, 1.0, 0.0));
+		assert_eq!(intersect_ray_triangle(&back_side, a, b, c), Some(3.0));
+
+		let miss = Ray::new(vec3(2.0, 3.0, 0.0), vec3(0.0, -1.0, 0.0));
+		assert_eq!(intersect_ray_triangle(&miss, a, b, c), None);
+	}
+
+}
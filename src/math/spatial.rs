@@ -20,6 +20,48 @@ impl Spatial {
 		Matrix3::from(self.rotation)
 	}
 
+	/// Linear interpolation of position and normalized linear interpolation of rotation.
+	/// Cheap and good enough for the small steps between simulation ticks.
+	pub fn lerp(&self, other: &Spatial, t: Real) -> Spatial {
+		let target = shortest_arc_target(self.rotation, other.rotation);
+		let rotation = self.rotation * (1.0 - t) + target * t;
+
+		Spatial {
+			position: self.position.lerp(other.position, t),
+			rotation: rotation.normalize(),
+		}
+	}
+
+	/// Like `lerp`, but rotates with constant angular velocity
+	pub fn slerp(&self, other: &Spatial, t: Real) -> Spatial {
+		let target = shortest_arc_target(self.rotation, other.rotation);
+		let cos_angle = self.rotation.dot(target).min(1.0);
+
+		let rotation = if cos_angle > 0.9995 {
+			// nearly identical rotations, avoid dividing by sin of a tiny angle
+			(self.rotation * (1.0 - t) + target * t).normalize()
+		} else {
+			let angle = cos_angle.acos();
+			let sin_angle = angle.sin();
+			self.rotation * (((1.0 - t) * angle).sin() / sin_angle) + target * ((t * angle).sin() / sin_angle)
+		};
+
+		Spatial {
+			position: self.position.lerp(other.position, t),
+			rotation: rotation,
+		}
+	}
+
+}
+
+/// q and -q are the same rotation, pick the one closer to `from`
+/// so interpolation doesn't take the long way around
+fn shortest_arc_target(from: Quaternion, to: Quaternion) -> Quaternion {
+	if from.dot(to) < 0.0 {
+		-to
+	} else {
+		to
+	}
 }
 
 impl Default for Spatial {
@@ -33,3 +75,68 @@ impl Default for Spatial {
 
 }
 
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::math::*;
+
+	fn assert_near(a: Vector3, b: Vector3) {
+		assert!((a - b).magnitude() < 0.0001, "{:?} != {:?}", a, b);
+	}
+
+	fn spatial(position: Vector3, yaw: Deg<Real>) -> Spatial {
+		Spatial {
+			position: position,
+			rotation: Quaternion::from_angle_y(yaw),
+		}
+	}
+
+	#[test]
+	fn test_interpolation_endpoints() {
+		let a = spatial(vec3(0.0, 0.0, 0.0), Deg(0.0));
+		let b = spatial(vec3(10.0, 2.0, -4.0), Deg(90.0));
+
+		for &(t, expected) in [(0.0, &a), (1.0, &b)].iter() {
+			for interpolated in [a.lerp(&b, t), a.slerp(&b, t)].iter() {
+				assert_near(interpolated.position, expected.position);
+				assert!(interpolated.rotation.dot(expected.rotation).abs() > 0.9999);
+			}
+		}
+
+		let half = a.slerp(&b, 0.5);
+		assert_near(half.position, vec3(5.0, 1.0, -2.0));
+		assert_near(half.rotation.rotate_vector(vec3(0.0, 0.0, 1.0)), Quaternion::from_angle_y(Deg(45.0)).rotate_vector(vec3(0.0, 0.0, 1.0)));
+	}
+
+	#[test]
+	fn test_hemisphere_flip() {
+		let a = spatial(vec3(0.0, 0.0, 0.0), Deg(170.0));
+		let b = spatial(vec3(0.0, 0.0, 0.0), Deg(-170.0));
+
+		// same rotation as b, stored in the opposite hemisphere
+		let mut flipped = b;
+		flipped.rotation = -b.rotation;
+		assert!(a.rotation.dot(b.rotation) < 0.0);
+		assert!(a.rotation.dot(flipped.rotation) > 0.0);
+
+		// both must turn 10 degrees through 180, not 170 degrees through 0
+		let expected = Quaternion::from_angle_y(Deg(180.0)).rotate_vector(vec3(0.0, 0.0, 1.0));
+		for target in [b, flipped].iter() {
+			assert_near(a.slerp(target, 0.5).rotation.rotate_vector(vec3(0.0, 0.0, 1.0)), expected);
+			assert_near(a.lerp(target, 0.5).rotation.rotate_vector(vec3(0.0, 0.0, 1.0)), expected);
+		}
+	}
+
+	#[test]
+	fn test_slerp_constant_velocity() {
+		let a = spatial(vec3(0.0, 0.0, 0.0), Deg(0.0));
+		let b = spatial(vec3(0.0, 0.0, 0.0), Deg(120.0));
+
+		let quarter = a.slerp(&b, 0.25).rotation;
+		assert!((quarter.magnitude() - 1.0).abs() < 0.0001);
+		assert_near(quarter.rotate_vector(vec3(0.0, 0.0, 1.0)), Quaternion::from_angle_y(Deg(30.0)).rotate_vector(vec3(0.0, 0.0, 1.0)));
+	}
+
+}
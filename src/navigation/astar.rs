@@ -0,0 +1,274 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use ::math::*;
+use ::navigation::{NavGrid, Cell, CellRect, MovementClass};
+
+/// Finds a walkable path between two world positions on the XZ plane.
+///
+/// Returns waypoints after `start`, the last one is `goal` itself.
+/// None when the goal is outside of the grid, impassable or unreachable.
+pub fn find_path(grid: &NavGrid, class: MovementClass, start: Vector2, goal: Vector2) -> Option<Vec<Vector2>> {
+	let start_cell = match grid.cell_at(start) {
+		Some(cell) => cell,
+		None => return None,
+	};
+	let goal_cell = match grid.cell_at(goal) {
+		Some(cell) if grid.is_passable(cell, class) => cell,
+		_ => return None,
+	};
+
+	find_cell_path(grid, class, start_cell, goal_cell).map(|cells| smooth_path(grid, class, start, goal, &cells))
+}
+
+/// A* over grid cells with the octile distance heuristic.
+/// Path includes both start and goal cells. Start cell may be impassable,
+/// so units pushed onto a steep spot can still walk away.
+pub fn find_cell_path(grid: &NavGrid, class: MovementClass, start: Cell, goal: Cell) -> Option<Vec<Cell>> {
+	find_cell_path_in(grid, class, start, goal, grid.bounds())
+}
+
+/// Same as `find_cell_path`, but never leaves the area
+pub fn find_cell_path_in(grid: &NavGrid, class: MovementClass, start: Cell, goal: Cell, area: CellRect) -> Option<Vec<Cell>> {
+	if !area.contains(start) || !area.contains(goal) {
+		return None;
+	}
+
+	let cell_count = area.width() * area.height();
+
+	let mut costs = vec![::std::f32::INFINITY; cell_count];
+	let mut parents = vec![NO_PARENT; cell_count];
+	let mut closed = vec![false; cell_count];
+	let mut open = BinaryHeap::new();
+	let mut order = 0;
+
+	costs[area.index(start)] = 0.0;
+	open.push(OpenNode {
+		estimate: octile_distance(start, goal),
+		heuristic: octile_distance(start, goal),
+		order: order,
+		cell: start,
+	});
+
+	while let Some(node) = open.pop() {
+		let index = area.index(node.cell);
+		if closed[index] {
+			continue;
+		}
+		closed[index] = true;
+
+		if node.cell == goal {
+			return Some(build_cell_path(&area, &parents, goal));
+		}
+
+		for (neighbour, step_cost) in grid.neighbours(node.cell, class) {
+			if !area.contains(neighbour) {
+				continue;
+			}
+
+			let neighbour_index = area.index(neighbour);
+			if closed[neighbour_index] {
+				continue;
+			}
+
+			let cost = costs[index] + step_cost;
+			if cost < costs[neighbour_index] {
+				costs[neighbour_index] = cost;
+				parents[neighbour_index] = index;
+
+				let heuristic = octile_distance(neighbour, goal);
+				order += 1;
+				open.push(OpenNode {
+					estimate: cost + heuristic,
+					heuristic: heuristic,
+					order: order,
+					cell: neighbour,
+				});
+			}
+		}
+	}
+
+	None
+}
+
+/// Shortest distance between cells moving in 8 directions, in cells
+pub fn octile_distance(a: Cell, b: Cell) -> Real {
+	let dx = (a.0 as isize - b.0 as isize).abs() as Real;
+	let dy = (a.1 as isize - b.1 as isize).abs() as Real;
+	dx.max(dy) + (::std::f32::consts::SQRT_2 - 1.0) * dx.min(dy)
+}
+
+/// String pulling: skips cell centers that can be walked past in a straight line
+pub fn smooth_path(grid: &NavGrid, class: MovementClass, start: Vector2, goal: Vector2, cells: &[Cell]) -> Vec<Vector2> {
+	let mut points: Vec<Vector2> = cells.iter().skip(1).map(|&cell| grid.cell_center(cell)).collect();
+	match points.last_mut() {
+		Some(last) => *last = goal,
+		None => points.push(goal),
+	}
+
+	let mut waypoints = Vec::new();
+	let mut current = start;
+	let mut i = 0;
+
+	while i < points.len() {
+		let mut next = i;
+		while next + 1 < points.len() && grid.line_of_sight(current, points[next + 1], class) {
+			next += 1;
+		}
+
+		current = points[next];
+		waypoints.push(current);
+		i = next + 1;
+	}
+
+	waypoints
+}
+
+const NO_PARENT: usize = ::std::usize::MAX;
+
+fn build_cell_path(area: &CellRect, parents: &[usize], goal: Cell) -> Vec<Cell> {
+	let mut path = vec![goal];
+	let mut index = parents[area.index(goal)];
+
+	while index != NO_PARENT {
+		path.push(area.cell(index));
+		index = parents[index];
+	}
+
+	path.reverse();
+	path
+}
+
+struct OpenNode {
+	estimate: Real,
+	heuristic: Real,
+	order: u32,
+	cell: Cell,
+}
+
+impl PartialEq for OpenNode {
+	fn eq(&self, other: &OpenNode) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+	fn partial_cmp(&self, other: &OpenNode) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenNode {
+	/// Reversed for the max-heap: lowest estimate first, ties go to the node
+	/// closer to the goal, then to the one pushed first
+	fn cmp(&self, other: &OpenNode) -> Ordering {
+		other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+			.then_with(|| other.heuristic.partial_cmp(&self.heuristic).unwrap_or(Ordering::Equal))
+			.then_with(|| other.order.cmp(&self.order))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::terrain::Heightfield;
+
+	fn flat_grid(size: usize) -> NavGrid {
+		let heightfield = Heightfield::from_fn(size, size, vec3(size as Real, 1.0, size as Real), |_, _| 0.0);
+		NavGrid::from_heightfield(&heightfield, 1.0)
+	}
+
+	#[test]
+	fn test_straight_path() {
+		let grid = flat_grid(16);
+		let path = find_path(&grid, MovementClass::Infantry, vec2(1.5, 1.5), vec2(12.2, 9.7)).unwrap();
+
+		assert_eq!(path, vec![vec2(12.2, 9.7)]);
+		assert_eq!(octile_distance((0, 0), (3, 1)), 2.0 + ::std::f32::consts::SQRT_2);
+	}
+
+	#[test]
+	fn test_path_around_wall() {
+		let mut grid = flat_grid(16);
+		// wall across the map with a gap at the top
+		for y in 0..13 {
+			grid.set_blocked((8, y), true);
+		}
+
+		let start = vec2(2.5, 2.5);
+		let goal = vec2(13.5, 2.5);
+		let path = find_path(&grid, MovementClass::Infantry, start, goal).unwrap();
+
+		assert_eq!(*path.last().unwrap(), goal);
+		assert!(path.iter().any(|waypoint| waypoint.y > 13.0));
+
+		let mut previous = start;
+		for &waypoint in path.iter() {
+			assert!(grid.line_of_sight(previous, waypoint, MovementClass::Infantry));
+			previous = waypoint;
+		}
+
+		// only the cells around the wall end are left after smoothing
+		assert_eq!(path, vec![vec2(7.5, 13.5), vec2(9.5, 13.5), goal]);
+
+		// no way around when the search can't leave the bottom of the map
+		let area = CellRect { min: (0, 0), max: (16, 13) };
+		assert!(find_cell_path_in(&grid, MovementClass::Infantry, (2, 2), (13, 2), area).is_none());
+		assert!(find_cell_path_in(&grid, MovementClass::Infantry, (2, 2), (2, 14), area).is_none());
+	}
+
+	#[test]
+	fn test_unreachable() {
+		let mut grid = flat_grid(8);
+		for y in 0..8 {
+			grid.set_blocked((4, y), true);
+		}
+
+		assert!(find_path(&grid, MovementClass::Infantry, vec2(1.5, 1.5), vec2(6.5, 1.5)).is_none());
+		assert!(find_path(&grid, MovementClass::Infantry, vec2(1.5, 1.5), vec2(4.5, 1.5)).is_none());
+		assert!(find_path(&grid, MovementClass::Infantry, vec2(1.5, 1.5), vec2(-1.0, 1.5)).is_none());
+	}
+
+	#[test]
+	fn test_slope_limit_per_class() {
+		// 30 degree ridge in the middle of the map, with a flat pass at the bottom
+		let rise = (30.0 as Real).to_radians().tan();
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 64.0 * rise, 64.0), |x, y| {
+			if y >= 56 || x < 24 || x >= 40 {
+				0.0
+			} else if x < 32 {
+				(x - 24) as Real / 64.0
+			} else {
+				(40 - x) as Real / 64.0
+			}
+		});
+		let grid = NavGrid::from_heightfield(&heightfield, 2.0);
+
+		let start = vec2(8.0, 8.0);
+		let goal = vec2(56.0, 8.0);
+
+		let infantry = find_path(&grid, MovementClass::Infantry, start, goal).unwrap();
+		let vehicle = find_path(&grid, MovementClass::Vehicle, start, goal).unwrap();
+
+		assert!(infantry.iter().all(|waypoint| waypoint.y < 40.0));
+		assert!(vehicle.iter().any(|waypoint| waypoint.y > 50.0));
+	}
+
+	#[test]
+	fn test_deterministic() {
+		let mut grid = flat_grid(32);
+		for i in 4..28 {
+			grid.set_blocked((i, 16), true);
+			grid.set_blocked((16, i), true);
+		}
+
+		let first = find_cell_path(&grid, MovementClass::Infantry, (2, 2), (29, 29)).unwrap();
+		for _ in 0..10 {
+			assert_eq!(find_cell_path(&grid, MovementClass::Infantry, (2, 2), (29, 29)).unwrap(), first);
+		}
+	}
+
+}
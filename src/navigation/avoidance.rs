@@ -0,0 +1,448 @@
+use ::math::*;
+use ::navigation::{NavGrid, MovementClass};
+
+const EPSILON: Real = 0.00001;
+
+/// Moving body taking part in local avoidance
+#[derive(Copy, Clone, Debug)]
+pub struct AvoidanceAgent {
+	pub position: Vector2,
+	pub velocity: Vector2,
+	/// Velocity the agent would move with if nothing was in the way
+	pub preferred_velocity: Vector2,
+	pub radius: Real,
+	pub max_speed: Real,
+}
+
+/// Axis aligned box agents must not walk into
+#[derive(Copy, Clone, Debug)]
+pub struct Obstacle {
+	pub min: Vector2,
+	pub max: Vector2,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct AvoidanceParams {
+	/// Seconds ahead collisions with other agents are avoided
+	pub time_horizon: Real,
+	/// Seconds ahead collisions with obstacles are avoided
+	pub obstacle_time_horizon: Real,
+	pub neighbour_distance: Real,
+	pub max_neighbours: usize,
+}
+
+impl Default for AvoidanceParams {
+	fn default() -> Self {
+		AvoidanceParams {
+			time_horizon: 2.0,
+			obstacle_time_horizon: 1.0,
+			neighbour_distance: 5.0,
+			max_neighbours: 10,
+		}
+	}
+}
+
+/// Half-plane of allowed velocities, left of `direction` going through `point`
+#[derive(Copy, Clone, Debug)]
+struct Line {
+	point: Vector2,
+	direction: Vector2,
+}
+
+/// ORCA: velocity closest to the preferred one that doesn't collide with
+/// neighbours within the time horizon, assuming they do the same,
+/// and never walks into obstacles
+pub fn avoidance_velocity(agent: &AvoidanceAgent, neighbours: &[AvoidanceAgent], obstacles: &[Obstacle], params: &AvoidanceParams, delta_time: Real) -> Vector2 {
+	let mut lines = Vec::with_capacity(obstacles.len() + neighbours.len());
+
+	for obstacle in obstacles {
+		lines.push(obstacle_line(agent, obstacle, params.obstacle_time_horizon, delta_time));
+	}
+	let obstacle_line_count = lines.len();
+
+	let inverse_time_horizon = 1.0 / params.time_horizon;
+
+	for other in neighbours {
+		let relative_position = other.position - agent.position;
+		let relative_velocity = agent.velocity - other.velocity;
+		let distance_squared = relative_position.magnitude2();
+		let combined_radius = agent.radius + other.radius;
+		let combined_radius_squared = combined_radius * combined_radius;
+
+		let direction;
+		let u;
+
+		if distance_squared > combined_radius_squared {
+			// vector from the cutoff circle center to the relative velocity
+			let w = relative_velocity - relative_position * inverse_time_horizon;
+			let w_length_squared = w.magnitude2();
+			let dot_product = w.dot(relative_position);
+
+			if dot_product < 0.0 && dot_product * dot_product > combined_radius_squared * w_length_squared {
+				// project on the cutoff circle
+				let w_length = w_length_squared.sqrt();
+				let unit_w = w / w_length;
+				direction = vec2(unit_w.y, -unit_w.x);
+				u = unit_w * (combined_radius * inverse_time_horizon - w_length);
+			} else {
+				// project on the legs of the velocity obstacle
+				let leg = (distance_squared - combined_radius_squared).sqrt();
+				direction = if determinant(relative_position, w) > 0.0 {
+					vec2(
+						relative_position.x * leg - relative_position.y * combined_radius,
+						relative_position.x * combined_radius + relative_position.y * leg,
+					) / distance_squared
+				} else {
+					-vec2(
+						relative_position.x * leg + relative_position.y * combined_radius,
+						-relative_position.x * combined_radius + relative_position.y * leg,
+					) / distance_squared
+				};
+				u = direction * relative_velocity.dot(direction) - relative_velocity;
+			}
+		} else {
+			// already colliding, get apart within this step
+			let inverse_delta_time = 1.0 / delta_time;
+			let w = relative_velocity - relative_position * inverse_delta_time;
+			let w_length = w.magnitude();
+			let unit_w = if w_length > EPSILON { w / w_length } else { vec2(1.0, 0.0) };
+			direction = vec2(unit_w.y, -unit_w.x);
+			u = unit_w * (combined_radius * inverse_delta_time - w_length);
+		}
+
+		// each agent takes half of the responsibility
+		lines.push(Line {
+			point: agent.velocity + u * 0.5,
+			direction: direction,
+		});
+	}
+
+	let mut result = vec2(0.0, 0.0);
+	let failed_line = linear_program_2(&lines, agent.max_speed, agent.preferred_velocity, false, &mut result);
+	if failed_line < lines.len() {
+		linear_program_3(&lines, obstacle_line_count, failed_line, agent.max_speed, &mut result);
+	}
+
+	result
+}
+
+/// New velocities for all agents. `candidates` gives indices of agents that
+/// may be near the agent, usually from a spatial query, the nearest ones
+/// within `neighbour_distance` are avoided.
+pub fn solve_avoidance<N, F>(agents: &[AvoidanceAgent], candidates: N, obstacles: F, params: &AvoidanceParams, delta_time: Real) -> Vec<Vector2>
+	where N: Fn(usize) -> Vec<usize>, F: Fn(usize) -> Vec<Obstacle>
+{
+	(0..agents.len()).map(|i| {
+		let neighbours = nearest_neighbours(agents, i, candidates(i), params);
+		avoidance_velocity(&agents[i], &neighbours, &obstacles(i), params, delta_time)
+	}).collect()
+}
+
+/// Up to `max_neighbours` candidates within `neighbour_distance`, nearest first
+fn nearest_neighbours(agents: &[AvoidanceAgent], index: usize, candidates: Vec<usize>, params: &AvoidanceParams) -> Vec<AvoidanceAgent> {
+	let position = agents[index].position;
+	let range_squared = params.neighbour_distance * params.neighbour_distance;
+
+	let mut nearest: Vec<(Real, usize)> = candidates.into_iter()
+		.filter(|&other| other != index)
+		.map(|other| ((agents[other].position - position).magnitude2(), other))
+		.filter(|&(distance_squared, _)| distance_squared < range_squared)
+		.collect();
+
+	nearest.sort_by(|a, b| a.partial_cmp(b).unwrap());
+	nearest.truncate(params.max_neighbours);
+
+	nearest.into_iter().map(|(_, other)| agents[other]).collect()
+}
+
+/// Impassable cells around the position as obstacles
+pub fn grid_obstacles(grid: &NavGrid, class: MovementClass, position: Vector2, range: Real) -> Vec<Obstacle> {
+	let cell_size = grid.cell_size();
+	let x0 = ((position.x - range) / cell_size).floor() as isize;
+	let y0 = ((position.y - range) / cell_size).floor() as isize;
+	let x1 = ((position.x + range) / cell_size).floor() as isize;
+	let y1 = ((position.y + range) / cell_size).floor() as isize;
+
+	let mut obstacles = Vec::new();
+	for y in y0..(y1 + 1) {
+		for x in x0..(x1 + 1) {
+			if !grid.is_passable_at(x, y, class) {
+				obstacles.push(Obstacle {
+					min: vec2(x as Real, y as Real) * cell_size,
+					max: vec2((x + 1) as Real, (y + 1) as Real) * cell_size,
+				});
+			}
+		}
+	}
+
+	obstacles
+}
+
+fn determinant(a: Vector2, b: Vector2) -> Real {
+	a.x * b.y - a.y * b.x
+}
+
+/// Limits the speed towards the obstacle so the agent can stop
+/// before touching it within the time horizon
+fn obstacle_line(agent: &AvoidanceAgent, obstacle: &Obstacle, time_horizon: Real, delta_time: Real) -> Line {
+	let closest = vec2(
+		agent.position.x.max(obstacle.min.x).min(obstacle.max.x),
+		agent.position.y.max(obstacle.min.y).min(obstacle.max.y),
+	);
+	let offset = closest - agent.position;
+	let distance = offset.magnitude();
+
+	let (normal, max_approach_speed) = if distance > agent.radius {
+		(offset / distance, (distance - agent.radius) / time_horizon)
+	} else {
+		// touching or inside, move out within this step
+		let normal = if distance > EPSILON {
+			offset / distance
+		} else {
+			let center = (obstacle.min + obstacle.max) * 0.5;
+			let to_center = center - agent.position;
+			if to_center.magnitude2() > EPSILON { to_center.normalize() } else { vec2(1.0, 0.0) }
+		};
+		(normal, (distance - agent.radius) / delta_time)
+	};
+
+	Line {
+		point: normal * max_approach_speed,
+		direction: vec2(-normal.y, normal.x),
+	}
+}
+
+/// Optimizes along a single line, constrained by the lines before it
+fn linear_program_1(lines: &[Line], line_index: usize, radius: Real, optimal_velocity: Vector2, optimize_direction: bool, result: &mut Vector2) -> bool {
+	let line = lines[line_index];
+	let dot_product = line.point.dot(line.direction);
+	let discriminant = dot_product * dot_product + radius * radius - line.point.magnitude2();
+
+	if discriminant < 0.0 {
+		// max speed circle doesn't reach the line
+		return false;
+	}
+
+	let discriminant_root = discriminant.sqrt();
+	let mut t_left = -dot_product - discriminant_root;
+	let mut t_right = -dot_product + discriminant_root;
+
+	for other in lines.iter().take(line_index) {
+		let denominator = determinant(line.direction, other.direction);
+		let numerator = determinant(other.direction, line.point - other.point);
+
+		if denominator.abs() <= EPSILON {
+			// parallel lines
+			if numerator < 0.0 {
+				return false;
+			}
+			continue;
+		}
+
+		let t = numerator / denominator;
+		if denominator >= 0.0 {
+			t_right = t_right.min(t);
+		} else {
+			t_left = t_left.max(t);
+		}
+
+		if t_left > t_right {
+			return false;
+		}
+	}
+
+	*result = if optimize_direction {
+		if optimal_velocity.dot(line.direction) > 0.0 {
+			line.point + line.direction * t_right
+		} else {
+			line.point + line.direction * t_left
+		}
+	} else {
+		let t = line.direction.dot(optimal_velocity - line.point);
+		line.point + line.direction * t.max(t_left).min(t_right)
+	};
+
+	true
+}
+
+/// Returns the index of the first line that can't be satisfied, or the line count
+fn linear_program_2(lines: &[Line], radius: Real, optimal_velocity: Vector2, optimize_direction: bool, result: &mut Vector2) -> usize {
+	*result = if optimize_direction {
+		optimal_velocity * radius
+	} else if optimal_velocity.magnitude2() > radius * radius {
+		optimal_velocity.normalize() * radius
+	} else {
+		optimal_velocity
+	};
+
+	for i in 0..lines.len() {
+		if determinant(lines[i].direction, lines[i].point - *result) > 0.0 {
+			let previous = *result;
+			if !linear_program_1(lines, i, radius, optimal_velocity, optimize_direction, result) {
+				*result = previous;
+				return i;
+			}
+		}
+	}
+
+	lines.len()
+}
+
+/// Infeasible case: minimizes the largest violation of agent lines,
+/// obstacle lines stay hard constraints
+fn linear_program_3(lines: &[Line], obstacle_line_count: usize, begin_line: usize, radius: Real, result: &mut Vector2) {
+	let mut distance = 0.0;
+
+	for i in begin_line..lines.len() {
+		if determinant(lines[i].direction, lines[i].point - *result) <= distance {
+			continue;
+		}
+
+		let mut projected_lines: Vec<Line> = lines[..obstacle_line_count].to_vec();
+
+		for j in obstacle_line_count..i {
+			let denominator = determinant(lines[i].direction, lines[j].direction);
+
+			let point = if denominator.abs() <= EPSILON {
+				if lines[i].direction.dot(lines[j].direction) > 0.0 {
+					// same direction
+					continue;
+				}
+				(lines[i].point + lines[j].point) * 0.5
+			} else {
+				lines[i].point + lines[i].direction * (determinant(lines[j].direction, lines[i].point - lines[j].point) / denominator)
+			};
+
+			projected_lines.push(Line {
+				point: point,
+				direction: (lines[j].direction - lines[i].direction).normalize(),
+			});
+		}
+
+		let previous = *result;
+		let direction = vec2(-lines[i].direction.y, lines[i].direction.x);
+		if linear_program_2(&projected_lines, radius, direction, true, result) < projected_lines.len() {
+			// can only fail because of rounding errors, keep the previous result
+			*result = previous;
+		}
+
+		distance = determinant(lines[i].direction, lines[i].point - *result);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn agent(position: Vector2, preferred_velocity: Vector2) -> AvoidanceAgent {
+		AvoidanceAgent {
+			position: position,
+			velocity: preferred_velocity,
+			preferred_velocity: preferred_velocity,
+			radius: 0.5,
+			max_speed: 2.0,
+		}
+	}
+
+	// moves agents towards their goals for a number of steps, checking they never overlap
+	fn simulate(agents: &mut Vec<AvoidanceAgent>, goals: &[Vector2], obstacles: &[Obstacle], steps: usize) {
+		let params = AvoidanceParams::default();
+		let delta_time = 0.05;
+
+		for _ in 0..steps {
+			for (agent, &goal) in agents.iter_mut().zip(goals.iter()) {
+				let offset = goal - agent.position;
+				let distance = offset.magnitude();
+				agent.preferred_velocity = if distance > agent.max_speed * delta_time {
+					offset / distance * agent.max_speed
+				} else {
+					offset / delta_time
+				};
+			}
+
+			let count = agents.len();
+			let velocities = solve_avoidance(agents, |_| (0..count).collect(), |_| obstacles.to_vec(), &params, delta_time);
+			for (agent, velocity) in agents.iter_mut().zip(velocities) {
+				assert!(velocity.magnitude() <= agent.max_speed + 0.001);
+				agent.velocity = velocity;
+				agent.position += velocity * delta_time;
+			}
+
+			for i in 0..agents.len() {
+				for j in (i + 1)..agents.len() {
+					let distance = (agents[i].position - agents[j].position).magnitude();
+					assert!(distance > agents[i].radius + agents[j].radius - 0.05, "agents {} and {} overlap: {}", i, j, distance);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn test_free_agent_keeps_preferred_velocity() {
+		let lonely = agent(vec2(0.0, 0.0), vec2(1.0, 0.5));
+		let far = agent(vec2(50.0, 0.0), vec2(-1.0, 0.0));
+		let velocities = solve_avoidance(&[lonely, far], |_| vec![0, 1], |_| Vec::new(), &AvoidanceParams::default(), 0.05);
+		assert_eq!(velocities[0], vec2(1.0, 0.5));
+
+		// too fast preferred velocity is clamped
+		let hasty = agent(vec2(0.0, 0.0), vec2(10.0, 0.0));
+		assert!((avoidance_velocity(&hasty, &[], &[], &AvoidanceParams::default(), 0.05) - vec2(2.0, 0.0)).magnitude() < 0.0001);
+	}
+
+	#[test]
+	fn test_head_on() {
+		let mut agents = vec![agent(vec2(0.0, 0.0), vec2(2.0, 0.0)), agent(vec2(10.0, 0.0), vec2(-2.0, 0.0))];
+		let goals = [vec2(10.0, 0.0), vec2(0.0, 0.0)];
+
+		simulate(&mut agents, &goals, &[], 200);
+
+		assert!((agents[0].position - goals[0]).magnitude() < 0.1);
+		assert!((agents[1].position - goals[1]).magnitude() < 0.1);
+	}
+
+	#[test]
+	fn test_crossing_groups() {
+		// two lines of agents walk through each other at a right angle
+		let mut agents = Vec::new();
+		let mut goals = Vec::new();
+		for i in 0..4 {
+			let offset = (i as Real) * 1.5 - 2.25;
+			agents.push(agent(vec2(-8.0, offset), vec2(0.0, 0.0)));
+			goals.push(vec2(8.0, offset));
+			agents.push(agent(vec2(offset, -8.0), vec2(0.0, 0.0)));
+			goals.push(vec2(offset, 8.0));
+		}
+
+		simulate(&mut agents, &goals, &[], 400);
+
+		for (agent, goal) in agents.iter().zip(goals.iter()) {
+			assert!((agent.position - goal).magnitude() < 0.5);
+		}
+	}
+
+	#[test]
+	fn test_obstacle() {
+		let wall = Obstacle { min: vec2(4.0, -10.0), max: vec2(5.0, 10.0) };
+		let mut agents = vec![agent(vec2(0.0, 0.0), vec2(2.0, 0.0))];
+
+		simulate(&mut agents, &[vec2(10.0, 0.0)], &[wall], 200);
+
+		// stops in front of the wall
+		assert!(agents[0].position.x <= 4.0 - 0.5 + 0.01);
+		assert!(agents[0].position.x > 3.0);
+	}
+
+	#[test]
+	fn test_deterministic() {
+		let run = || {
+			let mut agents: Vec<AvoidanceAgent> = (0..6).map(|i| agent(vec2(i as Real * 0.9, (i % 2) as Real), vec2(0.0, 0.0))).collect();
+			let goals: Vec<Vector2> = (0..6).map(|i| vec2(5.0 - i as Real, 3.0)).collect();
+			simulate(&mut agents, &goals, &[], 50);
+			agents.iter().map(|agent| (agent.position.x.to_bits(), agent.position.y.to_bits())).collect::<Vec<_>>()
+		};
+
+		assert_eq!(run(), run());
+	}
+
+}
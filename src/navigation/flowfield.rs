@@ -0,0 +1,404 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::rc::Rc;
+
+use ::math::*;
+use ::navigation::{NavGrid, Cell, MovementClass};
+
+/// Directions to a single goal from every cell of the grid.
+///
+/// Built once and shared by all units of a class heading to the same
+/// goal cell, so moving a big group costs one search instead of one per unit.
+pub struct FlowField {
+	goal: Cell,
+	class: MovementClass,
+	grid_version: u64,
+	width: usize,
+	height: usize,
+	cell_size: Real,
+	/// Cost of walking from the cell to the goal, infinite when unreachable
+	integration: Vec<Real>,
+	/// Neighbour cell to step to, None at the goal and in unreachable cells
+	next_cells: Vec<Option<Cell>>,
+	/// Goal can be walked to in a straight line from the cell center
+	line_of_sight: Vec<bool>,
+}
+
+impl FlowField {
+
+	pub fn build(grid: &NavGrid, class: MovementClass, goal: Cell) -> Self {
+		let cell_count = grid.width() * grid.height();
+		let mut integration = vec![::std::f32::INFINITY; cell_count];
+
+		// integration field, Dijkstra from the goal
+		if grid.is_passable(goal, class) {
+			let mut open = BinaryHeap::new();
+			let mut order = 0;
+			integration[grid.index(goal)] = 0.0;
+			open.push(OpenCell { cost: 0.0, order: order, cell: goal });
+
+			while let Some(OpenCell { cost, cell, .. }) = open.pop() {
+				if cost > integration[grid.index(cell)] {
+					continue;
+				}
+
+				for (neighbour, step_cost) in grid.neighbours(cell, class) {
+					let neighbour_cost = cost + step_cost;
+					let neighbour_index = grid.index(neighbour);
+					if neighbour_cost < integration[neighbour_index] {
+						integration[neighbour_index] = neighbour_cost;
+						order += 1;
+						open.push(OpenCell { cost: neighbour_cost, order: order, cell: neighbour });
+					}
+				}
+			}
+		}
+
+		// direction field, every cell points to its cheapest neighbour
+		let mut next_cells = vec![None; cell_count];
+
+		for y in 0..grid.height() {
+			for x in 0..grid.width() {
+				let cell = (x, y);
+				let index = grid.index(cell);
+
+				if integration[index] == 0.0 {
+					continue;
+				}
+
+				let mut best = ::std::f32::INFINITY;
+				for (neighbour, step_cost) in grid.neighbours(cell, class) {
+					let cost = integration[grid.index(neighbour)] + step_cost;
+					if cost < best {
+						best = cost;
+						next_cells[index] = Some(neighbour);
+					}
+				}
+			}
+		}
+
+		// line of sight field, swept outwards from the goal ring by ring. The line from a cell
+		// to the goal goes on through the neighbours closer to the goal, so the cell sees the goal
+		// when those neighbours see it. That errs on the side of not seeing it near obstacles.
+		let mut line_of_sight = vec![false; cell_count];
+		if grid.is_passable(goal, class) {
+			line_of_sight[grid.index(goal)] = true;
+
+			let rings = goal.0.max(goal.1).max(grid.width() - 1 - goal.0).max(grid.height() - 1 - goal.1);
+			for ring in 1..(rings + 1) {
+				for cell in ring_cells(grid, goal, ring) {
+					let index = grid.index(cell);
+					line_of_sight[index] = integration[index].is_finite() && sees_goal(grid, class, &line_of_sight, cell, goal);
+				}
+			}
+		}
+
+		FlowField {
+			goal: goal,
+			class: class,
+			grid_version: grid.version(),
+			width: grid.width(),
+			height: grid.height(),
+			cell_size: grid.cell_size(),
+			integration: integration,
+			next_cells: next_cells,
+			line_of_sight: line_of_sight,
+		}
+	}
+
+	pub fn goal(&self) -> Cell {
+		self.goal
+	}
+
+	pub fn class(&self) -> MovementClass {
+		self.class
+	}
+
+	/// Field was built for an older state of the grid
+	pub fn is_outdated(&self, grid: &NavGrid) -> bool {
+		self.grid_version != grid.version()
+	}
+
+	pub fn cost(&self, cell: Cell) -> Real {
+		self.integration[cell.1 * self.width + cell.0]
+	}
+
+	pub fn is_reachable(&self, cell: Cell) -> bool {
+		self.cost(cell).is_finite() || self.next_cells[cell.1 * self.width + cell.0].is_some()
+	}
+
+	/// Unit direction to walk in from the cell
+	pub fn direction(&self, cell: Cell) -> Option<Vector2> {
+		self.next_cells[cell.1 * self.width + cell.0].map(|next| {
+			vec2(next.0 as Real - cell.0 as Real, next.1 as Real - cell.1 as Real).normalize()
+		})
+	}
+
+	/// Point a unit at `position` should walk to next on the way to `destination`,
+	/// which has to be in or close to the goal cell
+	pub fn steering_target(&self, position: Vector2, destination: Vector2) -> Vector2 {
+		let x = (position.x / self.cell_size).floor();
+		let y = (position.y / self.cell_size).floor();
+		if x < 0.0 || y < 0.0 || x as usize >= self.width || y as usize >= self.height {
+			return destination;
+		}
+
+		let index = (y as usize) * self.width + (x as usize);
+		if self.line_of_sight[index] {
+			return destination;
+		}
+
+		match self.next_cells[index] {
+			Some(next) => vec2(((next.0 as Real) + 0.5) * self.cell_size, ((next.1 as Real) + 0.5) * self.cell_size),
+			None => destination,
+		}
+	}
+
+}
+
+/// Cells of the grid at the Chebyshev distance from the goal
+fn ring_cells(grid: &NavGrid, goal: Cell, ring: usize) -> Vec<Cell> {
+	let (goal_x, goal_y, ring) = (goal.0 as isize, goal.1 as isize, ring as isize);
+	let mut cells = Vec::new();
+	let mut push = |x: isize, y: isize| if x >= 0 && y >= 0 && (x as usize) < grid.width() && (y as usize) < grid.height() {
+		cells.push((x as usize, y as usize));
+	};
+
+	for x in (goal_x - ring)..(goal_x + ring + 1) {
+		push(x, goal_y - ring);
+		push(x, goal_y + ring);
+	}
+	for y in (goal_y - ring + 1)..(goal_y + ring) {
+		push(goal_x - ring, y);
+		push(goal_x + ring, y);
+	}
+
+	cells
+}
+
+/// Whether the cell sees the goal, given what the cells one ring closer see
+fn sees_goal(grid: &NavGrid, class: MovementClass, line_of_sight: &[bool], cell: Cell, goal: Cell) -> bool {
+	let dx = goal.0 as isize - cell.0 as isize;
+	let dy = goal.1 as isize - cell.1 as isize;
+	let (step_x, step_y) = (dx.signum(), dy.signum());
+
+	let neighbour = |x: isize, y: isize| ((cell.0 as isize + x) as usize, (cell.1 as isize + y) as usize);
+	let sees = |x: isize, y: isize| line_of_sight[grid.index(neighbour(x, y))];
+
+	if dx.abs() == dy.abs() {
+		// diagonal lines don't squeeze between two blocked corners
+		sees(step_x, step_y) && grid.is_passable(neighbour(step_x, 0), class) && grid.is_passable(neighbour(0, step_y), class)
+	} else if dx.abs() > dy.abs() {
+		sees(step_x, 0) && (dy == 0 || sees(step_x, step_y))
+	} else {
+		sees(0, step_y) && (dx == 0 || sees(step_x, step_y))
+	}
+}
+
+/// Flow fields by goal cell and movement class.
+/// Fields are dropped when the grid changes and the oldest ones
+/// are dropped when there are more than `capacity`.
+pub struct FlowFieldCache {
+	fields: HashMap<(Cell, MovementClass), Rc<FlowField>>,
+	insertion_order: VecDeque<(Cell, MovementClass)>,
+	grid_version: u64,
+	pub capacity: usize,
+}
+
+impl FlowFieldCache {
+
+	pub fn new() -> Self {
+		FlowFieldCache {
+			fields: HashMap::new(),
+			insertion_order: VecDeque::new(),
+			grid_version: 0,
+			capacity: 32,
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.fields.len()
+	}
+
+	pub fn get(&mut self, grid: &NavGrid, class: MovementClass, goal: Cell) -> Rc<FlowField> {
+		if self.grid_version != grid.version() {
+			self.fields.clear();
+			self.insertion_order.clear();
+			self.grid_version = grid.version();
+		}
+
+		let key = (goal, class);
+		if let Some(field) = self.fields.get(&key) {
+			return field.clone();
+		}
+
+		while self.fields.len() >= self.capacity.max(1) {
+			match self.insertion_order.pop_front() {
+				Some(oldest) => { self.fields.remove(&oldest); },
+				None => break,
+			}
+		}
+
+		let field = Rc::new(FlowField::build(grid, class, goal));
+		self.fields.insert(key, field.clone());
+		self.insertion_order.push_back(key);
+
+		field
+	}
+
+}
+
+struct OpenCell {
+	cost: Real,
+	order: u32,
+	cell: Cell,
+}
+
+impl PartialEq for OpenCell {
+	fn eq(&self, other: &OpenCell) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+	fn partial_cmp(&self, other: &OpenCell) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenCell {
+	/// Reversed for the max-heap, cheapest first
+	fn cmp(&self, other: &OpenCell) -> Ordering {
+		other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+			.then_with(|| other.order.cmp(&self.order))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::navigation::find_cell_path;
+	use ::terrain::Heightfield;
+
+	fn flat_grid(size: usize) -> NavGrid {
+		let heightfield = Heightfield::from_fn(size, size, vec3(size as Real, 1.0, size as Real), |_, _| 0.0);
+		NavGrid::from_heightfield(&heightfield, 1.0)
+	}
+
+	// walks the direction field from the cell, returns the number of steps to the goal
+	fn follow(field: &FlowField, grid: &NavGrid, mut cell: Cell) -> Option<usize> {
+		for steps in 0..(grid.width() * grid.height()) {
+			if cell == field.goal() {
+				return Some(steps);
+			}
+			let direction = match field.direction(cell) {
+				Some(direction) => direction,
+				None => return None,
+			};
+			let next = ((cell.0 as Real + direction.x.round()) as usize, (cell.1 as Real + direction.y.round()) as usize);
+			assert!(grid.is_passable(next, field.class()));
+			cell = next;
+		}
+		None
+	}
+
+	#[test]
+	fn test_flow_around_wall() {
+		let mut grid = flat_grid(16);
+		for y in 0..13 {
+			grid.set_blocked((8, y), true);
+		}
+
+		let field = FlowField::build(&grid, MovementClass::Infantry, (13, 2));
+
+		assert_eq!(field.cost((13, 2)), 0.0);
+		assert!(field.cost((8, 5)).is_infinite());
+
+		// integration matches the A* path cost
+		let path = find_cell_path(&grid, MovementClass::Infantry, (2, 2), (13, 2)).unwrap();
+		let mut path_cost = 0.0;
+		for step in path.windows(2) {
+			path_cost += if step[0].0 != step[1].0 && step[0].1 != step[1].1 { ::std::f32::consts::SQRT_2 } else { 1.0 };
+		}
+		assert!((field.cost((2, 2)) - path_cost).abs() < 0.001);
+
+		assert!(follow(&field, &grid, (2, 2)).is_some());
+		assert!(follow(&field, &grid, (0, 15)).is_some());
+
+		// behind the wall the goal is not visible, so units walk to the next cell
+		let target = field.steering_target(vec2(2.5, 2.5), vec2(13.2, 2.7));
+		assert!(target != vec2(13.2, 2.7));
+		assert!((target - vec2(2.5, 2.5)).magnitude() < 1.5);
+		// right in front of the goal they walk straight to it
+		assert_eq!(field.steering_target(vec2(11.5, 4.5), vec2(13.2, 2.7)), vec2(13.2, 2.7));
+	}
+
+	#[test]
+	fn test_unreachable_cells() {
+		let mut grid = flat_grid(8);
+		for y in 0..8 {
+			grid.set_blocked((4, y), true);
+		}
+
+		let field = FlowField::build(&grid, MovementClass::Infantry, (6, 1));
+		assert!(!field.is_reachable((1, 1)));
+		assert!(field.direction((1, 1)).is_none());
+		assert!(field.is_reachable((7, 7)));
+	}
+
+	#[test]
+	fn test_line_of_sight_sweep() {
+		let mut grid = flat_grid(24);
+		for &(x, y) in [(10, 10), (11, 10), (15, 4), (4, 17), (18, 18), (19, 17)].iter() {
+			grid.set_blocked((x, y), true);
+		}
+		let goal = (12, 12);
+		let field = FlowField::build(&grid, MovementClass::Infantry, goal);
+
+		// the sweep never sees the goal where a straight walk to it would hit something
+		for y in 0..24 {
+			for x in 0..24 {
+				if field.line_of_sight[grid.index((x, y))] {
+					assert!(grid.line_of_sight(grid.cell_center((x, y)), grid.cell_center(goal), MovementClass::Infantry), "{:?}", (x, y));
+				}
+			}
+		}
+
+		// open ground all around sees it, the cells behind the wall don't
+		assert!(field.line_of_sight[grid.index((23, 0))]);
+		assert!(field.line_of_sight[grid.index((0, 23))]);
+		assert!(!field.line_of_sight[grid.index((9, 8))]);
+	}
+
+	#[test]
+	fn test_cache() {
+		let mut grid = flat_grid(16);
+		let mut cache = FlowFieldCache::new();
+		cache.capacity = 2;
+
+		let a = cache.get(&grid, MovementClass::Infantry, (3, 3));
+		assert!(Rc::ptr_eq(&a, &cache.get(&grid, MovementClass::Infantry, (3, 3))));
+
+		let vehicle = cache.get(&grid, MovementClass::Vehicle, (3, 3));
+		assert!(!Rc::ptr_eq(&a, &vehicle));
+		assert_eq!(cache.len(), 2);
+
+		// oldest field is evicted
+		cache.get(&grid, MovementClass::Infantry, (9, 9));
+		assert_eq!(cache.len(), 2);
+		assert!(!Rc::ptr_eq(&a, &cache.get(&grid, MovementClass::Infantry, (3, 3))));
+
+		// obstacle change invalidates everything
+		let before = cache.get(&grid, MovementClass::Infantry, (3, 3));
+		grid.set_blocked((5, 5), true);
+		assert!(before.is_outdated(&grid));
+		let after = cache.get(&grid, MovementClass::Infantry, (3, 3));
+		assert!(!Rc::ptr_eq(&before, &after));
+		assert!(after.cost((5, 5)).is_infinite());
+		assert_eq!(cache.len(), 1);
+	}
+
+}
@@ -0,0 +1,346 @@
+use ::math::*;
+use ::terrain::Heightfield;
+
+/// Cell coordinates, x goes along world X and y along world Z
+pub type Cell = (usize, usize);
+
+/// Rectangle of cells, `max` is exclusive
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct CellRect {
+	pub min: Cell,
+	pub max: Cell,
+}
+
+impl CellRect {
+
+	pub fn width(&self) -> usize {
+		self.max.0 - self.min.0
+	}
+
+	pub fn height(&self) -> usize {
+		self.max.1 - self.min.1
+	}
+
+	pub fn contains(&self, cell: Cell) -> bool {
+		cell.0 >= self.min.0 && cell.1 >= self.min.1 && cell.0 < self.max.0 && cell.1 < self.max.1
+	}
+
+	/// Index of the cell in row-major arrays covering the rectangle
+	pub fn index(&self, cell: Cell) -> usize {
+		(cell.1 - self.min.1) * self.width() + (cell.0 - self.min.0)
+	}
+
+	pub fn cell(&self, index: usize) -> Cell {
+		(self.min.0 + index % self.width(), self.min.1 + index / self.width())
+	}
+
+}
+
+/// Kinds of units that differ in the terrain they can cross
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub enum MovementClass {
+	Infantry,
+	Vehicle,
+}
+
+impl MovementClass {
+
+	/// Steepest slope units of the class can climb
+	pub fn max_slope(&self) -> Rad<Real> {
+		match *self {
+			MovementClass::Infantry => Rad::from(Deg(40.0)),
+			MovementClass::Vehicle => Rad::from(Deg(25.0)),
+		}
+	}
+
+	pub fn all() -> &'static [MovementClass] {
+		&[MovementClass::Infantry, MovementClass::Vehicle]
+	}
+
+}
+
+/// Passability of the map split into square cells on the XZ plane.
+///
+/// Keeps the steepest terrain slope inside every cell, so one grid
+/// answers queries for all movement classes.
+pub struct NavGrid {
+	width: usize,
+	height: usize,
+	cell_size: Real,
+	slopes: Vec<Real>,
+	blocked: Vec<bool>,
+	version: u64,
+	changed_cells: Vec<Cell>,
+}
+
+impl NavGrid {
+
+	pub fn from_heightfield(heightfield: &Heightfield, cell_size: Real) -> Self {
+		let width = ((heightfield.scale.x / cell_size).ceil() as usize).max(1);
+		let height = ((heightfield.scale.z / cell_size).ceil() as usize).max(1);
+
+		let mut slopes = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				slopes.push(cell_slope(heightfield, (x, y), cell_size));
+			}
+		}
+
+		NavGrid {
+			width: width,
+			height: height,
+			cell_size: cell_size,
+			slopes: slopes,
+			blocked: vec![false; width * height],
+			version: 0,
+			changed_cells: Vec::new(),
+		}
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	pub fn cell_size(&self) -> Real {
+		self.cell_size
+	}
+
+	/// Changes every time passability changes, caches built over the grid compare it
+	pub fn version(&self) -> u64 {
+		self.version
+	}
+
+	pub fn bounds(&self) -> CellRect {
+		CellRect {
+			min: (0, 0),
+			max: (self.width, self.height),
+		}
+	}
+
+	pub fn index(&self, cell: Cell) -> usize {
+		cell.1 * self.width + cell.0
+	}
+
+	pub fn cell_at(&self, position: Vector2) -> Option<Cell> {
+		let x = (position.x / self.cell_size).floor();
+		let y = (position.y / self.cell_size).floor();
+
+		if x >= 0.0 && y >= 0.0 && (x as usize) < self.width && (y as usize) < self.height {
+			Some((x as usize, y as usize))
+		} else {
+			None
+		}
+	}
+
+	pub fn cell_center(&self, cell: Cell) -> Vector2 {
+		vec2(((cell.0 as Real) + 0.5) * self.cell_size, ((cell.1 as Real) + 0.5) * self.cell_size)
+	}
+
+	pub fn contains(&self, x: isize, y: isize) -> bool {
+		x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+	}
+
+	pub fn is_passable(&self, cell: Cell, class: MovementClass) -> bool {
+		let index = self.index(cell);
+		!self.blocked[index] && self.slopes[index] <= class.max_slope().0
+	}
+
+	/// Same as `is_passable`, cells outside of the grid are impassable
+	pub fn is_passable_at(&self, x: isize, y: isize, class: MovementClass) -> bool {
+		self.contains(x, y) && self.is_passable((x as usize, y as usize), class)
+	}
+
+	/// Steepest slope of the terrain inside the cell
+	pub fn slope(&self, cell: Cell) -> Rad<Real> {
+		Rad(self.slopes[self.index(cell)])
+	}
+
+	/// Picks up changes of the terrain inside the area, like ground flattened under a building
+	pub fn update_slopes(&mut self, heightfield: &Heightfield, area: CellRect) {
+		for y in area.min.1..area.max.1.min(self.height) {
+			for x in area.min.0..area.max.0.min(self.width) {
+				let slope = cell_slope(heightfield, (x, y), self.cell_size);
+				let index = self.index((x, y));
+				if self.slopes[index] != slope {
+					self.slopes[index] = slope;
+					self.version += 1;
+					self.changed_cells.push((x, y));
+				}
+			}
+		}
+	}
+
+	pub fn is_blocked(&self, cell: Cell) -> bool {
+		self.blocked[self.index(cell)]
+	}
+
+	/// Marks cells taken by obstacles like buildings
+	pub fn set_blocked(&mut self, cell: Cell, blocked: bool) {
+		let index = self.index(cell);
+		if self.blocked[index] != blocked {
+			self.blocked[index] = blocked;
+			self.version += 1;
+			self.changed_cells.push(cell);
+		}
+	}
+
+	/// Cells whose passability changed since the last call
+	pub fn take_changed_cells(&mut self) -> Vec<Cell> {
+		::std::mem::replace(&mut self.changed_cells, Vec::new())
+	}
+
+	/// Neighbour cells a unit can step to, diagonal steps may not cut corners.
+	/// Order is fixed so searches over the grid are deterministic.
+	pub fn neighbours(&self, cell: Cell, class: MovementClass) -> Vec<(Cell, Real)> {
+		let x = cell.0 as isize;
+		let y = cell.1 as isize;
+
+		let mut result = Vec::with_capacity(8);
+		for &(dx, dy) in NEIGHBOUR_OFFSETS.iter() {
+			if !self.is_passable_at(x + dx, y + dy, class) {
+				continue;
+			}
+
+			if dx != 0 && dy != 0 {
+				if !self.is_passable_at(x + dx, y, class) || !self.is_passable_at(x, y + dy, class) {
+					continue;
+				}
+				result.push((((x + dx) as usize, (y + dy) as usize), ::std::f32::consts::SQRT_2));
+			} else {
+				result.push((((x + dx) as usize, (y + dy) as usize), 1.0));
+			}
+		}
+
+		result
+	}
+
+	/// Checks that a unit can walk straight from `a` to `b`.
+	/// Walks every cell the segment touches, passing exactly through
+	/// a corner requires both cells beside it to be passable.
+	pub fn line_of_sight(&self, a: Vector2, b: Vector2, class: MovementClass) -> bool {
+		let mut x = (a.x / self.cell_size).floor() as isize;
+		let mut y = (a.y / self.cell_size).floor() as isize;
+		let end_x = (b.x / self.cell_size).floor() as isize;
+		let end_y = (b.y / self.cell_size).floor() as isize;
+
+		let delta = b - a;
+		let step_x: isize = if delta.x > 0.0 { 1 } else { -1 };
+		let step_y: isize = if delta.y > 0.0 { 1 } else { -1 };
+
+		let boundary = |cell: isize, step: isize| ((cell + if step > 0 { 1 } else { 0 }) as Real) * self.cell_size;
+
+		let mut t_max_x = if delta.x != 0.0 { (boundary(x, step_x) - a.x) / delta.x } else { ::std::f32::INFINITY };
+		let mut t_max_y = if delta.y != 0.0 { (boundary(y, step_y) - a.y) / delta.y } else { ::std::f32::INFINITY };
+		let t_delta_x = if delta.x != 0.0 { self.cell_size / delta.x.abs() } else { ::std::f32::INFINITY };
+		let t_delta_y = if delta.y != 0.0 { self.cell_size / delta.y.abs() } else { ::std::f32::INFINITY };
+
+		let max_steps = (end_x - x).abs() + (end_y - y).abs() + 1;
+
+		for _ in 0..(max_steps + 1) {
+			if !self.is_passable_at(x, y, class) {
+				return false;
+			}
+			if x == end_x && y == end_y {
+				return true;
+			}
+
+			if (t_max_x - t_max_y).abs() < 0.000001 {
+				if !self.is_passable_at(x + step_x, y, class) || !self.is_passable_at(x, y + step_y, class) {
+					return false;
+				}
+				x += step_x;
+				y += step_y;
+				t_max_x += t_delta_x;
+				t_max_y += t_delta_y;
+			} else if t_max_x < t_max_y {
+				x += step_x;
+				t_max_x += t_delta_x;
+			} else {
+				y += step_y;
+				t_max_y += t_delta_y;
+			}
+		}
+
+		false
+	}
+
+}
+
+/// Steepest slope at the center and the corners of the cell
+fn cell_slope(heightfield: &Heightfield, cell: Cell, cell_size: Real) -> Real {
+	let x0 = (cell.0 as Real) * cell_size;
+	let z0 = (cell.1 as Real) * cell_size;
+	let x1 = (x0 + cell_size).min(heightfield.scale.x);
+	let z1 = (z0 + cell_size).min(heightfield.scale.z);
+
+	let samples = [((x0 + x1) * 0.5, (z0 + z1) * 0.5), (x0, z0), (x1, z0), (x0, z1), (x1, z1)];
+	samples.iter()
+		.map(|&(sx, sz)| heightfield.slope_at(sx, sz).0)
+		.fold(0.0, Real::max)
+}
+
+const NEIGHBOUR_OFFSETS: [(isize, isize); 8] = [(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn test_slope_passability() {
+		// flat ground on the left, a 45 degree ramp on the right
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 64.0, 64.0), |x, _| if x < 32 { 0.0 } else { (x - 32) as Real / 64.0 });
+		let grid = NavGrid::from_heightfield(&heightfield, 2.0);
+
+		assert_eq!((grid.width(), grid.height()), (32, 32));
+		assert!(grid.is_passable((4, 16), MovementClass::Vehicle));
+		assert!(!grid.is_passable((24, 16), MovementClass::Infantry));
+		assert!(!grid.is_passable((24, 16), MovementClass::Vehicle));
+
+		// 30 degree ramp is too steep for vehicles only
+		let rise = (30.0 as Real).to_radians().tan();
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 64.0 * rise, 64.0), |x, _| x as Real / 64.0);
+		let grid = NavGrid::from_heightfield(&heightfield, 2.0);
+
+		assert!(grid.is_passable((16, 16), MovementClass::Infantry));
+		assert!(!grid.is_passable((16, 16), MovementClass::Vehicle));
+	}
+
+	#[test]
+	fn test_update_slopes() {
+		let mut heightfield = Heightfield::from_fn(16, 16, vec3(16.0, 16.0, 16.0), |x, _| x as Real / 16.0);
+		let mut grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		assert!(!grid.is_passable((8, 8), MovementClass::Vehicle));
+
+		heightfield.flatten(vec2(4.0, 4.0), vec2(12.0, 12.0));
+		let version = grid.version();
+		grid.update_slopes(&heightfield, CellRect { min: (4, 4), max: (12, 12) });
+
+		assert!(grid.is_passable((8, 8), MovementClass::Vehicle));
+		assert_eq!(grid.slope((8, 8)).0, 0.0);
+		assert!(grid.version() > version);
+		assert!(grid.take_changed_cells().contains(&(8, 8)));
+	}
+
+	#[test]
+	fn test_line_of_sight() {
+		let heightfield = Heightfield::from_fn(8, 8, vec3(8.0, 1.0, 8.0), |_, _| 0.0);
+		let mut grid = NavGrid::from_heightfield(&heightfield, 1.0);
+		let class = MovementClass::Infantry;
+
+		assert!(grid.line_of_sight(vec2(0.5, 0.5), vec2(7.5, 6.5), class));
+
+		grid.set_blocked((3, 3), true);
+		assert!(!grid.line_of_sight(vec2(0.5, 0.5), vec2(7.5, 7.5), class));
+		assert!(grid.line_of_sight(vec2(0.5, 3.5), vec2(2.5, 3.5), class));
+
+		// passing exactly through the corner of a blocked cell is not allowed
+		assert!(!grid.line_of_sight(vec2(3.5, 4.5), vec2(4.5, 3.5), class));
+		assert!(grid.line_of_sight(vec2(4.5, 5.5), vec2(5.5, 4.5), class));
+	}
+
+}
@@ -0,0 +1,617 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use ::math::*;
+use ::navigation::{NavGrid, Cell, CellRect, MovementClass, find_cell_path_in, smooth_path, octile_distance};
+
+/// Entrances wider than this get a transition at both ends instead of one in the middle
+const MAX_SINGLE_TRANSITION_WIDTH: usize = 6;
+
+/// Borders are owned by the cluster west or north of them
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+enum BorderSide {
+	East,
+	South,
+}
+
+type BorderKey = (usize, BorderSide);
+
+struct AbstractNode {
+	cell: Cell,
+	cluster: usize,
+	border: BorderKey,
+	/// Node on the other side of the border
+	transition: usize,
+	/// Nodes of the same cluster with costs of walking to them
+	edges: Vec<(usize, Real)>,
+}
+
+/// Abstraction of the navigation grid for HPA*.
+///
+/// The grid is split into square clusters. Passable runs along cluster
+/// borders become entrances with a node on each side, nodes of a cluster
+/// are connected with the costs of walking between them inside the cluster.
+/// Searches go over this small graph first and are refined into cells
+/// only along the found route.
+pub struct AbstractGraph {
+	class: MovementClass,
+	cluster_size: usize,
+	clusters_x: usize,
+	clusters_y: usize,
+	nodes: Vec<Option<AbstractNode>>,
+	free_nodes: Vec<usize>,
+	cluster_nodes: Vec<Vec<usize>>,
+}
+
+impl AbstractGraph {
+
+	pub fn build(grid: &NavGrid, class: MovementClass, cluster_size: usize) -> Self {
+		assert!(cluster_size > 1);
+
+		let clusters_x = (grid.width() + cluster_size - 1) / cluster_size;
+		let clusters_y = (grid.height() + cluster_size - 1) / cluster_size;
+
+		let mut graph = AbstractGraph {
+			class: class,
+			cluster_size: cluster_size,
+			clusters_x: clusters_x,
+			clusters_y: clusters_y,
+			nodes: Vec::new(),
+			free_nodes: Vec::new(),
+			cluster_nodes: vec![Vec::new(); clusters_x * clusters_y],
+		};
+
+		for cluster in 0..(clusters_x * clusters_y) {
+			for &border in graph.own_borders(cluster).iter() {
+				graph.add_entrances(grid, border);
+			}
+		}
+
+		for cluster in 0..(clusters_x * clusters_y) {
+			graph.connect_cluster(grid, cluster);
+		}
+
+		graph
+	}
+
+	pub fn class(&self) -> MovementClass {
+		self.class
+	}
+
+	pub fn cluster_at(&self, cell: Cell) -> usize {
+		(cell.1 / self.cluster_size) * self.clusters_x + cell.0 / self.cluster_size
+	}
+
+	pub fn cluster_rect(&self, grid: &NavGrid, cluster: usize) -> CellRect {
+		let x = (cluster % self.clusters_x) * self.cluster_size;
+		let y = (cluster / self.clusters_x) * self.cluster_size;
+
+		CellRect {
+			min: (x, y),
+			max: ((x + self.cluster_size).min(grid.width()), (y + self.cluster_size).min(grid.height())),
+		}
+	}
+
+	/// Rebuilds clusters containing the changed cells together with
+	/// their borders, the rest of the graph stays untouched
+	pub fn update_cells(&mut self, grid: &NavGrid, cells: &[Cell]) {
+		if cells.is_empty() {
+			return;
+		}
+
+		let mut clusters: Vec<usize> = cells.iter().map(|&cell| self.cluster_at(cell)).collect();
+		clusters.sort();
+		clusters.dedup();
+
+		let mut borders = Vec::new();
+		for &cluster in clusters.iter() {
+			let x = cluster % self.clusters_x;
+			let y = cluster / self.clusters_x;
+
+			borders.extend(self.own_borders(cluster));
+			if x > 0 {
+				borders.push((cluster - 1, BorderSide::East));
+			}
+			if y > 0 {
+				borders.push((cluster - self.clusters_x, BorderSide::South));
+			}
+		}
+		borders.sort();
+		borders.dedup();
+
+		let mut touched_clusters = clusters;
+		for &border in borders.iter() {
+			let (a, b) = self.border_clusters(border);
+			touched_clusters.push(a);
+			touched_clusters.push(b);
+		}
+		touched_clusters.sort();
+		touched_clusters.dedup();
+
+		for &border in borders.iter() {
+			self.remove_entrances(border);
+		}
+		for &border in borders.iter() {
+			self.add_entrances(grid, border);
+		}
+		for &cluster in touched_clusters.iter() {
+			self.connect_cluster(grid, cluster);
+		}
+	}
+
+	/// Route through entrances from `start` to `goal`, both included.
+	/// Consecutive cells are either in the same cluster or on the two sides of a border.
+	pub fn find_abstract_path(&self, grid: &NavGrid, start: Cell, goal: Cell) -> Option<Vec<Cell>> {
+		let start_cluster = self.cluster_at(start);
+		let goal_cluster = self.cluster_at(goal);
+
+		if start_cluster == goal_cluster {
+			let area = self.cluster_rect(grid, start_cluster);
+			if find_cell_path_in(grid, self.class, start, goal, area).is_some() {
+				return Some(vec![start, goal]);
+			}
+		}
+
+		let start_edges = self.costs_to_cluster_nodes(grid, start);
+		let goal_edges = self.costs_to_cluster_nodes(grid, goal);
+		if start_edges.is_empty() || goal_edges.is_empty() {
+			return None;
+		}
+
+		// temporary nodes after the graph ones
+		let start_node = self.nodes.len();
+		let goal_node = self.nodes.len() + 1;
+		let node_cell = |node: usize| if node == start_node {
+			start
+		} else if node == goal_node {
+			goal
+		} else {
+			self.nodes[node].as_ref().unwrap().cell
+		};
+
+		let mut costs = vec![::std::f32::INFINITY; self.nodes.len() + 2];
+		let mut parents = vec![::std::usize::MAX; self.nodes.len() + 2];
+		let mut closed = vec![false; self.nodes.len() + 2];
+		let mut open = BinaryHeap::new();
+		let mut order = 0;
+
+		costs[start_node] = 0.0;
+		open.push(OpenNode { estimate: octile_distance(start, goal), order: order, node: start_node });
+
+		while let Some(OpenNode { node, .. }) = open.pop() {
+			if closed[node] {
+				continue;
+			}
+			closed[node] = true;
+
+			if node == goal_node {
+				let mut path = vec![goal];
+				let mut current = parents[goal_node];
+				while current != ::std::usize::MAX {
+					let cell = node_cell(current);
+					if *path.last().unwrap() != cell {
+						path.push(cell);
+					}
+					current = parents[current];
+				}
+				path.reverse();
+				return Some(path);
+			}
+
+			let mut neighbours: Vec<(usize, Real)> = Vec::new();
+			if node == start_node {
+				neighbours.extend(start_edges.iter().cloned());
+			} else {
+				let abstract_node = self.nodes[node].as_ref().unwrap();
+				neighbours.extend(abstract_node.edges.iter().cloned());
+				neighbours.push((abstract_node.transition, 1.0));
+				if let Some(&(_, cost)) = goal_edges.iter().find(|&&(goal_edge, _)| goal_edge == node) {
+					neighbours.push((goal_node, cost));
+				}
+			}
+
+			for (neighbour, edge_cost) in neighbours {
+				if closed[neighbour] {
+					continue;
+				}
+
+				let cost = costs[node] + edge_cost;
+				if cost < costs[neighbour] {
+					costs[neighbour] = cost;
+					parents[neighbour] = node;
+					order += 1;
+					open.push(OpenNode { estimate: cost + octile_distance(node_cell(neighbour), goal), order: order, node: neighbour });
+				}
+			}
+		}
+
+		None
+	}
+
+	/// Expands an abstract path into a path over neighbouring cells
+	pub fn refine_path(&self, grid: &NavGrid, abstract_path: &[Cell]) -> Option<Vec<Cell>> {
+		let mut cells = match abstract_path.first() {
+			Some(&first) => vec![first],
+			None => return None,
+		};
+
+		for step in abstract_path.windows(2) {
+			let (from, to) = (step[0], step[1]);
+
+			if self.cluster_at(from) != self.cluster_at(to) {
+				// transition across a border, cells are next to each other
+				cells.push(to);
+				continue;
+			}
+
+			let area = self.cluster_rect(grid, self.cluster_at(from));
+			match find_cell_path_in(grid, self.class, from, to, area) {
+				Some(segment) => cells.extend(segment.into_iter().skip(1)),
+				None => return None,
+			}
+		}
+
+		Some(cells)
+	}
+
+	/// Like `find_path`, but searches the abstract graph first
+	pub fn find_path(&self, grid: &NavGrid, start: Vector2, goal: Vector2) -> Option<Vec<Vector2>> {
+		let start_cell = match grid.cell_at(start) {
+			Some(cell) => cell,
+			None => return None,
+		};
+		let goal_cell = match grid.cell_at(goal) {
+			Some(cell) if grid.is_passable(cell, self.class) => cell,
+			_ => return None,
+		};
+
+		self.find_abstract_path(grid, start_cell, goal_cell)
+			.and_then(|abstract_path| self.refine_path(grid, &abstract_path))
+			.map(|cells| smooth_path(grid, self.class, start, goal, &cells))
+	}
+
+	fn own_borders(&self, cluster: usize) -> Vec<BorderKey> {
+		let mut borders = Vec::with_capacity(2);
+		if cluster % self.clusters_x + 1 < self.clusters_x {
+			borders.push((cluster, BorderSide::East));
+		}
+		if cluster / self.clusters_x + 1 < self.clusters_y {
+			borders.push((cluster, BorderSide::South));
+		}
+		borders
+	}
+
+	fn border_clusters(&self, border: BorderKey) -> (usize, usize) {
+		match border.1 {
+			BorderSide::East => (border.0, border.0 + 1),
+			BorderSide::South => (border.0, border.0 + self.clusters_x),
+		}
+	}
+
+	/// Pairs of cells facing each other across the border, in order along it
+	fn border_cells(&self, grid: &NavGrid, border: BorderKey) -> Vec<(Cell, Cell)> {
+		let rect = self.cluster_rect(grid, border.0);
+		match border.1 {
+			BorderSide::East => (rect.min.1..rect.max.1).map(|y| ((rect.max.0 - 1, y), (rect.max.0, y))).collect(),
+			BorderSide::South => (rect.min.0..rect.max.0).map(|x| ((x, rect.max.1 - 1), (x, rect.max.1))).collect(),
+		}
+	}
+
+	fn add_entrances(&mut self, grid: &NavGrid, border: BorderKey) {
+		let class = self.class;
+		let pairs = self.border_cells(grid, border);
+
+		let mut transitions = Vec::new();
+		let mut run_start = None;
+
+		for i in 0..(pairs.len() + 1) {
+			let is_open = i < pairs.len() && grid.is_passable(pairs[i].0, class) && grid.is_passable(pairs[i].1, class);
+
+			match (is_open, run_start) {
+				(true, None) => run_start = Some(i),
+				(false, Some(first)) => {
+					let last = i - 1;
+					if last - first + 1 > MAX_SINGLE_TRANSITION_WIDTH {
+						transitions.push(pairs[first]);
+						transitions.push(pairs[last]);
+					} else {
+						transitions.push(pairs[(first + last) / 2]);
+					}
+					run_start = None;
+				}
+				_ => (),
+			}
+		}
+
+		let (cluster_a, cluster_b) = self.border_clusters(border);
+		for (cell_a, cell_b) in transitions {
+			let a = self.add_node(cell_a, cluster_a, border);
+			let b = self.add_node(cell_b, cluster_b, border);
+			self.nodes[a].as_mut().unwrap().transition = b;
+			self.nodes[b].as_mut().unwrap().transition = a;
+		}
+	}
+
+	fn remove_entrances(&mut self, border: BorderKey) {
+		for id in 0..self.nodes.len() {
+			let cluster = match self.nodes[id] {
+				Some(ref node) if node.border == border => node.cluster,
+				_ => continue,
+			};
+
+			self.nodes[id] = None;
+			self.free_nodes.push(id);
+			self.cluster_nodes[cluster].retain(|&node| node != id);
+		}
+	}
+
+	fn add_node(&mut self, cell: Cell, cluster: usize, border: BorderKey) -> usize {
+		let node = AbstractNode {
+			cell: cell,
+			cluster: cluster,
+			border: border,
+			transition: ::std::usize::MAX,
+			edges: Vec::new(),
+		};
+
+		let id = match self.free_nodes.pop() {
+			Some(id) => {
+				self.nodes[id] = Some(node);
+				id
+			}
+			None => {
+				self.nodes.push(Some(node));
+				self.nodes.len() - 1
+			}
+		};
+
+		self.cluster_nodes[cluster].push(id);
+		id
+	}
+
+	/// Recomputes costs between all nodes of the cluster
+	fn connect_cluster(&mut self, grid: &NavGrid, cluster: usize) {
+		let area = self.cluster_rect(grid, cluster);
+		let ids = self.cluster_nodes[cluster].clone();
+
+		for &id in ids.iter() {
+			let costs = cluster_costs(grid, self.class, self.nodes[id].as_ref().unwrap().cell, area);
+
+			let edges = ids.iter()
+				.filter(|&&other| other != id)
+				.map(|&other| (other, costs[area.index(self.nodes[other].as_ref().unwrap().cell)]))
+				.filter(|&(_, cost)| cost.is_finite())
+				.collect();
+
+			self.nodes[id].as_mut().unwrap().edges = edges;
+		}
+	}
+
+	/// Costs from the cell to the nodes of its cluster it can reach
+	fn costs_to_cluster_nodes(&self, grid: &NavGrid, cell: Cell) -> Vec<(usize, Real)> {
+		let cluster = self.cluster_at(cell);
+		let area = self.cluster_rect(grid, cluster);
+		let costs = cluster_costs(grid, self.class, cell, area);
+
+		self.cluster_nodes[cluster].iter()
+			.map(|&id| (id, costs[area.index(self.nodes[id].as_ref().unwrap().cell)]))
+			.filter(|&(_, cost)| cost.is_finite())
+			.collect()
+	}
+
+}
+
+/// Dijkstra from the cell to every cell of the area, without leaving it
+fn cluster_costs(grid: &NavGrid, class: MovementClass, source: Cell, area: CellRect) -> Vec<Real> {
+	let mut costs = vec![::std::f32::INFINITY; area.width() * area.height()];
+	let mut open = BinaryHeap::new();
+	let mut order = 0;
+
+	costs[area.index(source)] = 0.0;
+	open.push(OpenCell { cost: 0.0, order: order, cell: source });
+
+	while let Some(OpenCell { cost, cell, .. }) = open.pop() {
+		if cost > costs[area.index(cell)] {
+			continue;
+		}
+
+		for (neighbour, step_cost) in grid.neighbours(cell, class) {
+			if !area.contains(neighbour) {
+				continue;
+			}
+
+			let neighbour_cost = cost + step_cost;
+			let index = area.index(neighbour);
+			if neighbour_cost < costs[index] {
+				costs[index] = neighbour_cost;
+				order += 1;
+				open.push(OpenCell { cost: neighbour_cost, order: order, cell: neighbour });
+			}
+		}
+	}
+
+	costs
+}
+
+struct OpenNode {
+	estimate: Real,
+	order: u32,
+	node: usize,
+}
+
+impl PartialEq for OpenNode {
+	fn eq(&self, other: &OpenNode) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for OpenNode {}
+
+impl PartialOrd for OpenNode {
+	fn partial_cmp(&self, other: &OpenNode) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenNode {
+	/// Reversed for the max-heap, lowest estimate first
+	fn cmp(&self, other: &OpenNode) -> Ordering {
+		other.estimate.partial_cmp(&self.estimate).unwrap_or(Ordering::Equal)
+			.then_with(|| other.order.cmp(&self.order))
+	}
+}
+
+struct OpenCell {
+	cost: Real,
+	order: u32,
+	cell: Cell,
+}
+
+impl PartialEq for OpenCell {
+	fn eq(&self, other: &OpenCell) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for OpenCell {}
+
+impl PartialOrd for OpenCell {
+	fn partial_cmp(&self, other: &OpenCell) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for OpenCell {
+	/// Reversed for the max-heap, cheapest first
+	fn cmp(&self, other: &OpenCell) -> Ordering {
+		other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+			.then_with(|| other.order.cmp(&self.order))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+	use ::navigation::find_cell_path;
+	use ::terrain::Heightfield;
+
+	fn flat_grid(size: usize) -> NavGrid {
+		let heightfield = Heightfield::from_fn(size, size, vec3(size as Real, 1.0, size as Real), |_, _| 0.0);
+		NavGrid::from_heightfield(&heightfield, 1.0)
+	}
+
+	// walls with alternating gaps, so paths have to zigzag through the map
+	fn maze_grid(size: usize) -> NavGrid {
+		let mut grid = flat_grid(size);
+		let mut i = 0;
+		let mut x = 10;
+		while x < size - 4 {
+			for y in 0..size {
+				let gap = if i % 2 == 0 { y >= size - 6 } else { y < 6 };
+				if !gap {
+					grid.set_blocked((x, y), true);
+				}
+			}
+			i += 1;
+			x += 12;
+		}
+		grid.take_changed_cells();
+		grid
+	}
+
+	fn cell_path_cost(cells: &[Cell]) -> Real {
+		cells.windows(2).map(|step| octile_distance(step[0], step[1])).sum()
+	}
+
+	fn assert_walkable(grid: &NavGrid, cells: &[Cell]) {
+		for step in cells.windows(2) {
+			assert!(grid.neighbours(step[0], MovementClass::Infantry).iter().any(|&(cell, _)| cell == step[1]), "{:?}", step);
+		}
+	}
+
+	// nodes as (cell, transition cell, edges as (cell, cost)), independent of node ids
+	fn describe(graph: &AbstractGraph) -> Vec<(Cell, Cell, Vec<(Cell, i64)>)> {
+		let cell = |id: usize| graph.nodes[id].as_ref().unwrap().cell;
+		let mut nodes: Vec<_> = graph.nodes.iter().filter_map(|node| node.as_ref()).map(|node| {
+			let mut edges: Vec<(Cell, i64)> = node.edges.iter().map(|&(id, cost)| (cell(id), (cost * 1000.0).round() as i64)).collect();
+			edges.sort();
+			(node.cell, cell(node.transition), edges)
+		}).collect();
+		nodes.sort();
+		nodes
+	}
+
+	#[test]
+	fn test_hierarchical_path() {
+		let grid = maze_grid(64);
+		let graph = AbstractGraph::build(&grid, MovementClass::Infantry, 16);
+
+		let start = (2, 2);
+		let goal = (61, 40);
+
+		let abstract_path = graph.find_abstract_path(&grid, start, goal).unwrap();
+		let cells = graph.refine_path(&grid, &abstract_path).unwrap();
+
+		assert_eq!(cells[0], start);
+		assert_eq!(*cells.last().unwrap(), goal);
+		assert_walkable(&grid, &cells);
+
+		// abstraction costs a little optimality
+		let optimal = cell_path_cost(&find_cell_path(&grid, MovementClass::Infantry, start, goal).unwrap());
+		assert!(cell_path_cost(&cells) < optimal * 1.2);
+
+		let waypoints = graph.find_path(&grid, vec2(2.5, 2.5), vec2(61.5, 40.5)).unwrap();
+		assert_eq!(*waypoints.last().unwrap(), vec2(61.5, 40.5));
+	}
+
+	#[test]
+	fn test_path_inside_cluster() {
+		let grid = flat_grid(32);
+		let graph = AbstractGraph::build(&grid, MovementClass::Infantry, 16);
+
+		assert_eq!(graph.find_abstract_path(&grid, (1, 1), (14, 9)).unwrap(), vec![(1, 1), (14, 9)]);
+		assert!(graph.find_abstract_path(&grid, (1, 1), (30, 30)).unwrap().len() > 2);
+	}
+
+	#[test]
+	fn test_unreachable() {
+		let mut grid = flat_grid(32);
+		for y in 0..32 {
+			grid.set_blocked((20, y), true);
+		}
+		let graph = AbstractGraph::build(&grid, MovementClass::Infantry, 8);
+
+		assert!(graph.find_abstract_path(&grid, (2, 2), (28, 2)).is_none());
+		assert!(graph.find_abstract_path(&grid, (2, 2), (18, 30)).is_some());
+	}
+
+	#[test]
+	fn test_incremental_rebuild() {
+		let mut grid = maze_grid(64);
+		let mut graph = AbstractGraph::build(&grid, MovementClass::Infantry, 16);
+		assert!(graph.find_abstract_path(&grid, (2, 2), (61, 40)).is_some());
+
+		// building closes the first gap
+		for y in 58..64 {
+			grid.set_blocked((10, y), true);
+		}
+		let changed = grid.take_changed_cells();
+		graph.update_cells(&grid, &changed);
+
+		assert!(graph.find_abstract_path(&grid, (2, 2), (61, 40)).is_none());
+		assert_eq!(describe(&graph), describe(&AbstractGraph::build(&grid, MovementClass::Infantry, 16)));
+
+		// and opens a new one
+		for y in 20..23 {
+			grid.set_blocked((10, y), false);
+		}
+		let changed = grid.take_changed_cells();
+		graph.update_cells(&grid, &changed);
+
+		let cells = graph.refine_path(&grid, &graph.find_abstract_path(&grid, (2, 2), (61, 40)).unwrap()).unwrap();
+		assert_walkable(&grid, &cells);
+		assert_eq!(describe(&graph), describe(&AbstractGraph::build(&grid, MovementClass::Infantry, 16)));
+	}
+
+}
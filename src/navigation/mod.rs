@@ -0,0 +1,63 @@
+mod astar;
+mod avoidance;
+mod flowfield;
+mod grid;
+mod hierarchy;
+
+pub use self::astar::*;
+pub use self::avoidance::*;
+pub use self::flowfield::*;
+pub use self::grid::*;
+pub use self::hierarchy::*;
+
+use ::math::*;
+
+/// Cells in one side of a cluster of the hierarchical graph
+const CLUSTER_SIZE: usize = 16;
+
+/// Grids with more cells than this are searched hierarchically
+const HIERARCHICAL_SEARCH_MIN_CELLS: usize = 128 * 128;
+
+/// Everything units need to find their way around the map
+pub struct Navigation {
+	pub grid: NavGrid,
+	pub flow_fields: FlowFieldCache,
+	graphs: Vec<AbstractGraph>,
+}
+
+impl Navigation {
+
+	pub fn new(grid: NavGrid) -> Navigation {
+		let graphs = MovementClass::all().iter()
+			.map(|&class| AbstractGraph::build(&grid, class, CLUSTER_SIZE))
+			.collect();
+
+		Navigation {
+			grid: grid,
+			flow_fields: FlowFieldCache::new(),
+			graphs: graphs,
+		}
+	}
+
+	pub fn graph(&self, class: MovementClass) -> &AbstractGraph {
+		self.graphs.iter().find(|graph| graph.class() == class).unwrap()
+	}
+
+	/// Brings the hierarchical graphs up to date with changes of the grid
+	pub fn update(&mut self) {
+		let changed_cells = self.grid.take_changed_cells();
+		for graph in self.graphs.iter_mut() {
+			graph.update_cells(&self.grid, &changed_cells);
+		}
+	}
+
+	/// Plain A* on small maps, HPA* on big ones
+	pub fn find_path(&self, class: MovementClass, start: Vector2, goal: Vector2) -> Option<Vec<Vector2>> {
+		if self.grid.width() * self.grid.height() > HIERARCHICAL_SEARCH_MIN_CELLS {
+			self.graph(class).find_path(&self.grid, start, goal)
+		} else {
+			find_path(&self.grid, class, start, goal)
+		}
+	}
+
+}
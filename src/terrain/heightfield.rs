@@ -0,0 +1,248 @@
+use ::math::*;
+use ::terrain::HeightBoundsTree;
+
+/// CPU copy of the terrain heightmap.
+///
+/// Samples are stored the same way they are laid out in the GPU texture:
+/// row-major, first row at v = 0, values normalized to 0..1.
+/// All queries reproduce what the terrain shaders compute, so gameplay
+/// and the rendered ground agree.
+pub struct Heightfield {
+	width: usize,
+	height: usize,
+	samples: Vec<Real>,
+	bounds_tree: Option<HeightBoundsTree>,
+	version: u64,
+	pub scale: Vector3,
+}
+
+// Offset used by the terrain shaders to estimate normals, in texture coordinates
+const NORMAL_SAMPLE_STEP: Real = 1.0 / 64.0;
+
+impl Heightfield {
+
+	pub fn new(width: usize, height: usize, samples: Vec<Real>, scale: Vector3) -> Self {
+		assert!(width > 0 && height > 0);
+		assert_eq!(samples.len(), width * height);
+
+		let mut heightfield = Heightfield {
+			width: width,
+			height: height,
+			samples: samples,
+			bounds_tree: None,
+			version: 0,
+			scale: scale,
+		};
+
+		heightfield.bounds_tree = Some(HeightBoundsTree::build(&heightfield));
+
+		heightfield
+	}
+
+	pub fn from_fn<F: Fn(usize, usize) -> Real>(width: usize, height: usize, scale: Vector3, f: F) -> Self {
+		let mut samples = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				samples.push(f(x, y));
+			}
+		}
+
+		Heightfield::new(width, height, samples, scale)
+	}
+
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Normalized samples, laid out like the GPU texture
+	pub fn samples(&self) -> &[Real] {
+		&self.samples
+	}
+
+	/// Changes every time the samples change, caches built over the terrain compare it
+	pub fn version(&self) -> u64 {
+		self.version
+	}
+
+	pub fn bounds_tree(&self) -> &HeightBoundsTree {
+		self.bounds_tree.as_ref().unwrap()
+	}
+
+	/// Normalized height of a single texel, out of range coordinates are mirrored
+	/// like the default sampler does
+	pub fn texel(&self, x: isize, y: isize) -> Real {
+		let x = mirror_index(x, self.width);
+		let y = mirror_index(y, self.height);
+		self.samples[y * self.width + x]
+	}
+
+	/// Bilinear sample at texture coordinates, same as GL_LINEAR filtering
+	pub fn sample(&self, u: Real, v: Real) -> Real {
+		let tu = u * (self.width as Real) - 0.5;
+		let tv = v * (self.height as Real) - 0.5;
+
+		let x0 = tu.floor();
+		let y0 = tv.floor();
+		let fx = tu - x0;
+		let fy = tv - y0;
+
+		let x0 = x0 as isize;
+		let y0 = y0 as isize;
+
+		let s00 = self.texel(x0, y0);
+		let s10 = self.texel(x0 + 1, y0);
+		let s01 = self.texel(x0, y0 + 1);
+		let s11 = self.texel(x0 + 1, y0 + 1);
+
+		let top = s00 + (s10 - s00) * fx;
+		let bottom = s01 + (s11 - s01) * fx;
+
+		top + (bottom - top) * fy
+	}
+
+	pub fn contains(&self, x: Real, z: Real) -> bool {
+		x >= 0.0 && x <= self.scale.x && z >= 0.0 && z <= self.scale.z
+	}
+
+	/// Terrain height in world units at world position (x, z)
+	pub fn height_at(&self, x: Real, z: Real) -> Real {
+		self.sample(x / self.scale.x, z / self.scale.z) * self.scale.y
+	}
+
+	pub fn normal_at(&self, x: Real, z: Real) -> Vector3 {
+		let u = x / self.scale.x;
+		let v = z / self.scale.z;
+		let step = NORMAL_SAMPLE_STEP;
+
+		let s01 = self.sample(u - step, v);
+		let s21 = self.sample(u + step, v);
+		let s10 = self.sample(u, v - step);
+		let s12 = self.sample(u, v + step);
+
+		let va = vec3(self.scale.x / 32.0, (s21 - s01) * self.scale.y, 0.0).normalize();
+		let vb = vec3(0.0, (s12 - s10) * self.scale.y, self.scale.z / 32.0).normalize();
+
+		(-va.cross(vb)).normalize()
+	}
+
+	/// Angle between the terrain surface and the horizontal plane
+	pub fn slope_at(&self, x: Real, z: Real) -> Rad<Real> {
+		Rad(self.normal_at(x, z).y.max(-1.0).min(1.0).acos())
+	}
+
+	/// Levels the texels with centers inside the rectangle on the XZ plane to their average height.
+	/// The ground around the rectangle blends into it over a texel, like filtering does.
+	pub fn flatten(&mut self, min: Vector2, max: Vector2) {
+		let texel_x = |x: usize| (x as Real + 0.5) / self.width as Real * self.scale.x;
+		let texel_z = |y: usize| (y as Real + 0.5) / self.height as Real * self.scale.z;
+
+		let columns: Vec<usize> = (0..self.width).filter(|&x| texel_x(x) >= min.x && texel_x(x) <= max.x).collect();
+		let rows: Vec<usize> = (0..self.height).filter(|&y| texel_z(y) >= min.y && texel_z(y) <= max.y).collect();
+		if columns.is_empty() || rows.is_empty() {
+			return;
+		}
+
+		let mut sum = 0.0;
+		for &y in rows.iter() {
+			for &x in columns.iter() {
+				sum += self.samples[y * self.width + x];
+			}
+		}
+		let level = sum / (columns.len() * rows.len()) as Real;
+
+		for &y in rows.iter() {
+			for &x in columns.iter() {
+				self.samples[y * self.width + x] = level;
+			}
+		}
+
+		self.version += 1;
+		self.bounds_tree = Some(HeightBoundsTree::build(self));
+	}
+
+}
+
+fn mirror_index(i: isize, size: usize) -> usize {
+	let size = size as isize;
+	let i = ((i % (2 * size)) + 2 * size) % (2 * size);
+	if i < size {
+		i as usize
+	} else {
+		(2 * size - 1 - i) as usize
+	}
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	fn assert_near(a: Real, b: Real) {
+		assert!((a - b).abs() < 0.0001, "{} != {}", a, b);
+	}
+
+	#[test]
+	fn test_flat_heightfield() {
+		let heightfield = Heightfield::from_fn(4, 4, vec3(100.0, 20.0, 100.0), |_, _| 0.5);
+
+		assert_near(heightfield.height_at(0.0, 0.0), 10.0);
+		assert_near(heightfield.height_at(37.0, 81.0), 10.0);
+
+		let normal = heightfield.normal_at(50.0, 50.0);
+		assert_near(normal.x, 0.0);
+		assert_near(normal.y, 1.0);
+		assert_near(normal.z, 0.0);
+		assert_near(heightfield.slope_at(50.0, 50.0).0, 0.0);
+	}
+
+	#[test]
+	fn test_bilinear_sampling() {
+		let heightfield = Heightfield::from_fn(2, 2, vec3(2.0, 1.0, 2.0), |x, y| (x + 2 * y) as Real * 0.25);
+
+		// texel centers
+		assert_near(heightfield.height_at(0.5, 0.5), 0.0);
+		assert_near(heightfield.height_at(1.5, 0.5), 0.25);
+		assert_near(heightfield.height_at(0.5, 1.5), 0.5);
+		assert_near(heightfield.height_at(1.5, 1.5), 0.75);
+
+		// between texel centers
+		assert_near(heightfield.height_at(1.0, 1.0), 0.375);
+
+		// mirrored at the edges
+		assert_near(heightfield.height_at(0.0, 0.5), 0.0);
+		assert_near(heightfield.height_at(2.0, 0.5), 0.25);
+	}
+
+	#[test]
+	fn test_slope_normal() {
+		// height rises along x by 1 world unit per world unit
+		let heightfield = Heightfield::from_fn(64, 64, vec3(64.0, 64.0, 64.0), |x, _| x as Real / 64.0);
+
+		let normal = heightfield.normal_at(32.0, 32.0);
+		assert!(normal.x < 0.0);
+		assert_near(normal.z, 0.0);
+		assert_near(heightfield.slope_at(32.0, 32.0).0, ::std::f32::consts::PI / 4.0);
+	}
+
+	#[test]
+	fn test_flatten() {
+		let mut heightfield = Heightfield::from_fn(8, 8, vec3(8.0, 8.0, 8.0), |x, _| x as Real / 8.0);
+		let version = heightfield.version();
+		heightfield.flatten(vec2(2.0, 2.0), vec2(6.0, 6.0));
+		assert!(heightfield.version() > version);
+
+		// texels 2 to 5 take their average
+		assert_near(heightfield.height_at(2.5, 3.5), 3.5);
+		assert_near(heightfield.height_at(5.5, 5.5), 3.5);
+		assert_near(heightfield.slope_at(4.0, 4.0).0, 0.0);
+
+		// the rest stays as it was
+		assert_near(heightfield.height_at(1.5, 3.5), 1.0);
+		assert_near(heightfield.height_at(4.5, 6.5), 4.0);
+	}
+
+}
@@ -0,0 +1,49 @@
+mod heightfield;
+mod raycast;
+
+pub use self::heightfield::*;
+pub use self::raycast::*;
+
+use glium::Texture2d;
+
+use ::assets::Asset;
+use ::gfx::resources::Material;
+use ::math::*;
+
+pub struct Terrain {
+	pub map: Asset<Texture2d>,
+	pub materials: Vec<Asset<Material>>,
+	pub heightfield: Heightfield,
+}
+
+impl Terrain {
+
+	pub fn new(map: Asset<Texture2d>, heightfield: Heightfield) -> Terrain {
+		Terrain {
+			map: map,
+			materials: Vec::new(),
+			heightfield: heightfield,
+		}
+	}
+
+	pub fn scale(&self) -> Vector3 {
+		self.heightfield.scale
+	}
+
+	pub fn height_at(&self, x: Real, z: Real) -> Real {
+		self.heightfield.height_at(x, z)
+	}
+
+	pub fn normal_at(&self, x: Real, z: Real) -> Vector3 {
+		self.heightfield.normal_at(x, z)
+	}
+
+	pub fn slope_at(&self, x: Real, z: Real) -> Rad<Real> {
+		self.heightfield.slope_at(x, z)
+	}
+
+	pub fn raycast(&self, ray: &Ray) -> Option<TerrainHit> {
+		self.heightfield.raycast(ray)
+	}
+
+}
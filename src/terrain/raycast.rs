@@ -0,0 +1,221 @@
+use ::math::*;
+use ::terrain::Heightfield;
+
+pub struct TerrainHit {
+	pub position: Vector3,
+	pub normal: Vector3,
+	pub distance: Real,
+}
+
+/// Min/max height pyramid over a heightfield.
+///
+/// Node `(offset, lod)` covers the same area as the terrain `RenderNode`
+/// with the same offset and lod: `offset / lod .. (offset + 1) / lod`
+/// in texture coordinates. Leaf nodes are about one texel wide.
+pub struct HeightBoundsTree {
+	levels: Vec<Vec<(Real, Real)>>,
+}
+
+impl HeightBoundsTree {
+
+	pub fn build(heightfield: &Heightfield) -> Self {
+		let leaf_lod = heightfield.width().max(heightfield.height()).next_power_of_two();
+		let width = heightfield.width() as Real;
+		let height = heightfield.height() as Real;
+
+		let mut leaves = Vec::with_capacity(leaf_lod * leaf_lod);
+		for z in 0..leaf_lod {
+			// texels taking part in bilinear filtering anywhere inside the node
+			let y0 = ((z as Real) / (leaf_lod as Real) * height - 0.5).floor() as isize;
+			let y1 = (((z + 1) as Real) / (leaf_lod as Real) * height - 0.5).floor() as isize + 1;
+
+			for x in 0..leaf_lod {
+				let x0 = ((x as Real) / (leaf_lod as Real) * width - 0.5).floor() as isize;
+				let x1 = (((x + 1) as Real) / (leaf_lod as Real) * width - 0.5).floor() as isize + 1;
+
+				let mut bounds = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+				for ty in y0..(y1 + 1) {
+					for tx in x0..(x1 + 1) {
+						let sample = heightfield.texel(tx, ty);
+						bounds = (bounds.0.min(sample), bounds.1.max(sample));
+					}
+				}
+
+				leaves.push(bounds);
+			}
+		}
+
+		let mut levels = vec![leaves];
+		let mut lod = leaf_lod;
+		while lod > 1 {
+			let parent_lod = lod / 2;
+			let mut parents = Vec::with_capacity(parent_lod * parent_lod);
+			{
+				let children = levels.last().unwrap();
+				for z in 0..parent_lod {
+					for x in 0..parent_lod {
+						let mut bounds = (::std::f32::INFINITY, ::std::f32::NEG_INFINITY);
+						for &(cx, cz) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+							let child = children[(z * 2 + cz) * lod + x * 2 + cx];
+							bounds = (bounds.0.min(child.0), bounds.1.max(child.1));
+						}
+						parents.push(bounds);
+					}
+				}
+			}
+			levels.push(parents);
+			lod = parent_lod;
+		}
+
+		levels.reverse();
+
+		HeightBoundsTree {
+			levels: levels,
+		}
+	}
+
+	pub fn leaf_lod(&self) -> u16 {
+		1 << (self.levels.len() - 1)
+	}
+
+	/// Normalized min and max height inside the node
+	pub fn bounds(&self, offset: (u16, u16), lod: u16) -> (Real, Real) {
+		let level = lod.trailing_zeros() as usize;
+		self.levels[level][offset.1 as usize * lod as usize + offset.0 as usize]
+	}
+
+}
+
+impl Heightfield {
+
+	/// Finds the first point where the ray hits the terrain surface
+	pub fn raycast(&self, ray: &Ray) -> Option<TerrainHit> {
+		let tree = self.bounds_tree();
+		let leaf_lod = tree.leaf_lod();
+
+		let root = ((0, 0), 1);
+		if intersect_ray_aabb(ray, &self.node_bounds(root.0, root.1)).is_none() {
+			return None;
+		}
+
+		let mut stack = vec![root];
+
+		while let Some((offset, lod)) = stack.pop() {
+			if lod == leaf_lod {
+				if let Some(distance) = self.intersect_leaf(ray, offset, lod) {
+					let position = ray.point_at(distance);
+					return Some(TerrainHit {
+						position: position,
+						normal: self.normal_at(position.x, position.z),
+						distance: distance,
+					});
+				}
+				continue;
+			}
+
+			let child_lod = lod * 2;
+			let child_offset = (offset.0 * 2, offset.1 * 2);
+
+			let mut children = Vec::with_capacity(4);
+			for &(dx, dz) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+				let offset = (child_offset.0 + dx, child_offset.1 + dz);
+				if let Some(distance) = intersect_ray_aabb(ray, &self.node_bounds(offset, child_lod)) {
+					children.push((distance, offset));
+				}
+			}
+
+			// farthest first, so the nearest child is visited next
+			children.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+			for (_, offset) in children {
+				stack.push((offset, child_lod));
+			}
+		}
+
+		None
+	}
+
+	fn node_bounds(&self, offset: (u16, u16), lod: u16) -> AABB3 {
+		let (min_height, max_height) = self.bounds_tree().bounds(offset, lod);
+		let step_x = self.scale.x / (lod as Real);
+		let step_z = self.scale.z / (lod as Real);
+
+		AABB3 {
+			min: vec3(step_x * (offset.0 as Real), min_height * self.scale.y, step_z * (offset.1 as Real)),
+			max: vec3(step_x * ((offset.0 + 1) as Real), max_height * self.scale.y, step_z * ((offset.1 + 1) as Real)),
+		}
+	}
+
+	fn intersect_leaf(&self, ray: &Ray, offset: (u16, u16), lod: u16) -> Option<Real> {
+		let step_x = self.scale.x / (lod as Real);
+		let step_z = self.scale.z / (lod as Real);
+
+		let x0 = step_x * (offset.0 as Real);
+		let x1 = step_x * ((offset.0 + 1) as Real);
+		let z0 = step_z * (offset.1 as Real);
+		let z1 = step_z * ((offset.1 + 1) as Real);
+
+		let p00 = vec3(x0, self.height_at(x0, z0), z0);
+		let p10 = vec3(x1, self.height_at(x1, z0), z0);
+		let p01 = vec3(x0, self.height_at(x0, z1), z1);
+		let p11 = vec3(x1, self.height_at(x1, z1), z1);
+
+		match (intersect_ray_triangle(ray, p00, p10, p11), intersect_ray_triangle(ray, p00, p11, p01)) {
+			(Some(a), Some(b)) => Some(a.min(b)),
+			(Some(a), None) => Some(a),
+			(None, Some(b)) => Some(b),
+			(None, None) => None,
+		}
+	}
+
+}
+
+#[cfg(test)]
+mod tests {
+
+	use super::*;
+
+	#[test]
+	fn test_raycast_flat() {
+		let heightfield = Heightfield::from_fn(16, 16, vec3(100.0, 20.0, 100.0), |_, _| 0.5);
+
+		let ray = Ray::new(vec3(30.0, 50.0, 70.0), vec3(0.0, -1.0, 0.0));
+		let hit = heightfield.raycast(&ray).unwrap();
+		assert!((hit.position - vec3(30.0, 10.0, 70.0)).magnitude() < 0.001);
+		assert!((hit.normal - vec3(0.0, 1.0, 0.0)).magnitude() < 0.001);
+		assert!((hit.distance - 40.0).abs() < 0.001);
+
+		let slanted = Ray::new(vec3(-10.0, 30.0, 50.0), vec3(1.0, -1.0, 0.0));
+		let hit = heightfield.raycast(&slanted).unwrap();
+		assert!((hit.position - vec3(10.0, 10.0, 50.0)).magnitude() < 0.001);
+	}
+
+	#[test]
+	fn test_raycast_miss() {
+		let heightfield = Heightfield::from_fn(16, 16, vec3(100.0, 20.0, 100.0), |_, _| 0.5);
+
+		let outside = Ray::new(vec3(150.0, 50.0, 50.0), vec3(0.0, -1.0, 0.0));
+		assert!(heightfield.raycast(&outside).is_none());
+
+		let upwards = Ray::new(vec3(50.0, 15.0, 50.0), vec3(0.3, 1.0, 0.0));
+		assert!(heightfield.raycast(&upwards).is_none());
+
+		let above = Ray::new(vec3(-10.0, 15.0, 50.0), vec3(1.0, 0.0, 0.0));
+		assert!(heightfield.raycast(&above).is_none());
+	}
+
+	#[test]
+	fn test_raycast_hits_hill_first() {
+		// a wall 3 units high across the middle of flat ground
+		let heightfield = Heightfield::from_fn(32, 32, vec3(32.0, 10.0, 32.0), |x, _| if x >= 14 && x < 18 { 0.3 } else { 0.0 });
+
+		let ray = Ray::new(vec3(2.0, 2.0, 16.0), vec3(1.0, 0.0, 0.0));
+		let hit = heightfield.raycast(&ray).unwrap();
+		assert!(hit.position.x > 12.0 && hit.position.x < 16.0);
+		assert!(hit.normal.x < 0.0);
+
+		let over = Ray::new(vec3(2.0, 12.0, 16.0), vec3(1.0, -0.5, 0.0));
+		let hit = heightfield.raycast(&over).unwrap();
+		assert!((hit.position - vec3(26.0, 0.0, 16.0)).magnitude() < 0.001);
+	}
+
+}